@@ -0,0 +1,44 @@
+// Renders /admin/state, refreshing every 30 seconds.
+const REFRESH_MS = 30000;
+
+function cell(text, className) {
+  const td = document.createElement("td");
+  td.textContent = text ?? "";
+  if (className) {
+    td.className = className;
+  }
+  return td;
+}
+
+function render(snapshot) {
+  const controller = snapshot.controller;
+  document.getElementById("controller").textContent = controller.reachable
+    ? `Controller ${controller.url}: ${controller.devices} devices`
+    : `Controller ${controller.url} is unreachable: ${controller.error}`;
+  const rows = snapshot.machines.map((machine) => {
+    const tr = document.createElement("tr");
+    const last = machine.last_action;
+    tr.append(
+      cell(machine.system_id),
+      cell(machine.driver),
+      cell(machine.status, machine.status),
+      cell(machine.power_draw_watts == null ? "" : `${machine.power_draw_watts} W`),
+      cell(last && `${last.action} ${last.success ? "succeeded" : "failed"} at ${last.timestamp}`),
+      cell(machine.errors.join(", "), "error"),
+    );
+    return tr;
+  });
+  document.getElementById("machines").replaceChildren(...rows);
+}
+
+async function refresh() {
+  try {
+    const response = await fetch("../admin/state");
+    render(await response.json());
+  } catch (e) {
+    document.getElementById("controller").textContent = `Failed to load state: ${e}`;
+  }
+}
+
+refresh();
+setInterval(refresh, REFRESH_MS);
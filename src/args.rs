@@ -1,9 +1,34 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use clap_complete::Shell;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    #[arg(short, long)]
-    pub config_file: PathBuf,
+    /// Without a config file or dir the config is read from the `UNIFI_URL` and
+    /// `MACHINES` environment variables.
+    #[arg(short, long, global = true, conflicts_with = "config_dir")]
+    pub config_file: Option<PathBuf>,
+    /// Merge every `*.toml` file in a directory into one config.
+    #[arg(long, global = true)]
+    pub config_dir: Option<PathBuf>,
+    /// Use an in-memory controller with the configured devices instead of the
+    /// one at the configured URL, e.g. for demos and integration tests.
+    #[arg(long, global = true)]
+    pub mock: bool,
+    /// Serves the API when no command is given.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print a completion script for a shell
+    Completions { shell: Shell },
+    /// Print an example config with every option and its default
+    PrintExampleConfig,
+    /// Show the clients the controller sees on each mapped port
+    PortScan,
+    /// Upgrade the config file to the current layout, keeping a `.bak` copy
+    MigrateConfig,
 }
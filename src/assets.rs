@@ -0,0 +1,81 @@
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Redirect, Response},
+};
+use http::{
+    header::{CACHE_CONTROL, CONTENT_TYPE, ETAG},
+    HeaderMap, StatusCode,
+};
+use include_dir::{include_dir, Dir};
+
+use crate::etag::{etag, is_fresh};
+
+/// The dashboard and OpenAPI spec, built into the binary.
+static ASSETS: Dir = include_dir!("$CARGO_MANIFEST_DIR/assets");
+
+const INDEX: &str = "index.html";
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("yaml") => "application/yaml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Relative links in the dashboard resolve against `/ui/`.
+pub async fn ui_index() -> Redirect {
+    Redirect::permanent("/ui/")
+}
+
+/// Serves an embedded asset. Asset names are not versioned, so browsers must
+/// revalidate every time, which the `ETag` turns into a cheap `304`.
+pub async fn ui_asset(path: Option<Path<String>>, headers: HeaderMap) -> Response {
+    let path = match path.as_ref().map(|Path(path)| path.as_str()) {
+        None | Some("") => INDEX,
+        Some(path) => path,
+    };
+    let Some(file) = ASSETS.get_file(path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let etag = etag(file.contents());
+    let cache_headers = [(ETAG, etag.clone()), (CACHE_CONTROL, "no-cache".to_owned())];
+    if is_fresh(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (
+        cache_headers,
+        [(CONTENT_TYPE, content_type(path))],
+        file.contents(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::ui_asset;
+    use axum::extract::Path;
+    use http::{header::IF_NONE_MATCH, HeaderMap, StatusCode};
+
+    #[tokio::test]
+    async fn should_serve_assets_with_etag() {
+        let response = ui_asset(None, HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        let etag = response.headers()["etag"].clone();
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag);
+        let response = ui_asset(Some(Path("index.html".to_owned())), headers).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let response = ui_asset(Some(Path("missing.js".to_owned())), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,183 @@
+mod jwt;
+
+use axum::{
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization, HeaderMapExt,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, Method, Request};
+
+use crate::{
+    config::{AuthConfig, BasicAuthConfig, Role, TokenConfig},
+    router::{AppError, AppState},
+};
+
+use self::jwt::JwtValidator;
+
+/// Left open, so load balancers and orchestrators need no credentials.
+const UNAUTHENTICATED_PATHS: &[&str] = &["/readyz"];
+
+/// Routes taking a `POST` that change nothing, so the `read` role may call them.
+const READ_ONLY_POSTS: &[&str] = &["/graphql", "/admin/validate-config"];
+
+/// Routes taking a `GET` that answer with secrets, such as the credentials in
+/// driver options, so only the `power` role may call them.
+const SECRET_GETS: &[&str] = &["/admin/backup"];
+
+/// Checks requests against the methods configured under `[auth]`. Without
+/// `[auth]` every request is let through.
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    basic: Option<BasicAuthConfig>,
+    tokens: Vec<TokenConfig>,
+    jwt: Option<(JwtValidator, Vec<String>)>,
+    enabled: bool,
+}
+
+impl Authenticator {
+    pub fn new(config: Option<&AuthConfig>) -> anyhow::Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        let jwt = match &config.jwt {
+            Some(jwt) => Some((JwtValidator::new(jwt)?, jwt.power_scopes.clone())),
+            None => None,
+        };
+        Ok(Self {
+            basic: config.basic.clone(),
+            tokens: config.tokens.clone(),
+            jwt,
+            enabled: true,
+        })
+    }
+
+    /// The role of the client sending `headers`, `None` if it has no valid
+    /// credentials.
+    pub async fn role(&self, headers: &HeaderMap) -> Option<Role> {
+        if !self.enabled {
+            return Some(Role::Power);
+        }
+        if let (Some(expected), Some(Authorization(basic))) =
+            (&self.basic, headers.typed_get::<Authorization<Basic>>())
+        {
+            // Both are compared in full, so the time taken gives away neither.
+            let username = constant_time_eq(basic.username(), &expected.username);
+            let password = constant_time_eq(basic.password(), &expected.password);
+            return (username & password).then_some(expected.role);
+        }
+        let Authorization(bearer) = headers.typed_get::<Authorization<Bearer>>()?;
+        // Every token is compared, so the time taken does not tell which one
+        // was close.
+        let token = self.tokens.iter().fold(None, |role, token| {
+            role.or(constant_time_eq(bearer.token(), &token.token).then_some(token.role))
+        });
+        if token.is_some() {
+            return token;
+        }
+        let (jwt, power_scopes) = self.jwt.as_ref()?;
+        match jwt.validate(bearer.token()).await {
+            Ok(claims) => {
+                let subject = claims.sub.as_deref().unwrap_or("a token without `sub`");
+                tracing::debug!("authenticated {subject} with a bearer token");
+                let power = power_scopes
+                    .iter()
+                    .all(|scope| claims.scopes().any(|granted| granted == scope));
+                Some(if power { Role::Power } else { Role::Read })
+            }
+            Err(e) => {
+                tracing::debug!("refused a bearer token: {e:#}");
+                None
+            }
+        }
+    }
+}
+
+/// The role a request needs: queries need `read`, anything that changes state
+/// or reads secrets needs `power`.
+fn required_role<B>(request: &Request<B>) -> Role {
+    let method = request.method();
+    let path = request.uri().path();
+    if ((method == Method::GET || method == Method::HEAD) && !SECRET_GETS.contains(&path))
+        || (method == Method::POST && READ_ONLY_POSTS.contains(&path))
+    {
+        Role::Read
+    } else {
+        Role::Power
+    }
+}
+
+/// Refuses requests without valid credentials when `[auth]` is configured,
+/// and requests the client's role does not allow.
+pub async fn authenticate<B>(request: Request<B>, next: Next<B>) -> Response {
+    let state = request
+        .extensions()
+        .get::<AppState>()
+        .expect("the state is layered on every route");
+    if UNAUTHENTICATED_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    match state.authenticator.role(request.headers()).await {
+        Some(role) if role >= required_role(&request) => next.run(request).await,
+        Some(_) => AppError::Forbidden("This needs the `power` role".to_owned()).into_response(),
+        None => AppError::Unauthorized.into_response(),
+    }
+}
+
+/// Compares without stopping at the first difference, so the time taken does
+/// not tell how much of a guess was right. Only the length can leak.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{constant_time_eq, Authenticator};
+    use crate::config::{AuthConfig, BasicAuthConfig, Role, TokenConfig};
+    use http::{header::AUTHORIZATION, HeaderMap, HeaderValue};
+
+    fn authorization(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[tokio::test]
+    async fn should_give_each_credential_its_role() {
+        let auth = AuthConfig {
+            basic: Some(BasicAuthConfig {
+                username: "maas".to_owned(),
+                password: "secret".to_owned(),
+                role: Role::Power,
+            }),
+            tokens: vec![TokenConfig {
+                token: "dashboard".to_owned(),
+                role: Role::Read,
+            }],
+            jwt: None,
+        };
+        let authenticator = Authenticator::new(Some(&auth)).unwrap();
+        assert_eq!(authenticator.role(&HeaderMap::new()).await, None);
+        // maas:secret
+        let headers = authorization("Basic bWFhczpzZWNyZXQ=");
+        assert_eq!(authenticator.role(&headers).await, Some(Role::Power));
+        // maas:secreT
+        let headers = authorization("Basic bWFhczpzZWNyZVQ=");
+        assert_eq!(authenticator.role(&headers).await, None);
+        let headers = authorization("Bearer dashboard");
+        assert_eq!(authenticator.role(&headers).await, Some(Role::Read));
+        let headers = authorization("Bearer dashboarD");
+        assert_eq!(authenticator.role(&headers).await, None);
+        assert_eq!(
+            Authenticator::default().role(&headers).await,
+            Some(Role::Power)
+        );
+        assert!(!constant_time_eq("secret", "secret2"));
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::config::JwtConfig;
+
+/// Keys are not fetched again for an unknown key ID sooner than this, so a
+/// flood of forged tokens cannot hammer the identity provider.
+const MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// Validates bearer JWTs against the keys an identity provider publishes. The
+/// keys are fetched on first use, every `keys_refresh_secs`, and when a token
+/// names a key that is not known yet, as happens after a key rotation.
+#[derive(Clone)]
+pub struct JwtValidator {
+    config: JwtConfig,
+    http: reqwest::Client,
+    keys: Arc<RwLock<Option<FetchedKeys>>>,
+}
+
+struct FetchedKeys {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// The claims of a valid token that are checked beyond the standard ones.
+#[derive(Deserialize, Debug)]
+pub struct Claims {
+    pub sub: Option<String>,
+    /// Space separated, as in OAuth 2.0.
+    #[serde(default)]
+    scope: Option<String>,
+    /// A list, or space separated, depending on the identity provider.
+    #[serde(default)]
+    scp: Option<Scopes>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Scopes {
+    List(Vec<String>),
+    Spaced(String),
+}
+
+impl Claims {
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        let scp: Box<dyn Iterator<Item = &str>> = match &self.scp {
+            Some(Scopes::List(scopes)) => Box::new(scopes.iter().map(String::as_str)),
+            Some(Scopes::Spaced(scopes)) => Box::new(scopes.split_whitespace()),
+            None => Box::new(std::iter::empty()),
+        };
+        self.scope
+            .iter()
+            .flat_map(|scope| scope.split_whitespace())
+            .chain(scp)
+    }
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+impl JwtValidator {
+    pub fn new(config: &JwtConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            keys: Arc::default(),
+        })
+    }
+
+    /// The claims of `token` if it is signed by the provider, unexpired, and
+    /// has the configured issuer, audience and scopes.
+    pub async fn validate(&self, token: &str) -> anyhow::Result<Claims> {
+        let header = decode_header(token)?;
+        let jwk = self.key(header.kid.as_deref()).await?;
+        if let Some(algorithm) = jwk.common.key_algorithm {
+            if Algorithm::from_str(&algorithm.to_string())? != header.alg {
+                bail!(
+                    "the token is signed with {:?}, its key is for {algorithm}",
+                    header.alg
+                );
+            }
+        }
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        let claims = decode::<Claims>(token, &DecodingKey::from_jwk(&jwk)?, &validation)?.claims;
+        if let Some(missing) = self
+            .config
+            .scopes
+            .iter()
+            .find(|scope| !claims.scopes().any(|granted| granted == scope.as_str()))
+        {
+            bail!("the token lacks the `{missing}` scope");
+        }
+        Ok(claims)
+    }
+
+    async fn key(&self, kid: Option<&str>) -> anyhow::Result<Jwk> {
+        let refresh = Duration::from_secs(self.config.keys_refresh_secs);
+        {
+            let keys = self.keys.read().await;
+            if let Some(fetched) = keys.as_ref() {
+                let key = find(&fetched.keys, kid);
+                let age = fetched.fetched_at.elapsed();
+                if let Some(key) = key.filter(|_| age < refresh) {
+                    return Ok(key.clone());
+                }
+                if age < MIN_REFRESH {
+                    return key
+                        .cloned()
+                        .ok_or_else(|| anyhow!("no signing key matches the token"));
+                }
+            }
+        }
+        let mut keys = self.keys.write().await;
+        // Another request may have fetched the keys while this one waited.
+        let fresh = keys
+            .as_ref()
+            .filter(|fetched| fetched.fetched_at.elapsed() < MIN_REFRESH);
+        if fresh.is_none() {
+            *keys = Some(FetchedKeys {
+                keys: self.fetch_keys().await?,
+                fetched_at: Instant::now(),
+            });
+        }
+        let fetched = keys.as_ref().expect("fetched above");
+        find(&fetched.keys, kid)
+            .cloned()
+            .ok_or_else(|| anyhow!("no signing key matches the token"))
+    }
+
+    async fn fetch_keys(&self) -> anyhow::Result<JwkSet> {
+        let jwks_url = match &self.config.jwks_url {
+            Some(jwks_url) => jwks_url.clone(),
+            None => {
+                let discovery = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                self.get::<Discovery>(&discovery).await?.jwks_uri
+            }
+        };
+        self.get(&jwks_url).await
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, url: &str) -> anyhow::Result<T> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("failed to fetch {url}"))?
+            .json()
+            .await
+            .with_context(|| format!("failed to read {url}"))
+    }
+}
+
+/// The key named by the token, or the only key if the token names none.
+fn find<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::JwtValidator;
+    use crate::config::JwtConfig;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const SECRET: &[u8] = b"a secret of at least thirty-two bytes";
+
+    fn token(claims: serde_json::Value) -> String {
+        let header = Header {
+            kid: Some("key-1".to_owned()),
+            ..Header::new(Algorithm::HS256)
+        };
+        encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_validate_tokens_against_discovered_keys() {
+        let provider = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"jwks_uri": format!("{}/keys", provider.uri())})),
+            )
+            .mount(&provider)
+            .await;
+        let secret =
+            base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, SECRET);
+        Mock::given(method("GET"))
+            .and(path("/keys"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "keys": [{"kty": "oct", "kid": "key-1", "alg": "HS256", "k": secret}],
+            })))
+            .expect(1)
+            .mount(&provider)
+            .await;
+        let validator = JwtValidator::new(&JwtConfig {
+            issuer: provider.uri(),
+            jwks_url: None,
+            audience: "maas-power-unifi".to_owned(),
+            scopes: vec!["power".to_owned()],
+            power_scopes: Vec::new(),
+            keys_refresh_secs: 3600,
+        })
+        .unwrap();
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 300;
+        let claims = |aud: &str, scope: &str| json!({"iss": provider.uri(), "aud": aud, "exp": exp, "sub": "ops", "scope": scope});
+        let valid = validator
+            .validate(&token(claims("maas-power-unifi", "read power")))
+            .await
+            .unwrap();
+        assert_eq!(valid.sub.as_deref(), Some("ops"));
+        assert!(validator
+            .validate(&token(claims("other", "power")))
+            .await
+            .is_err());
+        assert!(validator
+            .validate(&token(claims("maas-power-unifi", "read")))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,323 @@
+pub mod edgeswitch;
+pub mod mpower;
+pub mod unifi_poe;
+pub mod wol;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use mac_address::MacAddress;
+use reqwest::Client;
+
+use crate::{
+    config::{Config, Driver, EdgeSwitchOptions, Machine, MpowerOptions},
+    unifi::{client::UnifiError, handler::UnifiHandler, models::PowerStatus},
+};
+
+use self::{
+    edgeswitch::{EdgeSwitchBackend, EdgeSwitchClient},
+    mpower::{MpowerBackend, MpowerClient},
+    unifi_poe::UnifiPoeBackend,
+    wol::WolBackend,
+};
+
+#[derive(Debug)]
+pub enum BackendError {
+    Unifi(UnifiError),
+    Failed(String),
+    /// The backend cannot perform the requested action at all.
+    Unsupported(String),
+}
+
+impl From<UnifiError> for BackendError {
+    fn from(inner: UnifiError) -> Self {
+        BackendError::Unifi(inner)
+    }
+}
+
+/// Something that can switch the power of a machine, e.g. a PoE port on a
+/// UniFi switch. Routing, hooks, notifications and the watchdog are shared by
+/// all backends.
+#[async_trait]
+pub trait PowerBackend: Send + Sync {
+    async fn status(&self, machine: &Machine) -> Result<PowerStatus, BackendError>;
+
+    async fn power_on(&self, machine: &Machine) -> Result<(), BackendError>;
+
+    async fn power_off(&self, machine: &Machine) -> Result<(), BackendError>;
+
+    async fn power_cycle(&self, machine: &Machine) -> Result<(), BackendError> {
+        self.power_off(machine).await?;
+        self.power_on(machine).await
+    }
+
+    /// The power currently drawn by the machine in watts, `None` if the backend
+    /// cannot measure it.
+    async fn power_draw(&self, _machine: &Machine) -> Result<Option<f64>, BackendError> {
+        Ok(None)
+    }
+
+    /// Whether the machine's network port has come up, `None` if the backend
+    /// cannot tell.
+    async fn link_up(&self, _machine: &Machine) -> Result<Option<bool>, BackendError> {
+        Ok(None)
+    }
+
+    /// Backend specific details passed to hooks as environment variables.
+    fn hook_env(&self, _machine: &Machine) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// The same backend acting through another controller session, `None` if
+    /// it does not go through the UniFi controller.
+    fn with_controller(&self, _handler: UnifiHandler) -> Option<Arc<dyn PowerBackend>> {
+        None
+    }
+
+    /// The UniFi device whose ports the backend switches, `None` if it does not
+    /// switch UniFi ports.
+    fn unifi_device(&self) -> Option<MacAddress> {
+        None
+    }
+}
+
+/// A machine together with the backend that controls its power.
+#[derive(Clone)]
+pub struct Target {
+    pub backend: Arc<dyn PowerBackend>,
+    pub machine: Machine,
+}
+
+/// Resolves MaaS system IDs to the backend instance that manages them. Clones
+/// share the same targets, so a mapping can be replaced while serving.
+#[derive(Clone, Default)]
+pub struct BackendRegistry {
+    targets: Arc<RwLock<HashMap<String, Target>>>,
+}
+
+impl BackendRegistry {
+    /// Registers a UniFi PoE backend for every configured device, and a backend
+    /// of the configured driver for every other machine.
+    pub fn new(config: &Config, handler: UnifiHandler) -> anyhow::Result<Self> {
+        let mut registry = Self::default();
+        for device in &config.devices {
+            let backend = Self::unifi_poe(config, handler.clone(), device.mac);
+            for machine in &device.machines {
+                registry.register(machine.clone(), backend.clone());
+            }
+        }
+        // Switches and strips are usually shared by several machines, so share
+        // the session.
+        let mut edgeswitches: HashMap<String, Arc<EdgeSwitchClient>> = HashMap::new();
+        let mut mpowers: HashMap<String, Arc<MpowerClient>> = HashMap::new();
+        let http_client = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()?;
+        for machine in &config.machines {
+            let backend: Arc<dyn PowerBackend> = match machine.driver {
+                Driver::UnifiPoe => anyhow::bail!(
+                    "machine `{}` uses the `unifi-poe` driver so must be listed under a device",
+                    machine.maas_id
+                ),
+                Driver::Wol => Arc::new(WolBackend::new(machine.options()?)),
+                Driver::Edgeswitch => {
+                    let options = machine.options::<EdgeSwitchOptions>()?;
+                    let client = match edgeswitches.get(&options.url) {
+                        Some(client) => client.clone(),
+                        None => {
+                            let client =
+                                Arc::new(EdgeSwitchClient::new(&options, http_client.clone())?);
+                            edgeswitches.insert(options.url.clone(), client.clone());
+                            client
+                        }
+                    };
+                    Arc::new(EdgeSwitchBackend::new(client, &options))
+                }
+                Driver::Mpower => {
+                    let options = machine.options::<MpowerOptions>()?;
+                    let client = match mpowers.get(&options.url) {
+                        Some(client) => client.clone(),
+                        None => {
+                            let client =
+                                Arc::new(MpowerClient::new(&options, http_client.clone())?);
+                            mpowers.insert(options.url.clone(), client.clone());
+                            client
+                        }
+                    };
+                    Arc::new(MpowerBackend::new(client, &options))
+                }
+            };
+            registry.register(machine.clone(), backend);
+        }
+        Ok(registry)
+    }
+
+    /// The PoE backend of a UniFi device, shared by every machine on it so
+    /// power ons are staggered across its ports.
+    pub fn unifi_poe(
+        config: &Config,
+        handler: UnifiHandler,
+        mac: MacAddress,
+    ) -> Arc<dyn PowerBackend> {
+        Arc::new(
+            UnifiPoeBackend::new(handler, mac)
+                .with_stagger(Duration::from_millis(config.power_on_stagger_ms))
+                .with_poe_budget(config.poe_budget),
+        )
+    }
+
+    pub fn register(&mut self, machine: Machine, backend: Arc<dyn PowerBackend>) {
+        self.targets
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(machine.maas_id.clone(), Target { backend, machine });
+    }
+
+    /// Registers a machine on a port of a UniFi device, through the backend
+    /// the device's other machines use or `backend` if it has none. Fails with
+    /// the machine already on the port if another one holds it.
+    pub fn register_port(
+        &self,
+        machine: Machine,
+        device: MacAddress,
+        backend: impl FnOnce() -> Arc<dyn PowerBackend>,
+    ) -> Result<(), String> {
+        let mut targets = self.targets.write().unwrap_or_else(|e| e.into_inner());
+        let on_device = || {
+            targets
+                .values()
+                .filter(|target| target.backend.unifi_device() == Some(device))
+        };
+        if let Some(holder) = on_device().find(|target| {
+            target.machine.port_id == machine.port_id && target.machine.maas_id != machine.maas_id
+        }) {
+            return Err(holder.machine.maas_id.clone());
+        }
+        let backend = on_device()
+            .map(|target| target.backend.clone())
+            .next()
+            .unwrap_or_else(backend);
+        targets.insert(machine.maas_id.clone(), Target { backend, machine });
+        Ok(())
+    }
+
+    pub fn resolve(&self, maas_id: &str) -> Option<Target> {
+        self.targets
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(maas_id)
+            .cloned()
+    }
+
+    pub fn targets(&self) -> Vec<Target> {
+        self.targets
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// A copy of the registry with UniFi machines powered through `handler`.
+    /// The copy does not share its targets with this registry.
+    pub fn with_controller(&self, handler: &UnifiHandler) -> BackendRegistry {
+        let targets = self
+            .targets
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(maas_id, target)| {
+                let backend = target
+                    .backend
+                    .with_controller(handler.clone())
+                    .unwrap_or_else(|| target.backend.clone());
+                let target = Target {
+                    backend,
+                    machine: target.machine.clone(),
+                };
+                (maas_id.clone(), target)
+            })
+            .collect();
+        BackendRegistry {
+            targets: Arc::new(RwLock::new(targets)),
+        }
+    }
+
+    /// Swaps in the targets of `other`, for every clone of this registry.
+    pub fn replace(&self, other: BackendRegistry) {
+        let targets = other
+            .targets
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        *self.targets.write().unwrap_or_else(|e| e.into_inner()) = targets;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BackendRegistry;
+    use crate::{
+        config::{Config, Device, Machine},
+        unifi::{
+            client::UnifiClient,
+            handler::UnifiHandler,
+            models::{self, Station, UnifiResponse},
+        },
+    };
+    use async_trait::async_trait;
+    use mac_address::MacAddress;
+
+    const MAAS_SYSTEM_ID: &str = "system-id";
+    const MACHINE_PORT: usize = 2;
+
+    #[derive(Clone)]
+    struct FakeUnifiClient {}
+
+    #[async_trait]
+    impl UnifiClient for FakeUnifiClient {
+        async fn login(&self, _: &str, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<models::Device>>> {
+            Ok(UnifiResponse::default())
+        }
+
+        async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+            Ok(UnifiResponse::default())
+        }
+
+        async fn power_on(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            Ok(UnifiResponse::default())
+        }
+
+        async fn power_off(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            Ok(UnifiResponse::default())
+        }
+    }
+
+    #[test]
+    fn should_resolve_configured_machine() {
+        let config = Config {
+            devices: vec![Device {
+                mac: MacAddress::from([0; 6]),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let handler = UnifiHandler::new(Box::new(FakeUnifiClient {}));
+        let registry = BackendRegistry::new(&config, handler).unwrap();
+        let target = registry.resolve(MAAS_SYSTEM_ID).unwrap();
+        assert_eq!(target.machine.port_id, MACHINE_PORT);
+        assert!(registry.resolve("unknown").is_none());
+    }
+}
@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use super::{BackendError, PowerBackend};
+use crate::{
+    config::{EdgeSwitchOptions, Machine},
+    unifi::models::PowerStatus,
+};
+
+const AUTH_TOKEN_HEADER: &str = "x-auth-token";
+const POE_OFF: &str = "off";
+
+/// Talks to the REST API of a standalone (not controller adopted) EdgeSwitch.
+pub struct EdgeSwitchClient {
+    base_url: Url,
+    username: String,
+    password: String,
+    client: Client,
+    token: Mutex<Option<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Interface {
+    identification: Identification,
+    #[serde(default)]
+    port: Option<InterfacePort>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Identification {
+    id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct InterfacePort {
+    poe: Option<String>,
+}
+
+impl EdgeSwitchClient {
+    pub fn new(options: &EdgeSwitchOptions, client: Client) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_url: Url::parse(&options.url)?,
+            username: options.username.clone(),
+            password: options.password.clone(),
+            client,
+            token: Mutex::new(None),
+        })
+    }
+
+    async fn login(&self) -> anyhow::Result<String> {
+        let url = self.base_url.join("/api/v1.0/user/login")?;
+        let response = self
+            .client
+            .post(url)
+            .json(&json!({"username": self.username, "password": self.password}))
+            .send()
+            .await?
+            .error_for_status()?;
+        let token = response
+            .headers()
+            .get(AUTH_TOKEN_HEADER)
+            .ok_or_else(|| anyhow::anyhow!("login response had no {AUTH_TOKEN_HEADER} header"))?
+            .to_str()?
+            .to_owned();
+        Ok(token)
+    }
+
+    /// Sends a request with the session token, logging in first if there is no
+    /// session yet and once more if the session has expired.
+    async fn send<F>(&self, build: F) -> anyhow::Result<reqwest::Response>
+    where
+        F: Fn(&str) -> anyhow::Result<RequestBuilder>,
+    {
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = Some(self.login().await?);
+        }
+        let response = build(token.as_deref().unwrap_or_default())?.send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response.error_for_status()?);
+        }
+        let fresh = self.login().await?;
+        let response = build(&fresh)?.send().await?;
+        *token = Some(fresh);
+        Ok(response.error_for_status()?)
+    }
+
+    async fn interfaces(&self) -> anyhow::Result<Vec<Interface>> {
+        let url = self.base_url.join("/api/v1.0/interfaces")?;
+        let response = self
+            .send(|token| {
+                Ok(self
+                    .client
+                    .request(Method::GET, url.clone())
+                    .header(AUTH_TOKEN_HEADER, token))
+            })
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn poe(&self, interface: &str) -> anyhow::Result<String> {
+        self.interfaces()
+            .await?
+            .into_iter()
+            .find(|i| i.identification.id == interface)
+            .and_then(|i| i.port)
+            .and_then(|port| port.poe)
+            .ok_or_else(|| anyhow::anyhow!("interface {interface} has no PoE settings"))
+    }
+
+    pub async fn set_poe(&self, interface: &str, poe: &str) -> anyhow::Result<()> {
+        let url = self.base_url.join("/api/v1.0/interfaces")?;
+        let body = json!([{"identification": {"id": interface}, "port": {"poe": poe}}]);
+        tracing::debug!("setting the PoE mode of interface {interface}");
+        self.send(|token| {
+            Ok(self
+                .client
+                .request(Method::PUT, url.clone())
+                .header(AUTH_TOKEN_HEADER, token)
+                .json(&body))
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Powers a machine through a PoE interface of an EdgeSwitch.
+pub struct EdgeSwitchBackend {
+    client: Arc<EdgeSwitchClient>,
+    interface: String,
+    poe: String,
+}
+
+impl EdgeSwitchBackend {
+    pub fn new(client: Arc<EdgeSwitchClient>, options: &EdgeSwitchOptions) -> Self {
+        Self {
+            client,
+            interface: options.interface.clone(),
+            poe: options.poe.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl PowerBackend for EdgeSwitchBackend {
+    async fn status(&self, _machine: &Machine) -> Result<PowerStatus, BackendError> {
+        let poe = self
+            .client
+            .poe(&self.interface)
+            .await
+            .map_err(|e| BackendError::Failed(e.to_string()))?;
+        let status = if poe == POE_OFF { "stopped" } else { "running" };
+        Ok(PowerStatus {
+            status: status.to_owned(),
+        })
+    }
+
+    async fn power_on(&self, _machine: &Machine) -> Result<(), BackendError> {
+        self.client
+            .set_poe(&self.interface, &self.poe)
+            .await
+            .map_err(|e| BackendError::Failed(e.to_string()))
+    }
+
+    async fn power_off(&self, _machine: &Machine) -> Result<(), BackendError> {
+        self.client
+            .set_poe(&self.interface, POE_OFF)
+            .await
+            .map_err(|e| BackendError::Failed(e.to_string()))
+    }
+
+    fn hook_env(&self, _machine: &Machine) -> Vec<(&'static str, String)> {
+        vec![("EDGESWITCH_INTERFACE", self.interface.clone())]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EdgeSwitchBackend, EdgeSwitchClient};
+    use crate::{
+        backend::PowerBackend,
+        config::{EdgeSwitchOptions, Machine},
+    };
+    use serde_json::json;
+    use std::sync::Arc;
+    use wiremock::{
+        matchers::{body_json, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const TOKEN: &str = "token";
+    const INTERFACE: &str = "0/3";
+
+    async fn backend(mock_server: &MockServer) -> EdgeSwitchBackend {
+        Mock::given(method("POST"))
+            .and(path("/api/v1.0/user/login"))
+            .respond_with(ResponseTemplate::new(200).insert_header("x-auth-token", TOKEN))
+            .mount(mock_server)
+            .await;
+        let options = EdgeSwitchOptions {
+            url: mock_server.uri(),
+            username: "ubnt".to_owned(),
+            password: "ubnt".to_owned(),
+            interface: INTERFACE.to_owned(),
+            poe: "active".to_owned(),
+        };
+        let client = EdgeSwitchClient::new(&options, reqwest::Client::new()).unwrap();
+        EdgeSwitchBackend::new(Arc::new(client), &options)
+    }
+
+    #[tokio::test]
+    async fn should_get_status_of_interface() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1.0/interfaces"))
+            .and(header("x-auth-token", TOKEN))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {"identification": {"id": "0/1"}, "port": {"poe": "active"}},
+                {"identification": {"id": INTERFACE}, "port": {"poe": "off"}},
+            ])))
+            .mount(&mock_server)
+            .await;
+        let status = backend(&mock_server)
+            .await
+            .status(&Machine::default())
+            .await
+            .unwrap();
+        assert_eq!(status.status, "stopped");
+    }
+
+    #[tokio::test]
+    async fn should_power_on_interface() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1.0/interfaces"))
+            .and(header("x-auth-token", TOKEN))
+            .and(body_json(
+                json!([{"identification": {"id": INTERFACE}, "port": {"poe": "active"}}]),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        backend(&mock_server)
+            .await
+            .power_on(&Machine::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_error_if_power_off_fails() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1.0/interfaces"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+        let result = backend(&mock_server)
+            .await
+            .power_off(&Machine::default())
+            .await;
+        assert!(result.is_err());
+    }
+}
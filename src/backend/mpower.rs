@@ -0,0 +1,299 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use hyper::header::COOKIE;
+use reqwest::{Client, Method, Url};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use super::{BackendError, PowerBackend};
+use crate::{
+    config::{Machine, MpowerOptions},
+    unifi::models::PowerStatus,
+};
+
+/// Talks to the HTTP interface of an mFi mPower power strip.
+pub struct MpowerClient {
+    base_url: Url,
+    username: String,
+    password: String,
+    client: Client,
+    session_id: String,
+    logged_in: Mutex<bool>,
+}
+
+/// Changing an output only returns the `status`, reading one also returns the
+/// `sensors`.
+#[derive(Deserialize, Debug)]
+struct SensorsResponse {
+    #[serde(default)]
+    sensors: Vec<Sensor>,
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sensor {
+    port: usize,
+    output: u8,
+    #[serde(default)]
+    power: Option<f64>,
+}
+
+/// The strip expects the client to pick its own 32 digit session ID.
+fn session_id() -> String {
+    let random = |seed| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(seed);
+        hasher.finish()
+    };
+    const DIGITS: u64 = 10_u64.pow(16);
+    format!("{:016}{:016}", random(0) % DIGITS, random(1) % DIGITS)
+}
+
+impl MpowerClient {
+    pub fn new(options: &MpowerOptions, client: Client) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_url: Url::parse(&options.url)?,
+            username: options.username.clone(),
+            password: options.password.clone(),
+            client,
+            session_id: session_id(),
+            logged_in: Mutex::new(false),
+        })
+    }
+
+    fn cookie(&self) -> String {
+        format!("AIROS_SESSIONID={}", self.session_id)
+    }
+
+    async fn login(&self) -> anyhow::Result<()> {
+        let url = self.base_url.join("/login.cgi")?;
+        self.client
+            .post(url)
+            .header(COOKIE, self.cookie())
+            .form(&[
+                ("username", self.username.as_str()),
+                ("password", self.password.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Sends a request with the session cookie. An expired session redirects
+    /// to the login page rather than returning JSON, in which case log in again
+    /// and retry once.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        form: Option<&[(&str, &str)]>,
+    ) -> anyhow::Result<SensorsResponse> {
+        let url = self.base_url.join(path)?;
+        let mut logged_in = self.logged_in.lock().await;
+        for attempt in 0..2 {
+            if !*logged_in || attempt > 0 {
+                self.login().await?;
+                *logged_in = true;
+            }
+            let mut request = self
+                .client
+                .request(method.clone(), url.clone())
+                .header(COOKIE, self.cookie());
+            if let Some(form) = form {
+                request = request.form(form);
+            }
+            let body = request.send().await?.error_for_status()?.text().await?;
+            match serde_json::from_str::<SensorsResponse>(&body) {
+                Ok(response) if response.status == "success" => return Ok(response),
+                Ok(response) => return Err(anyhow!("mPower returned {}", response.status)),
+                Err(_) => continue,
+            }
+        }
+        Err(anyhow!(
+            "mPower at {} did not accept the login",
+            self.base_url
+        ))
+    }
+
+    async fn sensor(&self, outlet: usize) -> anyhow::Result<Sensor> {
+        self.send(Method::GET, &format!("/sensors/{outlet}"), None)
+            .await?
+            .sensors
+            .into_iter()
+            .find(|sensor| sensor.port == outlet)
+            .ok_or_else(|| anyhow!("mPower has no outlet {outlet}"))
+    }
+
+    async fn set_output(&self, outlet: usize, on: bool) -> anyhow::Result<()> {
+        let output = if on { "1" } else { "0" };
+        self.send(
+            Method::PUT,
+            &format!("/sensors/{outlet}"),
+            Some(&[("output", output)]),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Powers a machine through a relay controlled outlet on an mPower strip.
+pub struct MpowerBackend {
+    client: Arc<MpowerClient>,
+    outlet: usize,
+}
+
+impl MpowerBackend {
+    pub fn new(client: Arc<MpowerClient>, options: &MpowerOptions) -> Self {
+        Self {
+            client,
+            outlet: options.outlet,
+        }
+    }
+}
+
+#[async_trait]
+impl PowerBackend for MpowerBackend {
+    async fn status(&self, _machine: &Machine) -> Result<PowerStatus, BackendError> {
+        let sensor = self
+            .client
+            .sensor(self.outlet)
+            .await
+            .map_err(|e| BackendError::Failed(e.to_string()))?;
+        let status = if sensor.output == 0 {
+            "stopped"
+        } else {
+            "running"
+        };
+        Ok(PowerStatus {
+            status: status.to_owned(),
+        })
+    }
+
+    async fn power_on(&self, _machine: &Machine) -> Result<(), BackendError> {
+        self.client
+            .set_output(self.outlet, true)
+            .await
+            .map_err(|e| BackendError::Failed(e.to_string()))
+    }
+
+    async fn power_off(&self, _machine: &Machine) -> Result<(), BackendError> {
+        self.client
+            .set_output(self.outlet, false)
+            .await
+            .map_err(|e| BackendError::Failed(e.to_string()))
+    }
+
+    async fn power_draw(&self, _machine: &Machine) -> Result<Option<f64>, BackendError> {
+        self.client
+            .sensor(self.outlet)
+            .await
+            .map(|sensor| Some(sensor.power.unwrap_or_default()))
+            .map_err(|e| BackendError::Failed(e.to_string()))
+    }
+
+    fn hook_env(&self, _machine: &Machine) -> Vec<(&'static str, String)> {
+        vec![("MPOWER_OUTLET", self.outlet.to_string())]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{session_id, MpowerBackend, MpowerClient};
+    use crate::{
+        backend::PowerBackend,
+        config::{Machine, MpowerOptions},
+    };
+    use serde_json::json;
+    use std::sync::Arc;
+    use wiremock::{
+        matchers::{body_string, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const OUTLET: usize = 2;
+
+    async fn backend(mock_server: &MockServer) -> MpowerBackend {
+        Mock::given(method("POST"))
+            .and(path("/login.cgi"))
+            .and(body_string("username=ubnt&password=ubnt"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(mock_server)
+            .await;
+        let options = MpowerOptions {
+            url: mock_server.uri(),
+            username: "ubnt".to_owned(),
+            password: "ubnt".to_owned(),
+            outlet: OUTLET,
+        };
+        let client = MpowerClient::new(&options, reqwest::Client::new()).unwrap();
+        MpowerBackend::new(Arc::new(client), &options)
+    }
+
+    fn sensors(output: u8) -> serde_json::Value {
+        json!({
+            "sensors": [{"port": OUTLET, "output": output, "power": 12.5}],
+            "status": "success"
+        })
+    }
+
+    #[test]
+    fn should_generate_numeric_session_id() {
+        let id = session_id();
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[tokio::test]
+    async fn should_get_status_and_power_draw_of_outlet() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!("/sensors/{OUTLET}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(sensors(1)))
+            .mount(&mock_server)
+            .await;
+        let backend = backend(&mock_server).await;
+        let status = backend.status(&Machine::default()).await.unwrap();
+        assert_eq!(status.status, "running");
+        let draw = backend.power_draw(&Machine::default()).await.unwrap();
+        assert_eq!(draw, Some(12.5));
+    }
+
+    #[tokio::test]
+    async fn should_switch_outlet_off() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path(format!("/sensors/{OUTLET}")))
+            .and(body_string("output=0"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(sensors(0)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        backend(&mock_server)
+            .await
+            .power_off(&Machine::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_error_if_login_is_not_accepted() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!("/sensors/{OUTLET}")))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html>login</html>"))
+            .mount(&mock_server)
+            .await;
+        let result = backend(&mock_server)
+            .await
+            .status(&Machine::default())
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use mac_address::MacAddress;
+
+use super::{BackendError, PowerBackend};
+use crate::{
+    config::{Machine, PoeBudgetAction, PoeBudgetConfig},
+    unifi::{
+        client::{PoeBudgetUse, UnifiError},
+        handler::UnifiHandler,
+        models::{DeviceId, PowerStatus},
+    },
+};
+
+/// Powers machines through the PoE ports of a single UniFi switch.
+pub struct UnifiPoeBackend {
+    handler: UnifiHandler,
+    device_mac: MacAddress,
+    /// The least time between powering on two ports, so their inrush does
+    /// not trip the switch's PoE budget.
+    stagger: Duration,
+    /// When the next port may be powered on, shared with the copies made for
+    /// other controller sessions.
+    next_power_on: Arc<Mutex<Instant>>,
+    poe_budget: Option<PoeBudgetConfig>,
+}
+
+impl UnifiPoeBackend {
+    pub fn new(handler: UnifiHandler, device_mac: MacAddress) -> Self {
+        Self {
+            handler,
+            device_mac,
+            stagger: Duration::ZERO,
+            next_power_on: Arc::new(Mutex::new(Instant::now())),
+            poe_budget: None,
+        }
+    }
+
+    pub fn with_stagger(mut self, stagger: Duration) -> Self {
+        self.stagger = stagger;
+        self
+    }
+
+    pub fn with_poe_budget(mut self, poe_budget: Option<PoeBudgetConfig>) -> Self {
+        self.poe_budget = poe_budget;
+        self
+    }
+
+    /// Refuses, or warns about, a power on while the switch draws more of its
+    /// PoE budget than allowed. Switches that report no budget are let be.
+    async fn check_poe_budget(&self, device_id: &DeviceId) -> Result<(), UnifiError> {
+        let Some(config) = &self.poe_budget else {
+            return Ok(());
+        };
+        let device = self.handler.device(device_id).await?;
+        let Some(budget) = device.total_max_power.filter(|budget| *budget > 0.0) else {
+            return Ok(());
+        };
+        let usage = PoeBudgetUse {
+            draw_watts: device.poe_watts(),
+            budget_watts: budget,
+            max_utilization: config.max_utilization,
+        };
+        if usage.draw_watts / budget < config.max_utilization {
+            return Ok(());
+        }
+        match config.on_exceed {
+            PoeBudgetAction::Reject => Err(UnifiError::PoeBudgetExceeded(usage)),
+            PoeBudgetAction::Warn => {
+                tracing::warn!(
+                    "powering on a port of {} while it draws {:.1}W of its {:.1}W PoE budget",
+                    self.device_mac,
+                    usage.draw_watts,
+                    budget
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits for this power on's turn, each taking the next slot.
+    async fn wait_to_power_on(&self) {
+        if self.stagger.is_zero() {
+            return;
+        }
+        let slot = {
+            let mut next = self.next_power_on.lock().unwrap_or_else(|e| e.into_inner());
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.stagger;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+#[async_trait]
+impl PowerBackend for UnifiPoeBackend {
+    async fn status(&self, machine: &Machine) -> Result<PowerStatus, BackendError> {
+        let device_id = self.handler.device_id(&self.device_mac).await?;
+        let device = self.handler.device(&device_id).await?;
+        Ok(device
+            .power_status(machine.port_id)
+            .ok_or(UnifiError::MachinePortIdIncorrect(machine.port_id))?)
+    }
+
+    async fn power_on(&self, machine: &Machine) -> Result<(), BackendError> {
+        let device_id = self.handler.device_id(&self.device_mac).await?;
+        // Checked after the wait, so it sees the draw of the power ons
+        // staggered before this one.
+        self.wait_to_power_on().await;
+        self.check_poe_budget(&device_id).await?;
+        Ok(self.handler.power_on(&device_id, machine.port_id).await?)
+    }
+
+    async fn power_off(&self, machine: &Machine) -> Result<(), BackendError> {
+        let device_id = self.handler.device_id(&self.device_mac).await?;
+        Ok(self.handler.power_off(&device_id, machine.port_id).await?)
+    }
+
+    async fn power_draw(&self, machine: &Machine) -> Result<Option<f64>, BackendError> {
+        let device_id = self.handler.device_id(&self.device_mac).await?;
+        let device = self.handler.device(&device_id).await?;
+        Ok(Some(
+            device
+                .port(machine.port_id)
+                .and_then(|port| port.poe_power)
+                .unwrap_or_default(),
+        ))
+    }
+
+    async fn link_up(&self, machine: &Machine) -> Result<Option<bool>, BackendError> {
+        let device_id = self.handler.device_id(&self.device_mac).await?;
+        let device = self.handler.device(&device_id).await?;
+        if let Some(up) = device.port(machine.port_id).and_then(|port| port.up) {
+            return Ok(Some(up));
+        }
+        // Older controllers leave out the link state, a MAC learned on the
+        // port shows the link is up just as well.
+        let learned = self.handler.clients().await?.iter().any(|station| {
+            station.sw_mac == Some(self.device_mac) && station.sw_port == Some(machine.port_id)
+        });
+        Ok(Some(learned))
+    }
+
+    fn hook_env(&self, machine: &Machine) -> Vec<(&'static str, String)> {
+        vec![
+            ("UNIFI_DEVICE_MAC", self.device_mac.to_string()),
+            ("UNIFI_PORT_ID", machine.port_id.to_string()),
+        ]
+    }
+
+    fn with_controller(&self, handler: UnifiHandler) -> Option<Arc<dyn PowerBackend>> {
+        Some(Arc::new(UnifiPoeBackend {
+            handler,
+            device_mac: self.device_mac,
+            stagger: self.stagger,
+            next_power_on: self.next_power_on.clone(),
+            poe_budget: self.poe_budget,
+        }))
+    }
+
+    fn unifi_device(&self) -> Option<MacAddress> {
+        Some(self.device_mac)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UnifiPoeBackend;
+    use crate::{
+        backend::{BackendError, PowerBackend},
+        config::{Machine, PoeBudgetAction, PoeBudgetConfig},
+        unifi::{
+            self,
+            client::{UnifiClient, UnifiError},
+            handler::UnifiHandler,
+            models::{DeviceId, Meta, PoeMode, Port, Station, UnifiResponse},
+        },
+    };
+    use async_trait::async_trait;
+    use mac_address::MacAddress;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    const UNIFI_DEVICE_MAC: [u8; 6] = [00, 00, 00, 00, 00, 00];
+    const UNIFI_DEVICE_ID: &str = "device-id";
+    const MACHINE_PORT: usize = 1;
+
+    /// A switch whose port draws another 1.5W with every power on.
+    #[derive(Clone, Default)]
+    struct FakeUnifiClient {
+        power_ons: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl UnifiClient for FakeUnifiClient {
+        async fn login(&self, _: &str, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<unifi::models::Device>>> {
+            Ok(UnifiResponse {
+                meta: Meta {
+                    rc: "".to_owned(),
+                    ..Default::default()
+                },
+                data: vec![unifi::models::Device {
+                    mac: MacAddress::from(UNIFI_DEVICE_MAC),
+                    device_id: DeviceId::new(UNIFI_DEVICE_ID),
+                    port_table: vec![Port {
+                        port_idx: MACHINE_PORT,
+                        poe_mode: Some(PoeMode::Off),
+                        poe_power: Some(1.5 * (1 + self.power_ons.load(Ordering::SeqCst)) as f64),
+                        ..Default::default()
+                    }],
+                    total_max_power: Some(2.0),
+                    ..Default::default()
+                }],
+            })
+        }
+
+        async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+            Ok(UnifiResponse::default())
+        }
+
+        async fn power_on(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            self.power_ons.fetch_add(1, Ordering::SeqCst);
+            Ok(UnifiResponse::default())
+        }
+
+        async fn power_off(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            Ok(UnifiResponse::default())
+        }
+    }
+
+    fn backend() -> UnifiPoeBackend {
+        let handler = UnifiHandler::new(Box::new(FakeUnifiClient::default()));
+        UnifiPoeBackend::new(handler, MacAddress::from(UNIFI_DEVICE_MAC))
+    }
+
+    fn machine(port_id: usize) -> Machine {
+        Machine {
+            maas_id: "system-id".to_owned(),
+            port_id,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_get_status_of_port() {
+        let status = backend().status(&machine(MACHINE_PORT)).await.unwrap();
+        assert_eq!(status.status, "stopped");
+    }
+
+    #[tokio::test]
+    async fn should_error_if_port_does_not_exist() {
+        let status = backend().status(&machine(MACHINE_PORT + 1)).await;
+        assert!(status.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_get_power_draw_of_port() {
+        let draw = backend().power_draw(&machine(MACHINE_PORT)).await.unwrap();
+        assert_eq!(draw, Some(1.5));
+    }
+
+    #[tokio::test]
+    async fn should_see_no_link_without_a_learned_mac() {
+        let link = backend().link_up(&machine(MACHINE_PORT)).await.unwrap();
+        assert_eq!(link, Some(false));
+    }
+
+    #[tokio::test]
+    async fn should_stagger_power_ons_across_sessions() {
+        let stagger = Duration::from_millis(20);
+        let backend = backend().with_stagger(stagger);
+        let other_session = backend
+            .with_controller(UnifiHandler::new(Box::new(FakeUnifiClient::default())))
+            .unwrap();
+        let start = Instant::now();
+        let machine = machine(MACHINE_PORT);
+        let (a, b, c) = tokio::join!(
+            backend.power_on(&machine),
+            backend.power_on(&machine),
+            other_session.power_on(&machine)
+        );
+        a.and(b).and(c).unwrap();
+        assert!(start.elapsed() >= stagger * 2);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_power_on_over_poe_budget() {
+        let budget = |max_utilization, on_exceed| {
+            backend().with_poe_budget(Some(PoeBudgetConfig {
+                max_utilization,
+                on_exceed,
+            }))
+        };
+        let machine = machine(MACHINE_PORT);
+        let refused = budget(0.5, PoeBudgetAction::Reject)
+            .power_on(&machine)
+            .await;
+        assert!(matches!(
+            refused,
+            Err(BackendError::Unifi(UnifiError::PoeBudgetExceeded(usage))) if usage.draw_watts == 1.5
+        ));
+        budget(0.5, PoeBudgetAction::Warn)
+            .power_on(&machine)
+            .await
+            .unwrap();
+        budget(0.9, PoeBudgetAction::Reject)
+            .power_on(&machine)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_check_the_poe_budget_after_the_stagger() {
+        let backend = backend()
+            .with_stagger(Duration::from_millis(20))
+            .with_poe_budget(Some(PoeBudgetConfig {
+                max_utilization: 0.9,
+                on_exceed: PoeBudgetAction::Reject,
+            }));
+        let machine = machine(MACHINE_PORT);
+        let (first, second) = tokio::join!(backend.power_on(&machine), backend.power_on(&machine));
+        first.unwrap();
+        assert!(matches!(
+            second,
+            Err(BackendError::Unifi(UnifiError::PoeBudgetExceeded(usage))) if usage.draw_watts == 3.0
+        ));
+    }
+}
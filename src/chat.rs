@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use crate::{
+    config::{ChatConfig, Severity},
+    notifications::{EventResult, PowerEvent},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chat {
+    Slack,
+    Discord,
+}
+
+/// Posts power events as chat messages to a Slack or Discord webhook, skipping
+/// those below the configured severity.
+pub struct ChatSink {
+    chat: Chat,
+    url: String,
+    min_severity: Severity,
+    client: Client,
+}
+
+impl ChatSink {
+    pub fn new(chat: Chat, config: &ChatConfig) -> anyhow::Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        Ok(Self {
+            chat,
+            url: config.url.clone(),
+            min_severity: config.min_severity,
+            client,
+        })
+    }
+
+    pub async fn send(&self, event: &PowerEvent) {
+        if event.result.severity() < self.min_severity {
+            return;
+        }
+        let result = self
+            .client
+            .post(&self.url)
+            .json(&self.format(event))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            tracing::warn!("failed to post power event to {:?}: {e}", self.chat);
+        }
+    }
+
+    fn format(&self, event: &PowerEvent) -> Value {
+        let text = message(event);
+        match self.chat {
+            Chat::Slack => json!({ "text": text }),
+            Chat::Discord => json!({ "content": text }),
+        }
+    }
+}
+
+/// One line both Slack and Discord render the same way.
+fn message(event: &PowerEvent) -> String {
+    let machine = &event.machine;
+    let action = event.action.as_str();
+    match event.result {
+        EventResult::Success => format!("✅ {action} of `{machine}` succeeded"),
+        EventResult::Failure => format!(
+            "❌ {action} of `{machine}` failed: {}",
+            event.error.as_deref().unwrap_or("unknown error")
+        ),
+        EventResult::NoPowerDraw => {
+            format!("⚠️ `{machine}` draws no power after {action}, check the PSU and cabling")
+        }
+        EventResult::NoLink => {
+            format!("⚠️ `{machine}` has no link after {action}, check that it boots")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Chat, ChatSink};
+    use crate::{
+        config::{ChatConfig, Severity},
+        notifications::{EventResult, PowerAction, PowerEvent},
+    };
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_json, method},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn should_post_events_at_or_above_the_severity() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(
+                json!({"content": "❌ power_on of `abc123` failed: boom"}),
+            ))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let config = ChatConfig {
+            url: mock_server.uri(),
+            min_severity: Severity::Warning,
+            timeout_secs: 1,
+        };
+        let sink = ChatSink::new(Chat::Discord, &config).unwrap();
+        sink.send(&PowerEvent::new(
+            "abc123",
+            PowerAction::On,
+            EventResult::Success,
+        ))
+        .await;
+        sink.send(
+            &PowerEvent::new("abc123", PowerAction::On, EventResult::Failure).with_error("boom"),
+        )
+        .await;
+    }
+}
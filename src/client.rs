@@ -0,0 +1,329 @@
+use std::{fmt::Display, time::Duration};
+
+use reqwest::{header::RETRY_AFTER, RequestBuilder, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+const SYSTEM_ID: &str = "system_id";
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidBaseUrl(String),
+    /// The request could not be sent or its response could not be read.
+    Http(reqwest::Error),
+    /// The service answered with an error status.
+    Api {
+        status: StatusCode,
+        message: String,
+        /// Set for `429` and `503` responses, when to try again.
+        retry_after: Option<Duration>,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidBaseUrl(e) => write!(f, "invalid base URL: {e}"),
+            Error::Http(e) => write!(f, "request failed: {e}"),
+            Error::Api {
+                status, message, ..
+            } => write!(f, "{status}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PowerAction {
+    #[serde(rename = "power_on")]
+    On,
+    #[serde(rename = "power_off")]
+    Off,
+    #[serde(rename = "power_cycle")]
+    Cycle,
+}
+
+impl PowerAction {
+    fn path(&self) -> &'static str {
+        match self {
+            PowerAction::On => "/power-on",
+            PowerAction::Off => "/power-off",
+            PowerAction::Cycle => "/power-cycle",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: String,
+    pub system_id: String,
+    pub action: PowerAction,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub attempts: u32,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Machine {
+    pub system_id: String,
+    /// e.g. `unifi-poe` or `wol`.
+    pub driver: String,
+    /// `on`, `off` or `unknown`, `None` if the service could not read it.
+    pub status: Option<String>,
+}
+
+/// What a finished power action did.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PowerResult {
+    pub system_id: String,
+    pub action: PowerAction,
+    /// The status before the action, `None` if it could not be read.
+    pub previous_status: Option<String>,
+    /// The status after the action, `None` if it could not be read.
+    pub status: Option<String>,
+    /// Whether the action switched the power, `None` if a status is unknown.
+    pub changed: Option<bool>,
+    pub duration_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct PowerStatus {
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Calls a maas-power-unifi service, e.g.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), maas_power_unifi::client::Error> {
+/// let client = maas_power_unifi::client::Client::new("http://bridge:3000")?;
+/// client.power_on("abc123").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Client {
+    base_url: Url,
+    http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> Result<Self, Error> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Uses `http` to send requests, for custom timeouts or TLS settings.
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Result<Self, Error> {
+        let base_url = Url::parse(base_url)
+            .map_err(|e| Error::InvalidBaseUrl(format!("`{base_url}`, {e}")))?;
+        Ok(Self { base_url, http })
+    }
+
+    /// Keeps any path of the base URL, for a service behind a reverse proxy.
+    fn url(&self, path: &str) -> Url {
+        let mut url = self.base_url.clone();
+        url.set_path(&format!(
+            "{}{path}",
+            self.base_url.path().trim_end_matches('/')
+        ));
+        url
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, Error> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs);
+        let message = match response.json::<ErrorBody>().await {
+            Ok(body) => body.error,
+            Err(_) => status.to_string(),
+        };
+        Err(Error::Api {
+            status,
+            message,
+            retry_after,
+        })
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    /// `on`, `off` or `unknown`.
+    pub async fn status(&self, system_id: &str) -> Result<String, Error> {
+        let request = self
+            .http
+            .get(self.url("/power-status"))
+            .header(SYSTEM_ID, system_id);
+        let status: PowerStatus = self.json(request).await?;
+        Ok(status.status)
+    }
+
+    /// Runs a power action, returning once it has finished.
+    pub async fn power(&self, system_id: &str, action: PowerAction) -> Result<PowerResult, Error> {
+        let request = self
+            .http
+            .post(self.url(action.path()))
+            .query(&[("async", "false")])
+            .header(SYSTEM_ID, system_id);
+        self.json(request).await
+    }
+
+    pub async fn power_on(&self, system_id: &str) -> Result<PowerResult, Error> {
+        self.power(system_id, PowerAction::On).await
+    }
+
+    pub async fn power_off(&self, system_id: &str) -> Result<PowerResult, Error> {
+        self.power(system_id, PowerAction::Off).await
+    }
+
+    pub async fn power_cycle(&self, system_id: &str) -> Result<PowerResult, Error> {
+        self.power(system_id, PowerAction::Cycle).await
+    }
+
+    /// Starts a power action as a job. Retrying with the same
+    /// `idempotency_key` returns the original job instead of starting another.
+    pub async fn start(
+        &self,
+        system_id: &str,
+        action: PowerAction,
+        idempotency_key: Option<&str>,
+    ) -> Result<Job, Error> {
+        let mut request = self
+            .http
+            .post(self.url(action.path()))
+            .query(&[("async", "true")])
+            .header(SYSTEM_ID, system_id);
+        if let Some(key) = idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY, key);
+        }
+        self.json(request).await
+    }
+
+    pub async fn job(&self, id: &str) -> Result<Job, Error> {
+        self.json(self.http.get(self.url(&format!("/jobs/{id}"))))
+            .await
+    }
+
+    /// Polls a job every `interval` until it has succeeded or failed.
+    pub async fn wait_for_job(&self, id: &str, interval: Duration) -> Result<Job, Error> {
+        loop {
+            let job = self.job(id).await?;
+            if matches!(job.status, JobStatus::Succeeded | JobStatus::Failed) {
+                return Ok(job);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    pub async fn machines(&self) -> Result<Vec<Machine>, Error> {
+        self.json(self.http.get(self.url("/machines"))).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Client, Error, JobStatus, PowerAction};
+    use serde_json::json;
+    use std::time::Duration;
+    use wiremock::{
+        matchers::{header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn should_read_power_status() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/power-status"))
+            .and(header("system_id", "abc123"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"status": "on"})))
+            .mount(&mock_server)
+            .await;
+        let client = Client::new(&mock_server.uri()).unwrap();
+        assert_eq!(client.status("abc123").await.unwrap(), "on");
+    }
+
+    #[tokio::test]
+    async fn should_start_job_with_idempotency_key() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/power-cycle"))
+            .and(query_param("async", "true"))
+            .and(header("idempotency-key", "key"))
+            .respond_with(ResponseTemplate::new(202).set_body_json(json!({
+                "id": "job",
+                "system_id": "abc123",
+                "action": "power_cycle",
+                "status": "queued",
+                "attempts": 0,
+                "created_at": "2023-01-01T00:00:00Z",
+                "updated_at": "2023-01-01T00:00:00Z"
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = Client::new(&mock_server.uri()).unwrap();
+        let job = client
+            .start("abc123", PowerAction::Cycle, Some("key"))
+            .await
+            .unwrap();
+        assert_eq!(job.id, "job");
+        assert_eq!(job.status, JobStatus::Queued);
+    }
+
+    #[tokio::test]
+    async fn should_return_api_errors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/power-on"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .insert_header("retry-after", "30")
+                    .set_body_json(json!({"error": "Too many power actions"})),
+            )
+            .mount(&mock_server)
+            .await;
+        let client = Client::new(&mock_server.uri()).unwrap();
+        let Err(Error::Api {
+            status,
+            message,
+            retry_after,
+        }) = client.power_on("abc123").await
+        else {
+            panic!("expected an API error");
+        };
+        assert_eq!(status, 429);
+        assert_eq!(message, "Too many power actions");
+        assert_eq!(retry_after, Some(Duration::from_secs(30)));
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mac_address::MacAddress;
+
+use crate::{metrics::Metrics, unifi::handler::UnifiHandler};
+
+/// Records health gauges of the controller and every configured switch every
+/// `interval`, from a single device list, so trouble with a switch shows
+/// before MaaS notices.
+pub fn spawn_device_sampler(
+    handler: UnifiHandler,
+    devices: Vec<MacAddress>,
+    metrics: Metrics,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            sample_devices(&handler, &devices, &metrics).await;
+            sample_controller(&handler, &metrics);
+            sample_cache(&handler, &metrics);
+        }
+    });
+}
+
+async fn sample_devices(handler: &UnifiHandler, devices: &[MacAddress], metrics: &Metrics) {
+    let listed = match handler.devices().await {
+        Ok(listed) => listed,
+        Err(e) => {
+            tracing::debug!("failed to list devices for metrics: {e:?}");
+            return;
+        }
+    };
+    for mac in devices {
+        let label = mac.to_string();
+        let labels = [("device", label.as_str())];
+        // A switch the controller has lost is not connected.
+        let Some(device) = listed.iter().find(|device| device.mac == *mac) else {
+            metrics.gauge("unifi_device_connected", &labels, 0.0);
+            continue;
+        };
+        let flag = |on: bool| if on { 1.0 } else { 0.0 };
+        metrics.gauge(
+            "unifi_device_connected",
+            &labels,
+            flag(device.is_connected()),
+        );
+        if let Some(adopted) = device.adopted {
+            metrics.gauge("unifi_device_adopted", &labels, flag(adopted));
+        }
+        if let Some(last_seen) = device.last_seen {
+            metrics.gauge("unifi_device_last_seen", &labels, last_seen as f64);
+        }
+        if let Some(uptime) = device.uptime {
+            metrics.gauge("unifi_device_uptime_secs", &labels, uptime as f64);
+        }
+        let watts = device.poe_watts();
+        metrics.gauge("unifi_device_poe_watts", &labels, watts);
+        if let Some(budget) = device.total_max_power.filter(|budget| *budget > 0.0) {
+            metrics.gauge("unifi_device_poe_budget_ratio", &labels, watts / budget);
+        }
+        if let Some(temperature) = device.general_temperature {
+            metrics.gauge("unifi_device_temperature_celsius", &labels, temperature);
+        }
+        if let Some(overheating) = device.overheating {
+            metrics.gauge("unifi_device_overheating", &labels, flag(overheating));
+        }
+        if let Some(fan_level) = device.fan_level {
+            metrics.gauge("unifi_device_fan_level", &labels, fan_level as f64);
+        }
+    }
+}
+
+fn sample_controller(handler: &UnifiHandler, metrics: &Metrics) {
+    let health = handler.health();
+    let healthy = if health.healthy { 1.0 } else { 0.0 };
+    metrics.gauge("unifi_controller_healthy", &[], healthy);
+    if let Some(last_login) = health.last_login {
+        metrics.gauge("unifi_controller_last_login", &[], last_login as f64);
+    }
+    if let Some(last_device_fetch) = health.last_device_fetch {
+        metrics.gauge(
+            "unifi_controller_last_device_fetch",
+            &[],
+            last_device_fetch as f64,
+        );
+    }
+}
+
+/// The device ID cache, counts are totals since startup.
+fn sample_cache(handler: &UnifiHandler, metrics: &Metrics) {
+    let stats = handler.cache_stats();
+    metrics.gauge("unifi_device_id_cache_size", &[], stats.size as f64);
+    metrics.gauge("unifi_device_id_cache_hits", &[], stats.hits as f64);
+    metrics.gauge("unifi_device_id_cache_misses", &[], stats.misses as f64);
+    metrics.gauge(
+        "unifi_device_id_cache_refreshes",
+        &[],
+        stats.refreshes as f64,
+    );
+    metrics.gauge(
+        "unifi_device_id_cache_evictions",
+        &[],
+        stats.evictions as f64,
+    );
+    if let Some(last_refresh) = stats.last_refresh {
+        let age = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .saturating_sub(last_refresh);
+        metrics.gauge("unifi_device_id_cache_age_secs", &[], age as f64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sample_cache, sample_controller, sample_devices};
+    use crate::{
+        config::StatsdFlavor,
+        metrics::test_sink::{received, statsd},
+        unifi::{handler::UnifiHandler, self_hosted::UnifiSelfHostedClient},
+    };
+    use mac_address::MacAddress;
+    use std::str::FromStr;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn should_record_device_health_gauges() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "meta": {"rc": "ok"},
+                "data": [{
+                    "mac": "00:00:00:00:00:01",
+                    "device_id": "device-id",
+                    "state": 1,
+                    "adopted": true,
+                    "last_seen": 1700000000,
+                    "uptime": 3600,
+                    "total_max_power": "50",
+                    "general_temperature": 61,
+                    "overheating": true,
+                    "fan_level": 3,
+                    "port_table": [
+                        {"port_idx": 1, "poe_mode": "auto", "poe_power": "10.0"},
+                        {"port_idx": 2, "poe_mode": "auto", "poe_power": "2.5"}
+                    ]
+                }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let handler = UnifiHandler::new(Box::new(client));
+        let (server, metrics) = statsd(StatsdFlavor::Dogstatsd, &[]);
+        let listed = MacAddress::from_str("00:00:00:00:00:01").unwrap();
+        let missing = MacAddress::from_str("00:00:00:00:00:02").unwrap();
+        sample_devices(&handler, &[listed, missing], &metrics).await;
+        let gauges = received(&server);
+        let gauge = |name, mac: MacAddress, value| {
+            let line = format!("maas.{name}:{value}|g|#device:{mac}");
+            assert!(gauges.contains(&line), "{line} not in {gauges:?}");
+        };
+        gauge("unifi_device_connected", listed, 1.0);
+        gauge("unifi_device_adopted", listed, 1.0);
+        gauge("unifi_device_last_seen", listed, 1700000000.0);
+        gauge("unifi_device_uptime_secs", listed, 3600.0);
+        gauge("unifi_device_poe_watts", listed, 12.5);
+        gauge("unifi_device_poe_budget_ratio", listed, 0.25);
+        gauge("unifi_device_temperature_celsius", listed, 61.0);
+        gauge("unifi_device_overheating", listed, 1.0);
+        gauge("unifi_device_fan_level", listed, 3.0);
+        gauge("unifi_device_connected", missing, 0.0);
+        sample_controller(&handler, &metrics);
+        let gauges = received(&server);
+        assert!(gauges.contains(&"maas.unifi_controller_healthy:1|g".to_owned()));
+        assert!(gauges
+            .iter()
+            .any(|gauge| gauge.starts_with("maas.unifi_controller_last_device_fetch:")));
+        sample_cache(&handler, &metrics);
+        let gauges = received(&server);
+        assert!(gauges.contains(&"maas.unifi_device_id_cache_size:1|g".to_owned()));
+        assert!(gauges.contains(&"maas.unifi_device_id_cache_refreshes:1|g".to_owned()));
+        assert!(gauges
+            .iter()
+            .any(|gauge| gauge.starts_with("maas.unifi_device_id_cache_age_secs:")));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{fs, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    time::sleep,
+};
+use tokio_rustls::{
+    rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+use crate::{
+    config::{EmailConfig, Severity},
+    notifications::PowerEvent,
+};
+
+/// How many events can wait for the next digest.
+const QUEUE: usize = 1024;
+
+/// Mails power events in digests. The first event opens a digest, and every
+/// event until it is sent goes into the same mail, so an outage which fails
+/// every machine at once sends one mail rather than one per machine.
+#[derive(Clone)]
+pub struct EmailDigest {
+    min_severity: Severity,
+    events: mpsc::Sender<PowerEvent>,
+}
+
+impl EmailDigest {
+    pub fn spawn(config: &EmailConfig) -> Self {
+        let (events, mut queue) = mpsc::channel::<PowerEvent>(QUEUE);
+        let mailer = Mailer::new(config.clone());
+        let window = Duration::from_secs(config.digest_secs);
+        tokio::spawn(async move {
+            while let Some(first) = queue.recv().await {
+                sleep(window).await;
+                let mut digest = vec![first];
+                while let Ok(event) = queue.try_recv() {
+                    digest.push(event);
+                }
+                if let Err(e) = mailer.send(&digest).await {
+                    tracing::warn!("failed to mail {} power events: {e:#}", digest.len());
+                }
+            }
+        });
+        Self {
+            min_severity: config.min_severity,
+            events,
+        }
+    }
+
+    pub fn send(&self, event: &PowerEvent) {
+        if event.result.severity() < self.min_severity {
+            return;
+        }
+        if self.events.try_send(event.clone()).is_err() {
+            tracing::warn!("too many power events waiting to be mailed, dropping one");
+        }
+    }
+}
+
+/// Sends mail through an SMTP relay, a connection per mail. The connection is
+/// upgraded with STARTTLS whenever the relay offers it.
+struct Mailer {
+    config: EmailConfig,
+    hostname: String,
+    tls: TlsConnector,
+}
+
+impl Mailer {
+    fn new(config: EmailConfig) -> Self {
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        let tls = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Self {
+            config,
+            hostname: fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|hostname| hostname.trim().to_owned())
+                .unwrap_or_else(|_| "localhost".to_owned()),
+            tls: TlsConnector::from(Arc::new(tls)),
+        }
+    }
+
+    async fn send(&self, events: &[PowerEvent]) -> anyhow::Result<()> {
+        let stream = TcpStream::connect(&self.config.server)
+            .await
+            .with_context(|| format!("failed to connect to {}", self.config.server))?;
+        let mut smtp = Smtp::new(stream);
+        smtp.reply(2).await?;
+        let extensions = smtp.command(&format!("EHLO {}", self.hostname), 2).await?;
+        if !extensions
+            .iter()
+            .any(|extension| extension.eq_ignore_ascii_case("STARTTLS"))
+        {
+            return self.deliver(smtp, events, false).await;
+        }
+        smtp.command("STARTTLS", 2).await?;
+        let host = self
+            .config
+            .server
+            .rsplit_once(':')
+            .map_or(self.config.server.as_str(), |(host, _)| host);
+        let name = ServerName::try_from(host)
+            .with_context(|| format!("`{host}` is not a name to check a certificate against"))?;
+        let stream = self
+            .tls
+            .connect(name, smtp.into_inner())
+            .await
+            .with_context(|| format!("failed to start TLS with {}", self.config.server))?;
+        let mut smtp = Smtp::new(stream);
+        smtp.command(&format!("EHLO {}", self.hostname), 2).await?;
+        self.deliver(smtp, events, true).await
+    }
+
+    /// Sends the mail once the relay has been greeted, logging in first if
+    /// configured. The password is only sent over TLS.
+    async fn deliver<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut smtp: Smtp<S>,
+        events: &[PowerEvent],
+        encrypted: bool,
+    ) -> anyhow::Result<()> {
+        if let Some(auth) = &self.config.auth {
+            if !encrypted {
+                bail!(
+                    "{} does not offer STARTTLS, so the password is not sent",
+                    self.config.server
+                );
+            }
+            let credentials = STANDARD.encode(format!("\0{}\0{}", auth.username, auth.password));
+            smtp.command(&format!("AUTH PLAIN {credentials}"), 2)
+                .await?;
+        }
+        smtp.command(&format!("MAIL FROM:<{}>", self.config.from), 2)
+            .await?;
+        for to in &self.config.to {
+            smtp.command(&format!("RCPT TO:<{to}>"), 2).await?;
+        }
+        smtp.command("DATA", 3).await?;
+        smtp.stream
+            .write_all(self.message(events).as_bytes())
+            .await?;
+        smtp.command(".", 2).await?;
+        // The mail is accepted, a failed goodbye does not matter.
+        let _ = smtp.command("QUIT", 2).await;
+        Ok(())
+    }
+
+    fn message(&self, events: &[PowerEvent]) -> String {
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: maas-power-unifi on {}: {} power event{}\r\n\
+             MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: 8bit\r\n\r\n",
+            self.config.from,
+            self.config.to.join(", "),
+            self.hostname,
+            events.len(),
+            if events.len() == 1 { "" } else { "s" },
+        );
+        for event in events {
+            let result = serde_json::to_value(event.result)
+                .ok()
+                .and_then(|result| result.as_str().map(str::to_owned))
+                .unwrap_or_default();
+            let mut line = format!(
+                "{} {} of {}: {result}",
+                event.timestamp,
+                event.action.as_str(),
+                event.machine
+            );
+            if let Some(error) = &event.error {
+                line.push_str(&format!(", {error}"));
+            }
+            // Errors carry a hook's output over several lines. A line of a
+            // single dot would end the mail early, SMTP escapes a leading dot
+            // with another.
+            for line in line.split(['\r', '\n']).filter(|line| !line.is_empty()) {
+                if line.starts_with('.') {
+                    message.push('.');
+                }
+                message.push_str(line);
+                message.push_str("\r\n");
+            }
+        }
+        message
+    }
+}
+
+struct Smtp<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Smtp<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Sends a command and waits for a reply in the given class, 2 for
+    /// success and 3 for the server waiting on more.
+    async fn command(&mut self, command: &str, class: u16) -> anyhow::Result<Vec<String>> {
+        self.stream
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        self.reply(class).await
+    }
+
+    /// Waits for a reply and returns the text of its lines, e.g. the
+    /// extensions a server lists after `EHLO`.
+    async fn reply(&mut self, class: u16) -> anyhow::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                bail!("the SMTP server closed the connection");
+            }
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse::<u16>().ok())
+                .with_context(|| format!("invalid SMTP reply `{}`", line.trim_end()))?;
+            lines.push(line.get(4..).unwrap_or_default().trim_end().to_owned());
+            // `250-` continues a reply over more lines, `250 ` ends it.
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            if code / 100 != class {
+                bail!("the SMTP server answered `{}`", line.trim_end());
+            }
+            return Ok(lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Mailer;
+    use crate::{
+        config::{EmailConfig, Severity, SmtpAuthConfig},
+        notifications::{EventResult, PowerAction, PowerEvent},
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// Accepts one mail, answering every command with success, and returns
+    /// everything the client sent.
+    async fn smtp_server(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        writer.write_all(b"220 ready\r\n").await.unwrap();
+        let mut received = String::new();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap() == 0 {
+                return received;
+            }
+            received.push_str(&line);
+            let reply: &[u8] = match line.trim_end() {
+                "." if in_data => {
+                    in_data = false;
+                    b"250 queued\r\n"
+                }
+                _ if in_data => continue,
+                "DATA" => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                }
+                "QUIT" => {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    return received;
+                }
+                command if command.starts_with("EHLO") => b"250-hello\r\n250 AUTH PLAIN\r\n",
+                command if command.starts_with("AUTH") => b"235 ok\r\n",
+                _ => b"250 ok\r\n",
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+    }
+
+    fn config(listener: &TcpListener) -> EmailConfig {
+        EmailConfig {
+            server: listener.local_addr().unwrap().to_string(),
+            auth: None,
+            from: "bridge@example.com".to_owned(),
+            to: vec!["ops@example.com".to_owned()],
+            min_severity: Severity::Error,
+            digest_secs: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_mail_every_event_in_one_digest() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = config(&listener);
+        let server = tokio::spawn(smtp_server(listener));
+        let events = [
+            PowerEvent::new("abc123", PowerAction::On, EventResult::Failure).with_error("boom"),
+            PowerEvent::new("def456", PowerAction::Off, EventResult::Failure),
+        ];
+        Mailer::new(config).send(&events).await.unwrap();
+        let received = server.await.unwrap();
+        assert!(received.contains("RCPT TO:<ops@example.com>\r\n"));
+        assert!(received.contains("power_on of abc123: failure, boom\r\n"));
+        assert!(received.contains("power_off of def456: failure\r\n"));
+        assert!(received.contains(": 2 power events\r\n"));
+    }
+
+    #[tokio::test]
+    async fn should_escape_every_line_of_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = config(&listener);
+        let server = tokio::spawn(smtp_server(listener));
+        let events = [
+            PowerEvent::new("abc123", PowerAction::On, EventResult::Failure)
+                .with_error("boom\r\n.\r\nRSET"),
+        ];
+        Mailer::new(config).send(&events).await.unwrap();
+        let received = server.await.unwrap();
+        assert!(received.contains("failure, boom\r\n..\r\nRSET\r\n.\r\nQUIT\r\n"));
+    }
+
+    #[tokio::test]
+    async fn should_not_send_the_password_without_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = EmailConfig {
+            auth: Some(SmtpAuthConfig {
+                username: "maas".to_owned(),
+                password: "secret".to_owned(),
+            }),
+            ..config(&listener)
+        };
+        let server = tokio::spawn(smtp_server(listener));
+        let events = [PowerEvent::new(
+            "abc123",
+            PowerAction::On,
+            EventResult::Failure,
+        )];
+        let error = Mailer::new(config).send(&events).await.unwrap_err();
+        assert!(error.to_string().contains("STARTTLS"), "{error:#}");
+        assert!(!server.await.unwrap().contains("AUTH"));
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::{
+    header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH},
+    HeaderMap, StatusCode,
+};
+use serde::Serialize;
+
+/// A strong validator for `body`.
+pub fn etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:x}\"", hasher.finish())
+}
+
+/// Whether the request's `If-None-Match` names `etag`, so the client already
+/// has the current body.
+pub fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Answers with `value` as JSON and its `ETag`, or with an empty `304` if the
+/// client sent that `ETag` in `If-None-Match`.
+pub fn json_with_etag<T: Serialize>(headers: &HeaderMap, value: T) -> Response {
+    let body = match serde_json::to_vec(&value) {
+        Ok(body) => body,
+        Err(_) => return Json(value).into_response(),
+    };
+    let etag = etag(&body);
+    let cache_headers = [(ETAG, etag.clone()), (CACHE_CONTROL, "no-cache".to_owned())];
+    if is_fresh(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (cache_headers, Json(value)).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{etag, is_fresh};
+    use http::{header::IF_NONE_MATCH, HeaderMap, HeaderValue};
+
+    #[test]
+    fn should_match_any_listed_etag() {
+        let etag = etag(b"{\"status\":\"on\"}");
+        let mut headers = HeaderMap::new();
+        assert!(!is_fresh(&headers, &etag));
+        let value = format!("\"other\", W/{etag}");
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_str(&value).unwrap());
+        assert!(is_fresh(&headers, &etag));
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!is_fresh(&headers, &etag));
+    }
+}
@@ -0,0 +1,285 @@
+use schemars::{
+    gen::SchemaSettings,
+    schema::{InstanceType, Schema, SchemaObject, SingleOrVec},
+    Map,
+};
+use serde_json::Value;
+
+use crate::config::Config;
+
+const WIDTH: usize = 80;
+
+/// An annotated config with every key, built from the schema of [`Config`] so
+/// it stays in step with the code. Keys with a default are set to it, optional
+/// keys and sections are commented out.
+pub fn example_config() -> String {
+    let root = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Config>();
+    let mut example = Example {
+        definitions: &root.definitions,
+        out: String::new(),
+    };
+    example.comment(
+        "An example maas-power-unifi config. Optional keys are commented out, \
+        keys with a default are set to it.",
+    );
+    example.out.push('\n');
+    example.table("", &root.schema, false);
+    example.out
+}
+
+struct Example<'a> {
+    definitions: &'a Map<String, Schema>,
+    out: String,
+}
+
+/// A key of a table, either a value or a table of its own.
+struct Key<'a> {
+    name: &'a str,
+    description: Option<&'a str>,
+    value: Option<&'a Value>,
+    schema: &'a SchemaObject,
+    optional: bool,
+}
+
+impl<'a> Example<'a> {
+    /// Follows references and unwraps `Option`s, returning the schema of the
+    /// underlying type and whether it was optional.
+    fn resolve(&self, schema: &'a SchemaObject) -> (&'a SchemaObject, bool) {
+        if let Some(Schema::Object(definition)) = schema
+            .reference
+            .as_deref()
+            .and_then(|reference| reference.strip_prefix("#/definitions/"))
+            .and_then(|name| self.definitions.get(name))
+        {
+            return self.resolve(definition);
+        }
+        if let Some(subschemas) = &schema.subschemas {
+            if let Some([Schema::Object(inner)]) = subschemas.all_of.as_deref() {
+                return self.resolve(inner);
+            }
+            if let Some(any_of) = &subschemas.any_of {
+                let mut types = any_of.iter().filter_map(|schema| match schema {
+                    Schema::Object(schema) if !is_null(schema) => Some(schema),
+                    _ => None,
+                });
+                if let (Some(inner), None) = (types.next(), types.next()) {
+                    return (self.resolve(inner).0, true);
+                }
+            }
+        }
+        let nullable = match &schema.instance_type {
+            Some(SingleOrVec::Vec(types)) => types.contains(&InstanceType::Null),
+            _ => false,
+        };
+        (schema, nullable)
+    }
+
+    fn is_table(&self, schema: &SchemaObject) -> bool {
+        schema
+            .object
+            .as_ref()
+            .is_some_and(|object| !object.properties.is_empty())
+    }
+
+    /// The table of items for an array of tables.
+    fn array_items(&self, schema: &'a SchemaObject) -> Option<&'a SchemaObject> {
+        match schema.array.as_ref()?.items.as_ref()? {
+            SingleOrVec::Single(items) => match items.as_ref() {
+                Schema::Object(items) => {
+                    let (items, _) = self.resolve(items);
+                    self.is_table(items).then_some(items)
+                }
+                Schema::Bool(_) => None,
+            },
+            SingleOrVec::Vec(_) => None,
+        }
+    }
+
+    fn keys(&self, schema: &'a SchemaObject) -> Vec<Key<'a>> {
+        let Some(object) = &schema.object else {
+            return Vec::new();
+        };
+        object
+            .properties
+            .iter()
+            .filter_map(|(name, property)| {
+                let Schema::Object(property) = property else {
+                    return None;
+                };
+                let (resolved, nullable) = self.resolve(property);
+                let metadata = property.metadata.as_deref();
+                let value = metadata
+                    .and_then(|metadata| metadata.examples.first())
+                    .or_else(|| metadata.and_then(|metadata| metadata.default.as_ref()))
+                    .filter(|value| !value.is_null());
+                let has_default = metadata
+                    .and_then(|metadata| metadata.default.as_ref())
+                    .is_some_and(|default| !default.is_null());
+                Some(Key {
+                    name,
+                    description: metadata
+                        .and_then(|metadata| metadata.description.as_deref())
+                        .or_else(|| {
+                            resolved
+                                .metadata
+                                .as_deref()
+                                .and_then(|metadata| metadata.description.as_deref())
+                        }),
+                    value,
+                    schema: resolved,
+                    optional: nullable
+                        || (!object.required.contains(name.as_str()) && !has_default),
+                })
+            })
+            .collect()
+    }
+
+    fn table(&mut self, path: &str, schema: &'a SchemaObject, commented: bool) {
+        let (tables, values): (Vec<_>, Vec<_>) = self
+            .keys(schema)
+            .into_iter()
+            .partition(|key| self.is_table(key.schema) || self.array_items(key.schema).is_some());
+        for (i, key) in values.into_iter().enumerate() {
+            if let Some(description) = key.description {
+                if i > 0 {
+                    self.out.push('\n');
+                }
+                self.comment(description);
+            }
+            let variants = variants(key.schema);
+            if !variants.is_empty() {
+                let variants: Vec<_> = variants.iter().map(|v| format!("`{v}`")).collect();
+                self.comment(&format!("One of {}.", variants.join(", ")));
+            }
+            let value = key
+                .value
+                .cloned()
+                .or_else(|| variants.first().map(|v| Value::String(v.clone())))
+                .unwrap_or_else(|| placeholder(key.schema));
+            self.line(
+                &format!("{} = {}", key.name, toml_value(value)),
+                commented || key.optional,
+            );
+        }
+        for key in tables {
+            let path = if path.is_empty() {
+                key.name.to_owned()
+            } else {
+                format!("{path}.{}", key.name)
+            };
+            self.out.push('\n');
+            if let Some(description) = key.description {
+                self.comment(description);
+            }
+            match self.array_items(key.schema) {
+                Some(items) => {
+                    self.line(&format!("[[{path}]]"), true);
+                    self.table(&path, items, true);
+                }
+                None => {
+                    let commented = commented || key.optional;
+                    self.line(&format!("[{path}]"), commented);
+                    self.table(&path, key.schema, commented);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, line: &str, commented: bool) {
+        if commented {
+            self.out.push_str("# ");
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    /// Writes `text` as comments, wrapped to fit [`WIDTH`].
+    fn comment(&mut self, text: &str) {
+        for paragraph in text.lines() {
+            let mut line = String::new();
+            for word in paragraph.split_whitespace() {
+                if !line.is_empty() && line.len() + word.len() + 3 > WIDTH {
+                    self.line(&format!("# {line}"), false);
+                    line.clear();
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(word);
+            }
+            self.line(format!("# {line}").trim_end(), false);
+        }
+    }
+}
+
+fn is_null(schema: &SchemaObject) -> bool {
+    schema.instance_type == Some(SingleOrVec::Single(Box::new(InstanceType::Null)))
+}
+
+/// The values of an enum, documented variants are listed under `oneOf`.
+fn variants(schema: &SchemaObject) -> Vec<String> {
+    let one_of = schema
+        .subschemas
+        .as_ref()
+        .and_then(|subschemas| subschemas.one_of.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|variant| match variant {
+            Schema::Object(variant) => variant.enum_values.as_ref(),
+            Schema::Bool(_) => None,
+        })
+        .flatten();
+    schema
+        .enum_values
+        .iter()
+        .flatten()
+        .chain(one_of)
+        .filter_map(|value| value.as_str().map(str::to_owned))
+        .collect()
+}
+
+/// An empty value of the right type for a key without a default or example.
+fn placeholder(schema: &SchemaObject) -> Value {
+    let instance_type = match &schema.instance_type {
+        Some(SingleOrVec::Single(instance_type)) => Some(**instance_type),
+        Some(SingleOrVec::Vec(types)) => types
+            .iter()
+            .copied()
+            .find(|instance_type| *instance_type != InstanceType::Null),
+        None => None,
+    };
+    match instance_type {
+        Some(InstanceType::Boolean) => Value::Bool(false),
+        Some(InstanceType::Integer) => Value::from(0),
+        Some(InstanceType::Number) => Value::from(0.0),
+        Some(InstanceType::Array) => Value::Array(Vec::new()),
+        Some(InstanceType::Object) => Value::Object(Default::default()),
+        _ => Value::String(String::new()),
+    }
+}
+
+fn toml_value(value: Value) -> String {
+    toml::Value::try_from(value)
+        .map(|value| value.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::example_config;
+    use crate::config::parse_config;
+
+    #[test]
+    fn should_generate_valid_example_config() {
+        let example = example_config();
+        let config = parse_config(&example).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.url, "https://localhost:8443");
+        assert!(example.contains("\n[concurrency]\nstatus = 16\npower = 8\n"));
+        assert!(example.contains("\n# [[devices]]\n"));
+        assert!(example.contains("# [[devices.machines]]\n"));
+        assert!(example.contains("# One of `unifi-poe`, `wol`, `edgeswitch`, `mpower`.\n"));
+    }
+}
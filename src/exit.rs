@@ -0,0 +1,101 @@
+use std::fmt::Display;
+
+use http::StatusCode;
+
+use crate::unifi::client::ControllerApiError;
+
+/// Why the binary failed, attached to errors with `.context(..)` so the exit
+/// code tells scripts which kind of failure it was. Any other error exits
+/// with 1, and clap exits with 2 for invalid arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The config could not be read, parsed or validated.
+    Config,
+    /// The controller rejected the credentials.
+    Auth,
+    /// The controller could not be reached or failed to answer.
+    Unreachable,
+    /// The mapping does not match what the controller reports.
+    Mapping,
+}
+
+impl Failure {
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::Config => 3,
+            Failure::Auth => 4,
+            Failure::Unreachable => 5,
+            Failure::Mapping => 6,
+        }
+    }
+
+    /// The exit code for `error`, 1 if no failure kind is attached.
+    pub fn exit_code(error: &anyhow::Error) -> u8 {
+        error
+            .downcast_ref::<Failure>()
+            .map_or(1, |failure| failure.code())
+    }
+
+    /// Classifies a failed login, the controller answers bad credentials
+    /// with a client error.
+    pub fn of_login(error: &anyhow::Error) -> Failure {
+        let status = error.chain().find_map(|e| {
+            e.downcast_ref::<reqwest::Error>()
+                .and_then(reqwest::Error::status)
+                .or_else(|| {
+                    e.downcast_ref::<ControllerApiError>()
+                        .and_then(|e| StatusCode::from_u16(e.status).ok())
+                })
+        });
+        match status {
+            Some(status) if status.is_client_error() && status != StatusCode::NOT_FOUND => {
+                Failure::Auth
+            }
+            _ => Failure::Unreachable,
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Config => write!(f, "invalid config"),
+            Failure::Auth => write!(f, "the controller rejected the credentials"),
+            Failure::Unreachable => write!(f, "the controller could not be reached"),
+            Failure::Mapping => write!(f, "the mapping does not match the controller"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Failure;
+    use crate::unifi::{client::UnifiClient, self_hosted::UnifiSelfHostedClient};
+    use anyhow::{anyhow, Context};
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn should_classify_login_failures() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/api/login"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&mock_server)
+            .await;
+        let client = UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let error = client.login("admin", "wrong").await.unwrap_err();
+        assert_eq!(Failure::of_login(&error), Failure::Auth);
+        let client =
+            UnifiSelfHostedClient::new("http://127.0.0.1:1", reqwest::Client::new()).unwrap();
+        let error = client.login("admin", "secret").await.unwrap_err();
+        assert_eq!(Failure::of_login(&error), Failure::Unreachable);
+    }
+
+    #[test]
+    fn should_exit_with_code_of_attached_failure() {
+        let error = Err::<(), _>(anyhow!("missing url"))
+            .context(Failure::Config)
+            .unwrap_err();
+        assert_eq!(Failure::exit_code(&error), 3);
+        assert_eq!(Failure::exit_code(&anyhow!("other")), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::{config::FlapViolation, notifications::PowerAction, shared_state::SharedState};
+
+/// Keeps a machine from being powered back on right after it was powered
+/// off, or the other way round, as rapid PoE toggling can damage a PSU. The
+/// last transition is kept in the shared state so the guard holds across
+/// instances. The interval is per machine, see `Config::anti_flap_secs`.
+#[derive(Clone)]
+pub struct FlapGuard {
+    shared: SharedState,
+    on_violation: FlapViolation,
+}
+
+impl Default for FlapGuard {
+    fn default() -> Self {
+        Self::new(SharedState::default(), FlapViolation::Reject)
+    }
+}
+
+/// A power action that came too soon after the last transition.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Flap {
+    /// Whether the machine was last powered on rather than off.
+    pub last_on: bool,
+    /// The interval that applies to the machine.
+    #[serde(rename = "min_interval_secs", serialize_with = "as_secs")]
+    pub min_interval: Duration,
+    #[serde(rename = "retry_after_secs", serialize_with = "as_secs")]
+    pub retry_after: Duration,
+}
+
+/// Whole seconds, rounded up so a client never retries too early.
+fn as_secs<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_secs() + u64::from(duration.subsec_nanos() > 0))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl FlapGuard {
+    pub fn new(shared: SharedState, on_violation: FlapViolation) -> Self {
+        Self {
+            shared,
+            on_violation,
+        }
+    }
+
+    /// Whether an action that comes too soon is held rather than refused.
+    pub fn delays(&self) -> bool {
+        self.on_violation == FlapViolation::Delay
+    }
+
+    /// Returns how long until `action` is allowed if it would reverse the
+    /// machine's last transition within `min_interval`. Repeating the last
+    /// transition is always allowed, a cycle reverses either.
+    pub async fn check(
+        &self,
+        system_id: &str,
+        action: PowerAction,
+        min_interval: Duration,
+    ) -> anyhow::Result<Option<Flap>> {
+        let Some(last) = self.shared.get(&format!("flap:{system_id}")).await? else {
+            return Ok(None);
+        };
+        let Some((direction, at)) = last.split_once(':') else {
+            return Ok(None);
+        };
+        let last_on = direction == "on";
+        let repeats = match action {
+            PowerAction::On => last_on,
+            PowerAction::Off => !last_on,
+            PowerAction::Cycle => false,
+        };
+        let elapsed = Duration::from_millis(now_ms().saturating_sub(at.parse()?));
+        let retry_after = min_interval.saturating_sub(elapsed);
+        Ok((!repeats && !retry_after.is_zero()).then_some(Flap {
+            last_on,
+            min_interval,
+            retry_after,
+        }))
+    }
+
+    /// Records a transition the machine went through, a cycle ends powered
+    /// on. It is kept for `min_interval`.
+    pub async fn record(
+        &self,
+        system_id: &str,
+        action: PowerAction,
+        min_interval: Duration,
+    ) -> anyhow::Result<()> {
+        let direction = if action == PowerAction::Off {
+            "off"
+        } else {
+            "on"
+        };
+        self.shared
+            .set(
+                &format!("flap:{system_id}"),
+                &format!("{direction}:{}", now_ms()),
+                min_interval,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FlapGuard;
+    use crate::notifications::PowerAction;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn should_only_refuse_reversals_within_interval() {
+        let guard = FlapGuard::default();
+        let interval = Duration::from_secs(60);
+        let check = |action| guard.check("a", action, interval);
+        assert!(check(PowerAction::On).await.unwrap().is_none());
+        guard.record("a", PowerAction::Off, interval).await.unwrap();
+        assert!(check(PowerAction::Off).await.unwrap().is_none());
+        let flap = check(PowerAction::On).await.unwrap().unwrap();
+        assert!(!flap.last_on);
+        assert!(flap.retry_after <= interval);
+        assert!(check(PowerAction::Cycle).await.unwrap().is_some());
+        assert!(guard
+            .check("b", PowerAction::On, interval)
+            .await
+            .unwrap()
+            .is_none());
+        // A shorter interval for the same machine has already passed.
+        assert!(guard
+            .check("a", PowerAction::On, Duration::ZERO)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,227 @@
+use std::time::SystemTime;
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Error, Object, Result, Schema, SimpleObject,
+};
+use axum::{Extension, Json};
+
+use crate::{backend::Target, router::AppState};
+
+/// A read-only view of the machines, devices and recent power actions.
+pub type FleetSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub fn schema(state: AppState) -> FleetSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(state)
+        .finish()
+}
+
+pub async fn graphql(
+    Extension(schema): Extension<FleetSchema>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(schema.execute(request).await)
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn machines(&self, ctx: &Context<'_>) -> Vec<Machine> {
+        let mut targets = ctx.data_unchecked::<AppState>().backends.targets();
+        targets.sort_by(|a, b| a.machine.maas_id.cmp(&b.machine.maas_id));
+        targets.into_iter().map(Machine).collect()
+    }
+
+    async fn machine(&self, ctx: &Context<'_>, system_id: String) -> Option<Machine> {
+        ctx.data_unchecked::<AppState>()
+            .backends
+            .resolve(&system_id)
+            .map(Machine)
+    }
+
+    /// The configured UniFi devices, with their ports as the controller
+    /// reports them.
+    async fn devices(&self, ctx: &Context<'_>) -> Result<Vec<Device>> {
+        let state = ctx.data_unchecked::<AppState>();
+        let controller_devices = state
+            .controller
+            .devices()
+            .await
+            .map_err(|e| Error::new(format!("failed to list devices: {e:?}")))?;
+        Ok(state
+            .config
+            .load()
+            .devices
+            .iter()
+            .map(|device| {
+                let ports = controller_devices
+                    .iter()
+                    .find(|controller_device| controller_device.mac == device.mac)
+                    .map(|controller_device| {
+                        controller_device
+                            .port_table
+                            .iter()
+                            .map(|port| DevicePort {
+                                idx: port.port_idx,
+                                supports_poe: port.supports_poe(),
+                                poe_watts: port.poe_power,
+                                system_id: device
+                                    .machines
+                                    .iter()
+                                    .find(|machine| machine.port_id == port.port_idx)
+                                    .map(|machine| machine.maas_id.clone()),
+                            })
+                            .collect()
+                    });
+                Device {
+                    mac: device.mac.to_string(),
+                    found: ports.is_some(),
+                    ports: ports.unwrap_or_default(),
+                }
+            })
+            .collect())
+    }
+}
+
+pub struct Machine(Target);
+
+#[Object]
+impl Machine {
+    async fn system_id(&self) -> &str {
+        &self.0.machine.maas_id
+    }
+
+    async fn driver(&self) -> String {
+        self.0.machine.driver.to_string()
+    }
+
+    /// The switch port for `unifi-poe` machines.
+    async fn port_id(&self) -> Option<usize> {
+        Some(self.0.machine.port_id).filter(|port_id| *port_id != 0)
+    }
+
+    async fn status(&self) -> Result<String> {
+        let status = self
+            .0
+            .backend
+            .status(&self.0.machine)
+            .await
+            .map_err(|e| Error::new(format!("{e:?}")))?;
+        Ok(status.status)
+    }
+
+    async fn power_draw_watts(&self) -> Result<Option<f64>> {
+        self.0
+            .backend
+            .power_draw(&self.0.machine)
+            .await
+            .map_err(|e| Error::new(format!("{e:?}")))
+    }
+
+    /// Power actions within `window`, e.g. `24h`, newest last.
+    async fn recent_actions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = "24h")] window: String,
+    ) -> Result<Vec<Action>> {
+        let since = SystemTime::now()
+            .checked_sub(humantime::parse_duration(&window)?)
+            .ok_or_else(|| Error::new(format!("The window `{window}` is too long")))?;
+        let actions = ctx
+            .data_unchecked::<AppState>()
+            .store
+            .power_actions(&self.0.machine.maas_id, since)
+            .await?;
+        Ok(actions
+            .into_iter()
+            .map(|record| Action {
+                action: record.action,
+                success: record.success,
+                timestamp: rfc3339(record.timestamp),
+            })
+            .collect())
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Device {
+    mac: String,
+    /// Whether the controller knows the device.
+    found: bool,
+    ports: Vec<DevicePort>,
+}
+
+#[derive(SimpleObject)]
+pub struct DevicePort {
+    idx: usize,
+    supports_poe: bool,
+    poe_watts: Option<f64>,
+    /// The machine mapped to the port.
+    system_id: Option<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct Action {
+    action: String,
+    success: bool,
+    timestamp: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{config::Config, router::routes, router::test::app_state};
+    use http::{Method, Request};
+    use hyper::{body, Body};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    async fn query(query: &str) -> serde_json::Value {
+        let config = toml::from_str::<Config>(
+            r#"
+                url = "https://localhost:8443"
+
+                [[devices]]
+                mac = "00:00:00:00:00:00"
+                machines = [{ maas_id = "maas_id", port_id = 1 }]
+                "#,
+        )
+        .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/graphql")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "query": query }).to_string()))
+            .unwrap();
+        let response = routes(app_state(config)).oneshot(request).await.unwrap();
+        serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_query_machines() {
+        let body = query("{ machines { systemId driver portId recentActions { action } } }").await;
+        assert_eq!(
+            body["data"]["machines"],
+            json!([{
+                "systemId": "maas_id",
+                "driver": "unifi-poe",
+                "portId": 1,
+                "recentActions": []
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn should_refuse_a_window_before_the_epoch() {
+        let body =
+            query(r#"{ machines { recentActions(window: "500000000000y") { action } } }"#).await;
+        assert!(body["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("is too long"));
+    }
+}
@@ -0,0 +1,260 @@
+use std::{net::SocketAddr, pin::Pin};
+
+use http::StatusCode;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tonic::{transport::Server, Code, Request, Response, Status};
+
+use crate::{
+    config::Role,
+    notifications::{self, PowerAction},
+    router::{machine_status, power_action_now, AppError, AppState},
+};
+
+use proto::{
+    power_server::{Power, PowerServer},
+    ActionReply, ListMachinesReply, ListMachinesRequest, Machine, MachineRequest, PowerEvent,
+    StatusReply, StreamEventsRequest,
+};
+
+pub mod proto {
+    tonic::include_proto!("maas_power_unifi.v1");
+}
+
+/// The gRPC API, running the same operations as the REST routes.
+pub struct PowerService {
+    state: AppState,
+}
+
+pub async fn serve(listen: SocketAddr, state: AppState) -> anyhow::Result<()> {
+    tracing::info!("serving gRPC on {listen}");
+    Server::builder()
+        .add_service(PowerServer::new(PowerService { state }))
+        .serve(listen)
+        .await?;
+    Ok(())
+}
+
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        let (status, message) = error.status_and_message();
+        let code = match status {
+            StatusCode::BAD_REQUEST => Code::InvalidArgument,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::CONFLICT => Code::Aborted,
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            StatusCode::NOT_IMPLEMENTED => Code::Unimplemented,
+            _ => Code::Internal,
+        };
+        Status::new(code, message)
+    }
+}
+
+impl From<notifications::PowerEvent> for PowerEvent {
+    fn from(event: notifications::PowerEvent) -> Self {
+        let result = serde_json::to_value(event.result)
+            .ok()
+            .and_then(|result| result.as_str().map(str::to_owned))
+            .unwrap_or_default();
+        Self {
+            system_id: event.machine,
+            action: event.action.as_str().to_owned(),
+            result,
+            error: event.error,
+            timestamp: event.timestamp,
+        }
+    }
+}
+
+impl PowerService {
+    /// Checks the request's metadata the same way `[auth]` checks HTTP
+    /// headers. Done at the top of every call rather than in an interceptor,
+    /// as those cannot wait on a JWT's signing keys being fetched.
+    async fn authorize<T>(&self, request: &Request<T>, needed: Role) -> Result<(), Status> {
+        let headers = request.metadata().clone().into_headers();
+        match self.state.authenticator.role(&headers).await {
+            Some(role) if role >= needed => Ok(()),
+            Some(_) => Err(Status::permission_denied("This needs the `power` role")),
+            None => Err(Status::unauthenticated("Missing or invalid credentials")),
+        }
+    }
+
+    async fn action(
+        &self,
+        request: Request<MachineRequest>,
+        action: PowerAction,
+    ) -> Result<Response<ActionReply>, Status> {
+        self.authorize(&request, Role::Power).await?;
+        let system_id = request.into_inner().system_id;
+        power_action_now(self.state.clone(), system_id, action).await?;
+        Ok(Response::new(ActionReply {}))
+    }
+}
+
+#[tonic::async_trait]
+impl Power for PowerService {
+    type StreamEventsStream = Pin<Box<dyn Stream<Item = Result<PowerEvent, Status>> + Send>>;
+
+    async fn status(
+        &self,
+        request: Request<MachineRequest>,
+    ) -> Result<Response<StatusReply>, Status> {
+        self.authorize(&request, Role::Read).await?;
+        let status = machine_status(&self.state, &request.into_inner().system_id).await?;
+        Ok(Response::new(StatusReply {
+            status: status.status,
+        }))
+    }
+
+    async fn power_on(
+        &self,
+        request: Request<MachineRequest>,
+    ) -> Result<Response<ActionReply>, Status> {
+        self.action(request, PowerAction::On).await
+    }
+
+    async fn power_off(
+        &self,
+        request: Request<MachineRequest>,
+    ) -> Result<Response<ActionReply>, Status> {
+        self.action(request, PowerAction::Off).await
+    }
+
+    async fn power_cycle(
+        &self,
+        request: Request<MachineRequest>,
+    ) -> Result<Response<ActionReply>, Status> {
+        self.action(request, PowerAction::Cycle).await
+    }
+
+    async fn list_machines(
+        &self,
+        request: Request<ListMachinesRequest>,
+    ) -> Result<Response<ListMachinesReply>, Status> {
+        self.authorize(&request, Role::Read).await?;
+        let mut machines: Vec<Machine> = self
+            .state
+            .backends
+            .targets()
+            .into_iter()
+            .map(|target| Machine {
+                system_id: target.machine.maas_id.clone(),
+                driver: target.machine.driver.to_string(),
+            })
+            .collect();
+        machines.sort_by(|a, b| a.system_id.cmp(&b.system_id));
+        Ok(Response::new(ListMachinesReply { machines }))
+    }
+
+    /// Subscribers too slow to keep up skip the events they missed.
+    async fn stream_events(
+        &self,
+        request: Request<StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        self.authorize(&request, Role::Read).await?;
+        let events = BroadcastStream::new(self.state.notifier.subscribe())
+            .filter_map(Result::ok)
+            .map(PowerEvent::from)
+            .map(Ok);
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        proto::{power_server::Power, ListMachinesRequest, MachineRequest, StreamEventsRequest},
+        PowerService,
+    };
+    use crate::{
+        config::{AuthConfig, Config, Role, TokenConfig},
+        router::test::app_state,
+    };
+    use tokio_stream::StreamExt;
+    use tonic::{Code, Request};
+
+    const CONFIG: &str = r#"
+        url = "https://localhost:8443"
+
+        [[devices]]
+        mac = "00:00:00:00:00:00"
+        machines = [{ maas_id = "maas_id", port_id = 1 }]
+    "#;
+
+    fn service() -> PowerService {
+        let config = toml::from_str::<Config>(CONFIG).unwrap();
+        PowerService {
+            state: app_state(config),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_require_the_power_role_for_power_actions() {
+        let mut config = toml::from_str::<Config>(CONFIG).unwrap();
+        config.auth = Some(AuthConfig {
+            basic: None,
+            tokens: vec![TokenConfig {
+                token: "dashboard".to_owned(),
+                role: Role::Read,
+            }],
+            jwt: None,
+        });
+        let service = PowerService {
+            state: app_state(config),
+        };
+        let request = |token: Option<&str>| {
+            let mut request = Request::new(MachineRequest {
+                system_id: "maas_id".to_owned(),
+            });
+            if let Some(token) = token {
+                let value = format!("Bearer {token}").parse().unwrap();
+                request.metadata_mut().insert("authorization", value);
+            }
+            request
+        };
+        let error = service.power_off(request(None)).await.unwrap_err();
+        assert_eq!(error.code(), Code::Unauthenticated);
+        let error = service
+            .power_off(request(Some("dashboard")))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::PermissionDenied);
+        service.status(request(Some("dashboard"))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_list_machines() {
+        let reply = service()
+            .list_machines(Request::new(ListMachinesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.machines.len(), 1);
+        assert_eq!(reply.machines[0].system_id, "maas_id");
+        assert_eq!(reply.machines[0].driver, "unifi-poe");
+    }
+
+    #[tokio::test]
+    async fn should_stream_power_events() {
+        let service = service();
+        let mut events = service
+            .stream_events(Request::new(StreamEventsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let request = || {
+            Request::new(MachineRequest {
+                system_id: "maas_id".to_owned(),
+            })
+        };
+        service.power_off(request()).await.unwrap();
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.system_id, "maas_id");
+        assert_eq!(event.action, "power_off");
+        let missing = MachineRequest {
+            system_id: "missing".to_owned(),
+        };
+        let error = service.status(Request::new(missing)).await.unwrap_err();
+        assert_eq!(error.code(), Code::NotFound);
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use reqwest::Client;
+
+use crate::{config::HeartbeatConfig, unifi::handler::UnifiHandler};
+
+/// Pings the heartbeat URL on an interval while the controller answers, so a
+/// dead man's switch pages someone when this service dies or loses the
+/// controller without a word.
+pub fn spawn_heartbeat(handler: UnifiHandler, config: HeartbeatConfig) -> anyhow::Result<()> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()?;
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            ticks.tick().await;
+            beat(&handler, &client, &config.url).await;
+        }
+    });
+    Ok(())
+}
+
+/// Pings `url` if the controller session can list devices. Returns whether
+/// it pinged.
+async fn beat(handler: &UnifiHandler, client: &Client, url: &str) -> bool {
+    if let Err(e) = handler.devices().await {
+        tracing::warn!("skipping heartbeat, the controller is not answering: {e:?}");
+        return false;
+    }
+    let result = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = result {
+        tracing::warn!("failed to send heartbeat to {url}: {e}");
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::beat;
+    use crate::unifi::{handler::UnifiHandler, self_hosted::UnifiSelfHostedClient};
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn should_only_ping_while_the_controller_answers() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ping"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let devices = Mock::given(path("/api/s/default/stat/device"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({"meta": {"rc": "ok"}, "data": []})),
+            )
+            .up_to_n_times(1)
+            .mount_as_scoped(&mock_server)
+            .await;
+        let client = UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let handler = UnifiHandler::new(Box::new(client));
+        let url = format!("{}/ping", mock_server.uri());
+        let http = reqwest::Client::new();
+        assert!(beat(&handler, &http, &url).await);
+        drop(devices);
+        assert!(!beat(&handler, &http, &url).await);
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use tokio::{process::Command, time::timeout};
+
+use crate::notifications::PowerAction;
+
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Details of the power action, passed to hooks as environment variables.
+pub struct HookContext<'a> {
+    pub system_id: &'a str,
+    pub action: PowerAction,
+    /// Backend specific variables, e.g. the switch port.
+    pub backend_env: Vec<(&'static str, String)>,
+}
+
+impl HookContext<'_> {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("MAAS_SYSTEM_ID", self.system_id.to_owned()),
+            ("MAAS_POWER_ACTION", self.action.as_str().to_owned()),
+        ];
+        env.extend(self.backend_env.iter().cloned());
+        env
+    }
+}
+
+/// Runs `command` through `sh -c`, failing if it exits non-zero or runs longer
+/// than `timeout_secs`.
+pub async fn run_hook(
+    command: &str,
+    context: &HookContext<'_>,
+    timeout_secs: Option<u64>,
+) -> anyhow::Result<()> {
+    let limit = timeout_secs.map_or(DEFAULT_HOOK_TIMEOUT, Duration::from_secs);
+    tracing::debug!("running hook `{command}` for {}", context.system_id);
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(context.env())
+        .kill_on_drop(true)
+        .output();
+    let output = timeout(limit, output)
+        .await
+        .map_err(|_| anyhow!("hook `{command}` timed out after {}s", limit.as_secs()))??;
+    if !output.status.success() {
+        bail!(
+            "hook `{command}` failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run_hook, HookContext};
+    use crate::notifications::PowerAction;
+
+    const MAAS_SYSTEM_ID: &str = "system-id";
+
+    fn context() -> HookContext<'static> {
+        HookContext {
+            system_id: MAAS_SYSTEM_ID,
+            action: PowerAction::Off,
+            backend_env: vec![("UNIFI_PORT_ID", "3".to_owned())],
+        }
+    }
+
+    #[tokio::test]
+    async fn should_pass_context_as_env() {
+        let command = r#"test "$MAAS_SYSTEM_ID" = system-id && test "$UNIFI_PORT_ID" = 3 && test "$MAAS_POWER_ACTION" = power_off"#;
+        run_hook(command, &context(), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_error_if_hook_fails() {
+        let result = run_hook("echo drain failed >&2; exit 1", &context(), None).await;
+        assert!(result.unwrap_err().to_string().contains("drain failed"));
+    }
+
+    #[tokio::test]
+    async fn should_error_if_hook_times_out() {
+        let result = run_hook("sleep 5", &context(), Some(0)).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{notifications::PowerAction, shared_state::SharedState};
+
+/// How long a claim outlives an instance that died before releasing it.
+const CLAIM_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A power action that is still running against a machine.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InFlightAction {
+    pub action: PowerAction,
+    pub started_at: String,
+}
+
+/// What a claim stores. The token tells this claim apart from a later one on
+/// the same machine, e.g. after this one outlived its TTL.
+#[derive(Serialize)]
+struct Claim<'a> {
+    token: String,
+    #[serde(flatten)]
+    action: &'a InFlightAction,
+}
+
+/// Tracks the power actions currently running, so a second action on the same
+/// machine is refused instead of interleaving with the first on the switch.
+/// Claims live in the shared state so every instance sees them.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    shared: SharedState,
+}
+
+/// Releases the machine when dropped, unless the claim has since expired and
+/// been taken by another action.
+pub struct InFlightGuard {
+    in_flight: InFlight,
+    system_id: String,
+    claim: String,
+}
+
+fn key(system_id: &str) -> String {
+    format!("in-flight:{system_id}")
+}
+
+impl InFlight {
+    pub fn new(shared: SharedState) -> Self {
+        Self { shared }
+    }
+
+    /// Claims the machine for `action`, or returns the action already running.
+    /// Fails only if the shared state cannot be reached.
+    pub async fn claim(
+        &self,
+        system_id: &str,
+        action: PowerAction,
+    ) -> anyhow::Result<Result<InFlightGuard, InFlightAction>> {
+        let action = InFlightAction {
+            action,
+            started_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        };
+        let claim = serde_json::to_string(&Claim {
+            token: Uuid::new_v4().to_string(),
+            action: &action,
+        })?;
+        let running = self
+            .shared
+            .set_if_absent(&key(system_id), &claim, CLAIM_TTL)
+            .await?;
+        if let Some(running) = running {
+            return Ok(Err(serde_json::from_str(&running)?));
+        }
+        Ok(Ok(InFlightGuard {
+            in_flight: self.clone(),
+            system_id: system_id.to_owned(),
+            claim,
+        }))
+    }
+
+    /// The action running against the machine, if any.
+    pub async fn running(&self, system_id: &str) -> anyhow::Result<Option<InFlightAction>> {
+        match self.shared.get(&key(system_id)).await? {
+            Some(running) => Ok(Some(serde_json::from_str(&running)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight
+            .shared
+            .remove_if(&key(&self.system_id), &self.claim);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{key, Claim, InFlight, InFlightAction};
+    use crate::notifications::PowerAction;
+
+    #[tokio::test]
+    async fn should_refuse_second_action_until_first_finishes() {
+        let in_flight = InFlight::default();
+        let guard = in_flight
+            .claim("a", PowerAction::Off)
+            .await
+            .unwrap()
+            .unwrap();
+        let running = in_flight
+            .claim("a", PowerAction::On)
+            .await
+            .unwrap()
+            .err()
+            .unwrap();
+        assert_eq!(running.action, PowerAction::Off);
+        assert!(in_flight.claim("b", PowerAction::On).await.unwrap().is_ok());
+        let running = in_flight.running("a").await.unwrap().unwrap();
+        assert_eq!(running.action, PowerAction::Off);
+        drop(guard);
+        assert!(in_flight.claim("a", PowerAction::On).await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_not_release_a_claim_taken_after_this_one_expired() {
+        let in_flight = InFlight::default();
+        let guard = in_flight
+            .claim("a", PowerAction::Off)
+            .await
+            .unwrap()
+            .unwrap();
+        // Stands in for the claim expiring and another instance claiming "a".
+        let other = serde_json::to_string(&Claim {
+            token: "other".to_owned(),
+            action: &InFlightAction {
+                action: PowerAction::On,
+                started_at: "now".to_owned(),
+            },
+        })
+        .unwrap();
+        in_flight
+            .shared
+            .set(&key("a"), &other, Duration::from_secs(60))
+            .await
+            .unwrap();
+        drop(guard);
+        let running = in_flight.running("a").await.unwrap().unwrap();
+        assert_eq!(running.action, PowerAction::On);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{notifications::PowerAction, store::Store};
+
+/// How long finished jobs can still be looked up.
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed)
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            JobStatus::Queued,
+            JobStatus::Running,
+            JobStatus::Succeeded,
+            JobStatus::Failed,
+        ]
+        .into_iter()
+        .find(|status| status.as_str() == s)
+        .ok_or_else(|| format!("unknown job status `{s}`"))
+    }
+}
+
+/// A power action accepted with `202 Accepted` and run in the background.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: String,
+    pub system_id: String,
+    pub action: PowerAction,
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// How many times the job has started running, more than 1 if it was
+    /// resumed after a restart.
+    pub attempts: u32,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub fn now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+/// Jobs kept in the store so those accepted before a restart are not lost.
+/// Finished jobs are forgotten after an hour.
+#[derive(Clone)]
+pub struct Jobs {
+    store: Store,
+}
+
+impl Jobs {
+    pub fn new(store: Store) -> Self {
+        Self { store }
+    }
+
+    /// Creates a queued job, or returns the job already created with
+    /// `idempotency_key`.
+    pub async fn create(
+        &self,
+        system_id: &str,
+        action: PowerAction,
+        idempotency_key: Option<String>,
+    ) -> anyhow::Result<Result<Job, Job>> {
+        self.store
+            .prune_jobs(SystemTime::now() - FINISHED_JOB_RETENTION)
+            .await?;
+        let job = Job {
+            id: Uuid::new_v4().to_string(),
+            system_id: system_id.to_owned(),
+            action,
+            status: JobStatus::Queued,
+            error: None,
+            idempotency_key,
+            attempts: 0,
+            created_at: now(),
+            updated_at: now(),
+        };
+        Ok(self.store.insert_job(job.clone()).await?.map(|()| job))
+    }
+
+    /// The job created with `key`, if it has not been forgotten yet.
+    pub async fn by_idempotency_key(&self, key: &str) -> anyhow::Result<Option<Job>> {
+        self.store.job_by_idempotency_key(key).await
+    }
+
+    pub async fn get(&self, id: &str) -> anyhow::Result<Option<Job>> {
+        self.store.job(id).await
+    }
+
+    /// Jobs that were queued or running when the service last stopped.
+    pub async fn unfinished(&self) -> anyhow::Result<Vec<Job>> {
+        self.store.unfinished_jobs().await
+    }
+
+    /// Updates the status of a job, logging rather than failing since the
+    /// action itself has already run.
+    pub async fn update(&self, id: &str, status: JobStatus, error: Option<String>) {
+        if let Err(e) = self.store.update_job(id, status, error).await {
+            tracing::warn!("failed to update job {id}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JobStatus, Jobs};
+    use crate::{notifications::PowerAction, store::Store};
+
+    #[tokio::test]
+    async fn should_track_job_status() {
+        let jobs = Jobs::new(Store::open(None).unwrap());
+        let job = jobs
+            .create("system-id", PowerAction::On, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(job.status, JobStatus::Queued);
+        jobs.update(&job.id, JobStatus::Failed, Some("boom".to_owned()))
+            .await;
+        let job = jobs.get(&job.id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.error.as_deref(), Some("boom"));
+        assert!(jobs.get("unknown").await.unwrap().is_none());
+        assert!(jobs.unfinished().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_find_job_by_idempotency_key() {
+        let jobs = Jobs::new(Store::open(None).unwrap());
+        let job = jobs
+            .create("system-id", PowerAction::On, Some("key".to_owned()))
+            .await
+            .unwrap()
+            .unwrap();
+        let existing = jobs
+            .create("other", PowerAction::Off, Some("key".to_owned()))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(existing.id, job.id);
+        jobs.update(&job.id, JobStatus::Running, None).await;
+        let found = jobs.by_idempotency_key("key").await.unwrap().unwrap();
+        assert_eq!(found.id, job.id);
+        assert_eq!(found.attempts, 1);
+        assert_eq!(jobs.unfinished().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    fs::{self, OpenOptions},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::config::{LeaderBackend, LeaderElectionConfig};
+
+/// Whether this instance may run power actions. Without leader election every
+/// instance is the leader.
+#[derive(Clone)]
+pub struct Leadership {
+    is_leader: Arc<AtomicBool>,
+    lease_ttl: Duration,
+}
+
+impl Default for Leadership {
+    fn default() -> Self {
+        Self {
+            is_leader: Arc::new(AtomicBool::new(true)),
+            lease_ttl: Duration::ZERO,
+        }
+    }
+}
+
+impl Leadership {
+    #[cfg(test)]
+    pub fn standby() -> Self {
+        Self {
+            is_leader: Arc::new(AtomicBool::new(false)),
+            lease_ttl: Duration::from_secs(15),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::Relaxed)
+    }
+
+    /// The longest a standby waits for a dead leader's lease to lapse.
+    pub fn lease_ttl(&self) -> Duration {
+        self.lease_ttl
+    }
+
+    fn set(&self, is_leader: bool) {
+        if self.is_leader.swap(is_leader, Ordering::Relaxed) != is_leader {
+            tracing::info!(
+                "this instance is now {}",
+                if is_leader { "the leader" } else { "a standby" }
+            );
+        }
+    }
+
+    /// Joins the election, returning once the first attempt to take the lease
+    /// has been made, and keeps renewing or retrying in the background.
+    pub async fn elect(config: &LeaderElectionConfig) -> anyhow::Result<Self> {
+        let instance_id = Uuid::new_v4().to_string();
+        let lease: Box<dyn Lease> = match config.backend {
+            LeaderBackend::File => Box::new(FileLease {
+                path: config
+                    .path
+                    .clone()
+                    .ok_or_else(|| anyhow!("leader election with a file needs a `path`"))?,
+                instance_id,
+                ttl: Duration::from_secs(config.ttl_secs),
+            }),
+            LeaderBackend::Etcd => Box::new(EtcdLease::new(
+                config
+                    .url
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("leader election with etcd needs a `url`"))?,
+                config.key.clone(),
+                instance_id,
+                config.ttl_secs,
+            )?),
+        };
+        let leadership = Self {
+            is_leader: Arc::new(AtomicBool::new(false)),
+            lease_ttl: Duration::from_secs(config.ttl_secs),
+        };
+        leadership.set(lease.try_acquire().await.unwrap_or(false));
+        let renew_interval = Duration::from_secs((config.ttl_secs / 3).max(1));
+        let background = leadership.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(renew_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let is_leader = lease.try_acquire().await.unwrap_or_else(|e| {
+                    tracing::warn!("failed to renew leader lease: {e}");
+                    false
+                });
+                background.set(is_leader);
+            }
+        });
+        Ok(leadership)
+    }
+}
+
+/// A lease that at most one instance holds at a time.
+#[async_trait]
+trait Lease: Send + Sync {
+    /// Takes or renews the lease, returning whether this instance holds it.
+    async fn try_acquire(&self) -> anyhow::Result<bool>;
+}
+
+/// A lease file on storage shared by the instances, holding the ID of the
+/// leader and when its lease expires.
+struct FileLease {
+    path: PathBuf,
+    instance_id: String,
+    ttl: Duration,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl FileLease {
+    /// Reads, checks and rewrites the lease while holding an exclusive lock
+    /// on a file next to it, so two instances never both see a free lease
+    /// and take it. The lock goes with the process if it dies.
+    fn acquire_locked(&self) -> anyhow::Result<bool> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))?;
+        lock.lock()?;
+        let current = fs::read_to_string(&self.path).unwrap_or_default();
+        let (holder, expires) = current.trim().split_once(' ').unwrap_or_default();
+        let expired = expires
+            .parse::<u64>()
+            .map_or(true, |expires| expires <= unix_now());
+        if holder != self.instance_id && !expired {
+            return Ok(false);
+        }
+        let lease = format!("{} {}", self.instance_id, unix_now() + self.ttl.as_secs());
+        // Written aside and renamed, so a reader never sees half a lease.
+        let temporary = self
+            .path
+            .with_extension(format!("{}.tmp", self.instance_id));
+        fs::write(&temporary, lease)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Lease for FileLease {
+    async fn try_acquire(&self) -> anyhow::Result<bool> {
+        let lease = FileLease {
+            path: self.path.clone(),
+            instance_id: self.instance_id.clone(),
+            ttl: self.ttl,
+        };
+        tokio::task::spawn_blocking(move || lease.acquire_locked()).await?
+    }
+}
+
+/// An etcd key attached to a lease through etcd's v3 JSON gateway, the key is
+/// deleted by etcd when the leader stops renewing it.
+struct EtcdLease {
+    base_url: Url,
+    key: String,
+    instance_id: String,
+    ttl_secs: u64,
+    client: Client,
+    lease_id: Mutex<Option<String>>,
+}
+
+/// etcd's JSON gateway encodes 64 bit integers as strings.
+#[derive(Deserialize)]
+struct LeaseGrantResponse {
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct LeaseKeepAliveResponse {
+    result: Option<LeaseKeepAliveResult>,
+}
+
+#[derive(Deserialize)]
+struct LeaseKeepAliveResult {
+    #[serde(rename = "TTL", default)]
+    ttl: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RangeResponse {
+    #[serde(default)]
+    kvs: Vec<KeyValue>,
+}
+
+#[derive(Deserialize)]
+struct KeyValue {
+    value: String,
+}
+
+impl EtcdLease {
+    fn new(url: &str, key: String, instance_id: String, ttl_secs: u64) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_url: Url::parse(url)?,
+            key,
+            instance_id,
+            ttl_secs,
+            client: Client::new(),
+            lease_id: Mutex::new(None),
+        })
+    }
+
+    async fn post<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+    ) -> anyhow::Result<T> {
+        Ok(self
+            .client
+            .post(self.base_url.join(path)?)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn holder(&self) -> anyhow::Result<Option<String>> {
+        let response: RangeResponse = self
+            .post("/v3/kv/range", json!({ "key": STANDARD.encode(&self.key) }))
+            .await?;
+        response
+            .kvs
+            .into_iter()
+            .next()
+            .map(|kv| Ok(String::from_utf8(STANDARD.decode(kv.value)?)?))
+            .transpose()
+    }
+}
+
+#[async_trait]
+impl Lease for EtcdLease {
+    async fn try_acquire(&self) -> anyhow::Result<bool> {
+        let mut lease_id = self.lease_id.lock().await;
+        if let Some(id) = lease_id.as_ref() {
+            let response: LeaseKeepAliveResponse = self
+                .post("/v3/lease/keepalive", json!({ "ID": id }))
+                .await?;
+            let alive = response
+                .result
+                .and_then(|result| result.ttl)
+                .is_some_and(|ttl| ttl != "0");
+            if alive {
+                return Ok(self.holder().await?.as_deref() == Some(&self.instance_id));
+            }
+            *lease_id = None;
+        }
+        let grant: LeaseGrantResponse = self
+            .post("/v3/lease/grant", json!({ "TTL": self.ttl_secs }))
+            .await?;
+        let key = STANDARD.encode(&self.key);
+        let _: serde_json::Value = self
+            .post(
+                "/v3/kv/txn",
+                json!({
+                    "compare": [{ "key": key, "target": "CREATE", "create_revision": "0" }],
+                    "success": [{ "request_put": {
+                        "key": key,
+                        "value": STANDARD.encode(&self.instance_id),
+                        "lease": grant.id,
+                    }}],
+                }),
+            )
+            .await?;
+        let is_leader = self.holder().await?.as_deref() == Some(&self.instance_id);
+        // A lease that lost the race is left to expire.
+        if is_leader {
+            *lease_id = Some(grant.id);
+        }
+        Ok(is_leader)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EtcdLease, FileLease, Lease};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::json;
+    use std::time::Duration;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn file_lease(path: &std::path::Path, instance_id: &str, ttl_secs: u64) -> FileLease {
+        FileLease {
+            path: path.to_owned(),
+            instance_id: instance_id.to_owned(),
+            ttl: Duration::from_secs(ttl_secs),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_hold_file_lease_until_it_expires() {
+        let path =
+            std::env::temp_dir().join(format!("maas-power-unifi-leader-{}", std::process::id()));
+        let first = file_lease(&path, "first", 60);
+        let second = file_lease(&path, "second", 60);
+        assert!(first.try_acquire().await.unwrap());
+        assert!(!second.try_acquire().await.unwrap());
+        assert!(first.try_acquire().await.unwrap());
+        tokio::fs::write(&path, "first 0").await.unwrap();
+        assert!(second.try_acquire().await.unwrap());
+        tokio::fs::remove_file(&path).await.unwrap();
+        tokio::fs::remove_file(path.with_extension("lock"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_give_a_free_file_lease_to_one_instance() {
+        let path = std::env::temp_dir().join(format!(
+            "maas-power-unifi-leader-race-{}",
+            std::process::id()
+        ));
+        let leases: Vec<_> = (0..8)
+            .map(|instance| file_lease(&path, &instance.to_string(), 60))
+            .collect();
+        let taken = futures::future::join_all(leases.iter().map(|lease| lease.try_acquire())).await;
+        let leaders = taken.into_iter().filter(|taken| *taken.as_ref().unwrap());
+        assert_eq!(leaders.count(), 1);
+        tokio::fs::remove_file(&path).await.unwrap();
+        tokio::fs::remove_file(path.with_extension("lock"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_take_etcd_lease_if_key_is_free() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v3/lease/grant"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ID": "7", "TTL": "15"})))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v3/kv/txn"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"succeeded": true})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v3/kv/range"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "kvs": [{ "value": STANDARD.encode("instance") }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let lease = EtcdLease::new(
+            &mock_server.uri(),
+            "leader".to_owned(),
+            "instance".to_owned(),
+            15,
+        )
+        .unwrap();
+        assert!(lease.try_acquire().await.unwrap());
+    }
+}
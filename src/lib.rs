@@ -0,0 +1,5 @@
+//! Library parts of maas-power-unifi that are useful outside the service.
+
+/// A typed client for the service's HTTP API.
+#[cfg(feature = "client")]
+pub mod client;
@@ -0,0 +1,158 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::config::{LogFileConfig, Rotation};
+
+/// A log file that is rotated when it grows too large or a new hour or day
+/// starts. Rotated files are renamed `<path>.1`, `<path>.2` and so on, oldest
+/// last, and only `retain` of them are kept.
+#[derive(Clone)]
+pub struct RotatingFile {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    period: u64,
+    rotation: Rotation,
+    max_bytes: Option<u64>,
+    retain: usize,
+}
+
+impl RotatingFile {
+    pub fn open(config: &LogFileConfig) -> io::Result<Self> {
+        let max_bytes = config.max_size_mb.map(|mb| mb * 1024 * 1024);
+        Self::open_with(&config.path, config.rotation, max_bytes, config.retain)
+    }
+
+    fn open_with(
+        path: &Path,
+        rotation: Rotation,
+        max_bytes: Option<u64>,
+        retain: usize,
+    ) -> io::Result<Self> {
+        let file = append(path)?;
+        let size = file.metadata()?.len();
+        let state = State {
+            path: path.to_owned(),
+            file,
+            size,
+            period: period(rotation),
+            rotation,
+            max_bytes,
+            retain,
+        };
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// The hour or day since the epoch, in UTC, a file is rotated when it changes.
+fn period(rotation: Rotation) -> u64 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match rotation {
+        Rotation::Never => 0,
+        Rotation::Hourly => secs / 3600,
+        Rotation::Daily => secs / 86400,
+    }
+}
+
+impl State {
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{n}", self.path.display()));
+        if self.retain == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.retain));
+            for n in (1..self.retain).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let period = period(state.rotation);
+        let full = state
+            .max_bytes
+            .is_some_and(|max| state.size > 0 && state.size + buf.len() as u64 > max);
+        if period != state.period || full {
+            state.rotate()?;
+            state.period = period;
+        }
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .file
+            .flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RotatingFile;
+    use crate::config::Rotation;
+    use std::{fs, io::Write};
+
+    #[test]
+    fn should_rotate_by_size_and_keep_only_retained_files() {
+        let dir = std::env::temp_dir().join(format!("maas-power-unifi-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bridge.log");
+        let mut file = RotatingFile::open_with(&path, Rotation::Never, Some(10), 2).unwrap();
+        for line in [
+            "first line\n",
+            "second line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("bridge.log"), "fourth line\n");
+        assert_eq!(read("bridge.log.1"), "third line\n");
+        assert_eq!(read("bridge.log.2"), "second line\n");
+        assert!(!dir.join("bridge.log.3").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
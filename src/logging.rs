@@ -0,0 +1,193 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use tracing_subscriber::{
+    filter::{filter_fn, FilterExt, LevelFilter, Targets},
+    fmt,
+    layer::Layer,
+    reload, Registry,
+};
+
+use crate::{
+    config::{LogSink, LogStreamConfig, LoggingConfig},
+    log_file::RotatingFile,
+    syslog::{SyslogLog, SyslogSink},
+};
+
+/// The filter logging starts with.
+pub const DEFAULT_FILTER: &str = "maas_power_unifi=debug";
+
+/// The target of the access log, a line per HTTP request.
+pub const ACCESS_TARGET: &str = "access";
+
+/// The target of the audit log, a line per power action.
+pub const AUDIT_TARGET: &str = "audit";
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The log output set up in `[logging]`, each stream written to its sinks
+/// with its own level. Application logs are everything but the access and
+/// audit logs, their filter is returned to be changed while running.
+pub fn layers(config: &LoggingConfig) -> anyhow::Result<(BoxedLayer, LogFilter)> {
+    let file = match &config.file {
+        Some(file) => Some(
+            RotatingFile::open(file)
+                .with_context(|| format!("failed to open {}", file.path.display()))?,
+        ),
+        None => None,
+    };
+    let syslog = match &config.syslog {
+        Some(syslog) => Some(SyslogLog::spawn(SyslogSink::new(syslog)?)),
+        None => None,
+    };
+    let sinks = |stream: &LogStreamConfig| -> Vec<BoxedLayer> {
+        config
+            .sinks(stream)
+            .into_iter()
+            .filter_map(|sink| match sink {
+                LogSink::Stdout => Some(fmt::layer().boxed()),
+                LogSink::File => file
+                    .clone()
+                    .map(|file| fmt::layer().with_writer(file).with_ansi(false).boxed()),
+                // Syslog stamps its own time.
+                LogSink::Syslog => syslog.clone().map(|syslog| {
+                    fmt::layer()
+                        .with_writer(syslog)
+                        .with_ansi(false)
+                        .without_time()
+                        .boxed()
+                }),
+            })
+            .collect()
+    };
+    let level = |stream: &LogStreamConfig| {
+        stream
+            .level
+            .as_deref()
+            .map_or(Ok(LevelFilter::INFO), str::parse)
+    };
+    let directives = config.app.level.as_deref().unwrap_or(DEFAULT_FILTER);
+    let (filter, handle) = reload::Layer::new(directives.parse::<Targets>()?);
+    let app = sinks(&config.app).with_filter(filter.and(filter_fn(|meta| {
+        ![ACCESS_TARGET, AUDIT_TARGET].contains(&meta.target())
+    })));
+    let access = sinks(&config.access)
+        .with_filter(Targets::new().with_target(ACCESS_TARGET, level(&config.access)?));
+    let audit = sinks(&config.audit)
+        .with_filter(Targets::new().with_target(AUDIT_TARGET, level(&config.audit)?));
+    let layers = vec![app.boxed(), access.boxed(), audit.boxed()];
+    Ok((layers.boxed(), LogFilter::new(handle, directives)))
+}
+
+/// The log filter, which can be changed while running, e.g. to turn on
+/// `reqwest=trace` during an incident.
+#[derive(Clone, Default)]
+pub struct LogFilter {
+    handle: Option<reload::Handle<Targets, Registry>>,
+    directives: Arc<Mutex<String>>,
+}
+
+impl LogFilter {
+    pub fn new(handle: reload::Handle<Targets, Registry>, directives: &str) -> Self {
+        Self {
+            handle: Some(handle),
+            directives: Arc::new(Mutex::new(directives.to_owned())),
+        }
+    }
+
+    /// The directives in use, e.g. `maas_power_unifi=debug,reqwest=trace`.
+    pub fn current(&self) -> String {
+        self.directives
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let targets: Targets = directives.parse()?;
+        if let Some(handle) = &self.handle {
+            handle.reload(targets)?;
+        }
+        *self.directives.lock().unwrap_or_else(|e| e.into_inner()) = directives.to_owned();
+        Ok(())
+    }
+
+    /// Sets the filter back to `previous` after `delay`, unless it has been
+    /// changed again in the meantime.
+    pub fn reset_after(&self, delay: Duration, previous: String) {
+        let filter = self.clone();
+        let directives = self.current();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if filter.current() != directives {
+                return;
+            }
+            match filter.set(&previous) {
+                Ok(()) => tracing::info!("log filter reset to `{previous}`"),
+                Err(e) => tracing::warn!("failed to reset log filter to `{previous}`: {e}"),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{layers, LogFilter, ACCESS_TARGET, AUDIT_TARGET, DEFAULT_FILTER};
+    use crate::config::{LogFileConfig, LogSink, LogStreamConfig, LoggingConfig, Rotation};
+    use std::{fs, time::Duration};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test]
+    async fn should_reset_filter_after_delay() {
+        let filter = LogFilter::default();
+        filter.set(DEFAULT_FILTER).unwrap();
+        assert!(filter.set("maas_power_unifi=loud").is_err());
+        filter.set("maas_power_unifi=trace,reqwest=debug").unwrap();
+        filter.reset_after(Duration::from_millis(10), DEFAULT_FILTER.to_owned());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(filter.current(), DEFAULT_FILTER);
+    }
+
+    #[test]
+    fn should_write_each_stream_to_its_sinks_at_its_level() {
+        let path = std::env::temp_dir().join(format!(
+            "maas-power-unifi-streams-{}.log",
+            std::process::id()
+        ));
+        let file_only = |level: &str| LogStreamConfig {
+            sinks: Some(vec![LogSink::File]),
+            level: Some(level.to_owned()),
+        };
+        let config = LoggingConfig {
+            file: Some(LogFileConfig {
+                path: path.clone(),
+                rotation: Rotation::Never,
+                max_size_mb: None,
+                retain: 1,
+            }),
+            app: file_only("maas_power_unifi=info"),
+            access: file_only("warn"),
+            audit: file_only("info"),
+            ..Default::default()
+        };
+        let (output, filter) = layers(&config).unwrap();
+        assert_eq!(filter.current(), "maas_power_unifi=info");
+        let subscriber = tracing_subscriber::registry().with(output);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("application line");
+            tracing::debug!("application detail");
+            tracing::info!(target: ACCESS_TARGET, "GET /machines 200");
+            tracing::info!(target: AUDIT_TARGET, "power_on of abc123 succeeded");
+        });
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(log.contains("application line"), "{log}");
+        assert!(log.contains("power_on of abc123 succeeded"), "{log}");
+        assert!(!log.contains("application detail"), "{log}");
+        assert!(!log.contains("GET /machines"), "{log}");
+        assert_eq!(log.lines().count(), 2, "{log}");
+    }
+}
@@ -1,40 +1,292 @@
 mod args;
+mod assets;
+mod auth;
+mod backend;
+mod backup;
+mod chat;
 pub mod config;
+mod device_metrics;
+mod email;
+mod etag;
+mod example_config;
+mod exit;
+mod flap_guard;
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod heartbeat;
+mod hooks;
+mod in_flight;
+mod jobs;
+mod leader;
+mod log_file;
+mod logging;
+mod mapping_source;
+pub mod metrics;
+mod migrate;
+mod notifications;
+mod port_scan;
+mod power_address;
+mod power_history;
+mod rate_limit;
 mod router;
+mod runtime_metrics;
+mod server;
+mod sessions;
+mod shared_state;
+mod snapshot;
+mod stats;
+mod store;
+mod syslog;
 pub mod unifi;
+mod validation;
+mod watchdog;
 
-use args::Args;
-use clap::Parser;
-use config::read_config_file;
-use reqwest::Client;
-use router::{routes, AppState};
-use tracing::Level;
-use tracing_subscriber::{filter, prelude::*};
-use unifi::{client::UnifiClient, handler::UnifiHandler, self_hosted::UnifiSelfHostedClient};
+use anyhow::{anyhow, Context};
+use arc_swap::ArcSwap;
+use args::{Args, Command};
+use auth::Authenticator;
+use backend::BackendRegistry;
+use clap::{CommandFactory, Parser};
+use config::{config_from_env, read_config_dir, read_config_file, FlapViolation};
+use device_metrics::spawn_device_sampler;
+use exit::Failure;
+use flap_guard::FlapGuard;
+use heartbeat::spawn_heartbeat;
+use in_flight::InFlight;
+use jobs::Jobs;
+use leader::Leadership;
+use mapping_source::MappingSource;
+use metrics::{Metrics, StatsdSink};
+use migrate::migrate_config_file;
+use notifications::Notifier;
+use power_history::spawn_sampler;
+use rate_limit::RateLimiter;
+use router::{resume_jobs, routes, AppState, UnknownNics};
+use runtime_metrics::spawn_runtime_sampler;
+use sessions::Sessions;
+use shared_state::SharedState;
+use std::{process::ExitCode, sync::Arc, time::Duration};
+use store::Store;
+use tracing_subscriber::prelude::*;
+use unifi::{
+    client::UnifiClient, failover, handler::UnifiHandler, mock::MockUnifiClient,
+    self_hosted::DEFAULT_SITE,
+};
+use validation::{reconcile, Reconciled};
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let filter = filter::Targets::new().with_target("maas_power_unifi", Level::DEBUG);
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(filter)
-        .init();
-    let args = Args::parse();
-    let config = &*Box::leak(Box::new(read_config_file(args.config_file).await?));
-    let http_client = Client::builder()
-        .cookie_store(true)
-        .danger_accept_invalid_certs(true)
-        .build()?;
-    let client = Box::new(UnifiSelfHostedClient::new(&config.url, http_client)?);
-    let username = std::env::var("UNIFI_USERNAME").unwrap();
-    let password = std::env::var("UNIFI_PASSWORD").unwrap();
-    client.login(&username, &password).await?;
-    let handler = UnifiHandler { client };
-    let state = AppState { config, handler };
-    let app = routes(state);
-    axum::Server::bind(&"0.0.0.0:3000".parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-    Ok(())
+async fn main() -> ExitCode {
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(Failure::exit_code(&e))
+        }
+    }
+}
+
+async fn run(args: Args) -> anyhow::Result<()> {
+    if let Some(Command::Completions { shell }) = args.command {
+        let mut command = Args::command();
+        let name = command.get_name().to_owned();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        return Ok(());
+    }
+    if let Some(Command::MigrateConfig) = args.command {
+        let config_file = args
+            .config_file
+            .context("`migrate-config` needs a `--config-file`")
+            .context(Failure::Config)?;
+        let changes = migrate_config_file(&config_file)
+            .await
+            .context(Failure::Config)?;
+        if changes.is_empty() {
+            println!("{} is already current", config_file.display());
+        }
+        for change in changes {
+            println!("{change}");
+        }
+        return Ok(());
+    }
+    if let Some(Command::PrintExampleConfig) = args.command {
+        print!("{}", example_config::example_config());
+        return Ok(());
+    }
+    let mut config = match (&args.config_file, &args.config_dir) {
+        (Some(config_file), _) => read_config_file(config_file.clone()).await,
+        (None, Some(config_dir)) => read_config_dir(config_dir.clone()).await,
+        (None, None) => config_from_env(),
+    }
+    .context(Failure::Config)?;
+    let (output, log_filter) = logging::layers(&config.logging).context(Failure::Config)?;
+    // The filters only apply to the log output, the console needs every span.
+    let registry = tracing_subscriber::registry().with(output);
+    #[cfg(feature = "tokio-console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
+    let mapping_source = match config.mapping_source.clone() {
+        Some(source) => {
+            let source = MappingSource::new(source).context(Failure::Config)?;
+            let (index, mapping) = source.fetch(None).await.context(Failure::Config)?;
+            mapping.apply_to(&mut config);
+            config.validate().context(Failure::Config)?;
+            Some((source, index))
+        }
+        None => None,
+    };
+    let machines = config
+        .devices
+        .iter()
+        .map(|device| device.machines.len())
+        .sum::<usize>()
+        + config.machines.len();
+    tracing::info!(
+        controller = %config.redacted_url(),
+        site = config.controller.site.as_deref().unwrap_or(DEFAULT_SITE),
+        listen = ?config.server.listen,
+        devices = config.devices.len(),
+        machines,
+        features = ?config.features(),
+        "starting {} {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    );
+    let credential = |name| match std::env::var(name) {
+        Err(_) if args.mock => Ok(String::new()),
+        value => value
+            .with_context(|| format!("`{name}` must be set"))
+            .context(Failure::Config),
+    };
+    let username = credential("UNIFI_USERNAME")?;
+    let password = credential("UNIFI_PASSWORD")?;
+    // Least-privilege mode cannot list the sites, so `site` is used as it is.
+    let resolve_site = !args.mock && !config.controller.least_privilege;
+    if let Some(name) = config.controller.site.clone().filter(|_| resolve_site) {
+        // Sites are listed the same for any site, so any client will do.
+        let client = failover::controller_client(&config.url, &config.controller)
+            .context(Failure::Config)?;
+        let handler = UnifiHandler::new(client).with_retry(config.controller.retry);
+        if let Err(e) = handler.login(&username, &password).await {
+            let failure = Failure::of_login(&e);
+            return Err(e.context(failure));
+        }
+        let site = handler.resolve_site(&name).await.context(Failure::Config)?;
+        tracing::info!("managing the site `{name}` as `{site}`");
+        config.controller.site = Some(site);
+    }
+    let config = Arc::new(config);
+    let client: Box<dyn UnifiClient + Send + Sync> = if args.mock {
+        tracing::warn!("using a mock controller, no real ports are powered");
+        Box::new(MockUnifiClient::new(&config))
+    } else {
+        failover::controller_client(&config.url, &config.controller).context(Failure::Config)?
+    };
+    let mut sessions = Sessions::new(&config.url, &config.controller);
+    if args.mock {
+        // Credentials sent with a request reach the same mock.
+        sessions = sessions.with_client(client.clone());
+    }
+    let handler = UnifiHandler::new(client).with_retry(config.controller.retry);
+    if let Err(e) = handler.login(&username, &password).await {
+        let failure = Failure::of_login(&e);
+        return Err(e.context(failure));
+    }
+    if let Some(secs) = config.controller.keep_warm_secs {
+        handler.keep_warm(Duration::from_secs(secs));
+    }
+    if let Some(Command::PortScan) = args.command {
+        return port_scan::port_scan(&config, &handler).await;
+    }
+    handler.check_permissions().await;
+    let problems = reconcile(&config, &handler).await;
+    for problem in &problems {
+        tracing::warn!("{problem}");
+    }
+    if config.strict_mapping && !problems.is_empty() {
+        return Err(
+            anyhow!("{} problem(s) with the mapping", problems.len()).context(Failure::Mapping)
+        );
+    }
+    let statsd = config
+        .metrics
+        .statsd
+        .as_ref()
+        .map(StatsdSink::connect)
+        .transpose()?;
+    let metrics = Metrics::new(statsd, config.metrics.drop_labels.clone());
+    if config.metrics.statsd.is_some() {
+        let interval = Duration::from_secs(config.metrics.runtime_interval_secs);
+        spawn_runtime_sampler(metrics.clone(), interval);
+        let devices = config.devices.iter().map(|device| device.mac).collect();
+        let interval = Duration::from_secs(config.metrics.device_interval_secs);
+        spawn_device_sampler(handler.clone(), devices, metrics.clone(), interval);
+    }
+    if let Some(heartbeat) = config.heartbeat.clone() {
+        spawn_heartbeat(handler.clone(), heartbeat)?;
+    }
+    let notifier = Notifier::new(&config.notifications)?;
+    let backends = BackendRegistry::new(&config, handler.clone())?;
+    let shared_config = Arc::new(ArcSwap::new(config.clone()));
+    if let Some((source, index)) = mapping_source {
+        source.spawn_watcher(
+            index,
+            shared_config.clone(),
+            handler.clone(),
+            backends.clone(),
+        );
+    }
+    let store = Store::open(config.storage.path.as_deref())?;
+    if let Some(power_history) = config.power_history {
+        spawn_sampler(backends.clone(), store.clone(), power_history);
+    }
+    let leadership = match &config.leader_election {
+        Some(election) => Leadership::elect(election).await?,
+        None => Leadership::default(),
+    };
+    let shared = match &config.redis {
+        Some(redis) => SharedState::connect(redis).await?,
+        None => SharedState::default(),
+    };
+    let state = AppState {
+        config: shared_config,
+        backends,
+        metrics,
+        notifier,
+        controller: handler,
+        config_file: args.config_file,
+        in_flight: InFlight::new(shared.clone()),
+        rate_limiter: RateLimiter::new(shared.clone(), config.rate_limit.clone()),
+        flap_guard: FlapGuard::new(
+            shared,
+            config
+                .anti_flap
+                .as_ref()
+                .map_or(FlapViolation::Reject, |anti_flap| anti_flap.on_violation),
+        ),
+        jobs: Jobs::new(store.clone()),
+        store,
+        leadership,
+        log_filter,
+        sessions,
+        authenticator: Authenticator::new(config.auth.as_ref()).context(Failure::Config)?,
+        unknown_nics: UnknownNics::default(),
+        reconciled: Reconciled::default(),
+    };
+    if state.leadership.is_leader() {
+        resume_jobs(&state).await?;
+    }
+    #[cfg(unix)]
+    snapshot::dump_on_sigusr1(state.clone())?;
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = &config.grpc {
+        let state = state.clone();
+        let listen = grpc.listen;
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(listen, state).await {
+                tracing::error!("gRPC server failed: {e:#}");
+            }
+        });
+    }
+    server::serve(&config.server, routes(state)).await
 }
@@ -0,0 +1,306 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use arc_swap::ArcSwap;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    backend::BackendRegistry,
+    config::{Config, Device, Machine, MappingBackend, MappingSourceConfig},
+    unifi::handler::UnifiHandler,
+};
+
+const CONSUL_INDEX_HEADER: &str = "x-consul-index";
+const CONSUL_TOKEN_HEADER: &str = "x-consul-token";
+/// How long a Consul blocking query waits for a change before returning.
+const CONSUL_WAIT: &str = "5m";
+
+/// The devices and machines of a bridge, as stored under the source's key.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    #[serde(default)]
+    pub devices: Vec<Device>,
+    #[serde(default)]
+    pub machines: Vec<Machine>,
+}
+
+impl Mapping {
+    pub fn parse(mapping_toml: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(mapping_toml)?)
+    }
+
+    /// Replaces the devices and machines of `config`.
+    pub fn apply_to(self, config: &mut Config) {
+        config.devices = self.devices;
+        config.machines = self.machines;
+    }
+}
+
+#[derive(Deserialize)]
+struct EtcdRangeResponse {
+    #[serde(default)]
+    kvs: Vec<EtcdKeyValue>,
+}
+
+/// etcd's JSON gateway encodes keys and values in base64 and 64 bit integers
+/// as strings.
+#[derive(Deserialize)]
+struct EtcdKeyValue {
+    value: String,
+    mod_revision: String,
+}
+
+/// Reads the mapping from Consul's KV store or etcd's v3 JSON gateway.
+pub struct MappingSource {
+    config: MappingSourceConfig,
+    base_url: Url,
+    client: Client,
+}
+
+impl MappingSource {
+    pub fn new(config: MappingSourceConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_url: Url::parse(&config.url)?,
+            config,
+            client: Client::new(),
+        })
+    }
+
+    /// Fetches the mapping along with the Consul index or etcd revision it was
+    /// read at. With a Consul `index` the request blocks until the key changes
+    /// or the wait times out.
+    pub async fn fetch(&self, index: Option<u64>) -> anyhow::Result<(u64, Mapping)> {
+        let (index, mapping) = match self.config.backend {
+            MappingBackend::Consul => self.fetch_consul(index).await,
+            MappingBackend::Etcd => self.fetch_etcd().await,
+        }
+        .with_context(|| format!("failed to fetch mapping `{}`", self.config.key))?;
+        let mapping = Mapping::parse(&mapping)
+            .with_context(|| format!("invalid mapping in `{}`", self.config.key))?;
+        Ok((index, mapping))
+    }
+
+    async fn fetch_consul(&self, index: Option<u64>) -> anyhow::Result<(u64, String)> {
+        let mut url = self.base_url.join(&format!("/v1/kv/{}", self.config.key))?;
+        url.query_pairs_mut().append_key_only("raw");
+        if let Some(index) = index {
+            url.query_pairs_mut()
+                .append_pair("index", &index.to_string())
+                .append_pair("wait", CONSUL_WAIT);
+        }
+        let mut request = self.client.get(url);
+        if let Some(token) = &self.config.token {
+            request = request.header(CONSUL_TOKEN_HEADER, token);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let index = response
+            .headers()
+            .get(CONSUL_INDEX_HEADER)
+            .and_then(|index| index.to_str().ok())
+            .and_then(|index| index.parse().ok())
+            .ok_or_else(|| anyhow!("Consul response had no {CONSUL_INDEX_HEADER} header"))?;
+        Ok((index, response.text().await?))
+    }
+
+    async fn fetch_etcd(&self) -> anyhow::Result<(u64, String)> {
+        let url = self.base_url.join("/v3/kv/range")?;
+        let response: EtcdRangeResponse = self
+            .client
+            .post(url)
+            .json(&json!({ "key": STANDARD.encode(&self.config.key) }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let kv = response
+            .kvs
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("key does not exist"))?;
+        let value = String::from_utf8(STANDARD.decode(kv.value)?)?;
+        Ok((kv.mod_revision.parse()?, value))
+    }
+
+    /// Watches the mapping and swaps the devices and machines of `config`, and
+    /// the machines served by `backends`, when it changes. Everything else in
+    /// the config is kept. An invalid mapping is logged and the current one
+    /// kept.
+    pub fn spawn_watcher(
+        self,
+        mut index: u64,
+        config: Arc<ArcSwap<Config>>,
+        handler: UnifiHandler,
+        backends: BackendRegistry,
+    ) {
+        let poll_interval = Duration::from_secs(self.config.poll_interval_secs);
+        tokio::spawn(async move {
+            loop {
+                if self.config.backend == MappingBackend::Etcd {
+                    tokio::time::sleep(poll_interval).await;
+                }
+                let (next, mapping) = match self.fetch(Some(index)).await {
+                    Ok(fetched) => fetched,
+                    Err(e) => {
+                        tracing::warn!("{e:#}");
+                        tokio::time::sleep(poll_interval).await;
+                        continue;
+                    }
+                };
+                if next == index {
+                    continue;
+                }
+                index = next;
+                let mut reloaded = Config::clone(&config.load());
+                mapping.apply_to(&mut reloaded);
+                let registry = reloaded
+                    .validate()
+                    .and_then(|_| BackendRegistry::new(&reloaded, handler.clone()));
+                match registry {
+                    Ok(registry) => {
+                        backends.replace(registry);
+                        config.store(Arc::new(reloaded));
+                        tracing::info!("loaded mapping `{}` at {index}", self.config.key);
+                    }
+                    Err(e) => tracing::warn!(
+                        "ignoring invalid mapping `{}` at {index}: {e:#}",
+                        self.config.key
+                    ),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use super::MappingSource;
+    use crate::{
+        backend::BackendRegistry,
+        config::{Config, MappingBackend, MappingSourceConfig},
+        unifi::{handler::UnifiHandler, mock::MockUnifiClient},
+    };
+    use arc_swap::ArcSwap;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_json, header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const KEY: &str = "maas/rack1";
+    const MAPPING: &str = r#"
+        [[devices]]
+        mac = "00:00:00:00:00:00"
+        machines = [{ maas_id = "maas_id", port_id = 2 }]
+    "#;
+
+    fn source(backend: MappingBackend, mock_server: &MockServer) -> MappingSource {
+        MappingSource::new(MappingSourceConfig {
+            backend,
+            url: mock_server.uri(),
+            key: KEY.to_owned(),
+            token: Some("token".to_owned()),
+            poll_interval_secs: 1,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_fetch_mapping_from_consul() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/kv/{KEY}")))
+            .and(query_param("index", "7"))
+            .and(header("x-consul-token", "token"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("x-consul-index", "8")
+                    .set_body_string(MAPPING),
+            )
+            .mount(&mock_server)
+            .await;
+        let (index, mapping) = source(MappingBackend::Consul, &mock_server)
+            .fetch(Some(7))
+            .await
+            .unwrap();
+        assert_eq!(index, 8);
+        assert_eq!(mapping.devices[0].machines[0].port_id, 2);
+    }
+
+    #[tokio::test]
+    async fn should_fetch_mapping_from_etcd() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v3/kv/range"))
+            .and(body_json(json!({ "key": STANDARD.encode(KEY) })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "kvs": [{ "value": STANDARD.encode(MAPPING), "mod_revision": "42" }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let (revision, mapping) = source(MappingBackend::Etcd, &mock_server)
+            .fetch(None)
+            .await
+            .unwrap();
+        assert_eq!(revision, 42);
+        assert_eq!(mapping.devices.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_error_if_etcd_key_is_missing() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v3/kv/range"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&mock_server)
+            .await;
+        let result = source(MappingBackend::Etcd, &mock_server).fetch(None).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_swap_only_the_mapping_of_the_running_config() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!("/v1/kv/{KEY}")))
+            .and(query_param("index", "7"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("x-consul-index", "8")
+                    .set_body_string(MAPPING),
+            )
+            .mount(&mock_server)
+            .await;
+        let running = Config {
+            url: "https://unifi.example".to_owned(),
+            power_on_stagger_ms: 500,
+            ..Default::default()
+        };
+        let handler = UnifiHandler::new(Box::new(MockUnifiClient::new(&running)));
+        let backends = BackendRegistry::new(&running, handler.clone()).unwrap();
+        let config = Arc::new(ArcSwap::from_pointee(running));
+        source(MappingBackend::Consul, &mock_server).spawn_watcher(
+            7,
+            config.clone(),
+            handler,
+            backends.clone(),
+        );
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while config.load().devices.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(config.load().power_on_stagger_ms, 500);
+        assert!(backends.resolve("maas_id").is_some());
+    }
+}
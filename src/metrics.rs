@@ -0,0 +1,223 @@
+use std::{net::UdpSocket, sync::Arc, time::Duration};
+
+use crate::config::{StatsdConfig, StatsdFlavor};
+
+/// A metric name together with its labels, e.g. `http_requests{route="/power-on"}`.
+#[derive(Debug)]
+pub struct MetricKey {
+    pub name: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+}
+
+impl MetricKey {
+    pub fn new(name: &'static str, labels: &[(&'static str, &str)]) -> Self {
+        Self {
+            name,
+            labels: labels
+                .iter()
+                .map(|(key, value)| (*key, value.to_string()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    statsd: Option<StatsdSink>,
+    /// Labels left off every metric, e.g. `system_id` on a large fleet.
+    dropped_labels: Vec<String>,
+}
+
+/// Counters, timings and gauges recorded while serving requests. They are
+/// sent to the StatsD sink as they are recorded, and dropped without one.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+impl Metrics {
+    pub fn new(statsd: Option<StatsdSink>, dropped_labels: Vec<String>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                statsd,
+                dropped_labels,
+            }),
+        }
+    }
+
+    fn send(&self, name: &'static str, labels: &[(&'static str, &str)], value: &str) {
+        let Some(statsd) = &self.inner.statsd else {
+            return;
+        };
+        let mut key = MetricKey::new(name, labels);
+        key.labels.retain(|(label, _)| {
+            !self
+                .inner
+                .dropped_labels
+                .iter()
+                .any(|dropped| dropped == label)
+        });
+        statsd.send(&key, value);
+    }
+
+    pub fn increment(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        self.send(name, labels, "1|c");
+    }
+
+    pub fn timing(&self, name: &'static str, labels: &[(&'static str, &str)], elapsed: Duration) {
+        self.send(name, labels, &format!("{}|ms", elapsed.as_millis()));
+    }
+
+    pub fn gauge(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        self.send(name, labels, &format!("{value}|g"));
+    }
+}
+
+/// Sends metrics over UDP in either plain StatsD or DogStatsD format.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    flavor: StatsdFlavor,
+}
+
+impl StatsdSink {
+    pub fn connect(config: &StatsdConfig) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect((config.host.as_str(), config.port))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: config.prefix.clone(),
+            flavor: config.flavor,
+        })
+    }
+
+    fn send(&self, key: &MetricKey, value: &str) {
+        let line = self.format(key, value);
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            tracing::debug!("failed to send metric to statsd: {e}");
+        }
+    }
+
+    fn format(&self, key: &MetricKey, value: &str) -> String {
+        let name = if self.prefix.is_empty() {
+            key.name.to_owned()
+        } else {
+            format!("{}.{}", self.prefix, key.name)
+        };
+        match self.flavor {
+            StatsdFlavor::Statsd => {
+                // Plain statsd has no tags, so fold the label values into the name.
+                let name = key.labels.iter().fold(name, |name, (_, value)| {
+                    format!("{name}.{}", sanitize(value))
+                });
+                format!("{name}:{value}")
+            }
+            StatsdFlavor::Dogstatsd if key.labels.is_empty() => format!("{name}:{value}"),
+            StatsdFlavor::Dogstatsd => {
+                let tags = key
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{key}:{value}"))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!("{name}:{value}|#{tags}")
+            }
+        }
+    }
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .trim_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// A sink listening on a local socket, for tests to read what is sent.
+#[cfg(test)]
+pub(crate) mod test_sink {
+    use super::{Metrics, StatsdSink};
+    use crate::config::{StatsdConfig, StatsdFlavor};
+    use std::net::UdpSocket;
+
+    /// Metrics sent with the `maas` prefix to the returned socket.
+    pub(crate) fn statsd(flavor: StatsdFlavor, dropped_labels: &[&str]) -> (UdpSocket, Metrics) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_nonblocking(true).unwrap();
+        let config = StatsdConfig {
+            host: "127.0.0.1".to_owned(),
+            port: server.local_addr().unwrap().port(),
+            prefix: "maas".to_owned(),
+            flavor,
+        };
+        let dropped_labels = dropped_labels.iter().map(|label| label.to_string());
+        let metrics = Metrics::new(
+            Some(StatsdSink::connect(&config).unwrap()),
+            dropped_labels.collect(),
+        );
+        (server, metrics)
+    }
+
+    /// Every line sent so far.
+    pub(crate) fn received(server: &UdpSocket) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut buf = [0; 512];
+        while let Ok(len) = server.recv(&mut buf) {
+            lines.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        test_sink::{received, statsd},
+        Metrics,
+    };
+    use crate::config::StatsdFlavor;
+    use std::time::Duration;
+
+    #[test]
+    fn should_send_nothing_without_a_sink() {
+        let metrics = Metrics::default();
+        metrics.increment("requests", &[("route", "/power-on")]);
+    }
+
+    #[test]
+    fn should_leave_off_dropped_labels() {
+        let (server, metrics) = statsd(StatsdFlavor::Dogstatsd, &["system_id"]);
+        metrics.increment("power_on_without_draw", &[("system_id", "abc123")]);
+        metrics.increment("power_on_without_draw", &[("system_id", "def456")]);
+        assert_eq!(
+            received(&server),
+            [
+                "maas.power_on_without_draw:1|c",
+                "maas.power_on_without_draw:1|c"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_send_statsd_counter() {
+        let (server, metrics) = statsd(StatsdFlavor::Statsd, &[]);
+        metrics.increment("requests", &[("route", "/power-on")]);
+        assert_eq!(received(&server), ["maas.requests.power_on:1|c"]);
+    }
+
+    #[test]
+    fn should_send_dogstatsd_timing_with_tags() {
+        let (server, metrics) = statsd(StatsdFlavor::Dogstatsd, &[]);
+        metrics.timing(
+            "request_duration",
+            &[("route", "/power-on")],
+            Duration::from_millis(12),
+        );
+        assert_eq!(
+            received(&server),
+            ["maas.request_duration:12|ms|#route:/power-on"]
+        );
+    }
+}
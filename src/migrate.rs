@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use toml_edit::{value, Document};
+
+use crate::config::{parse_config, SCHEMA_VERSION};
+
+/// A change to the config layout, upgrading a config at version `from` to
+/// the next one.
+struct Migration {
+    from: u32,
+    description: &'static str,
+    apply: fn(&mut Document),
+}
+
+/// Every layout change so far, oldest first. A breaking change to the config
+/// bumps [`SCHEMA_VERSION`] and adds its migration here.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Upgrades a TOML config to the current layout, keeping its comments and
+/// formatting. Returns the upgraded config and what was changed, nothing if
+/// it is already current.
+pub fn migrate(config_toml: &str) -> anyhow::Result<(String, Vec<String>)> {
+    let mut document = config_toml.parse::<Document>()?;
+    // A config from before `schema_version` is at the first version.
+    let version = match document.get("schema_version") {
+        None => 1,
+        Some(item) => item
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version > 0)
+            .ok_or_else(|| anyhow!("`schema_version` must be a whole number from 1"))?,
+    };
+    if version > SCHEMA_VERSION {
+        bail!(
+            "config schema version {version} is newer than the supported version {SCHEMA_VERSION}"
+        );
+    }
+    let mut changes = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.from >= version)
+    {
+        (migration.apply)(&mut document);
+        changes.push(format!(
+            "{} to {}: {}",
+            migration.from,
+            migration.from + 1,
+            migration.description
+        ));
+    }
+    if document
+        .get("schema_version")
+        .and_then(|item| item.as_integer())
+        != Some(i64::from(SCHEMA_VERSION))
+    {
+        document["schema_version"] = value(i64::from(SCHEMA_VERSION));
+        changes.push(format!("set `schema_version` to {SCHEMA_VERSION}"));
+    }
+    let migrated = document.to_string();
+    parse_config(&migrated).context("the migrated config is still not valid")?;
+    Ok((migrated, changes))
+}
+
+/// Upgrades a config file in place, keeping the original next to it as
+/// `<name>.toml.bak`. The file is replaced in one rename.
+pub async fn migrate_config_file(config_file: &Path) -> anyhow::Result<Vec<String>> {
+    let current = tokio::fs::read_to_string(config_file)
+        .await
+        .with_context(|| format!("failed to read config file {}", config_file.display()))?;
+    let (migrated, changes) = migrate(&current)?;
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+    tokio::fs::write(config_file.with_extension("toml.bak"), &current).await?;
+    let temporary = config_file.with_extension("toml.migrate");
+    tokio::fs::write(&temporary, migrated).await?;
+    tokio::fs::rename(&temporary, config_file).await?;
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate, migrate_config_file};
+    use crate::config::SCHEMA_VERSION;
+
+    const CONFIG: &str = r#"
+# The controller
+url = "https://unifi"
+
+[[devices]]
+mac = "00:00:00:00:00:00"
+machines = [{ maas_id = "abc123", port_id = 1 }]
+"#;
+
+    #[test]
+    fn should_stamp_unversioned_config_and_leave_current_config_alone() {
+        let (migrated, changes) = migrate(CONFIG).unwrap();
+        assert_eq!(
+            changes,
+            [format!("set `schema_version` to {SCHEMA_VERSION}")]
+        );
+        assert!(migrated.contains("# The controller"));
+        assert!(migrated.contains(&format!("schema_version = {SCHEMA_VERSION}")));
+        let (again, changes) = migrate(&migrated).unwrap();
+        assert!(changes.is_empty());
+        assert_eq!(again, migrated);
+        let newer = format!("schema_version = {}\n{CONFIG}", SCHEMA_VERSION + 1);
+        assert!(migrate(&newer).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_keep_a_backup_of_the_original() {
+        let path = std::env::temp_dir().join(format!(
+            "maas-power-unifi-migrate-{}.toml",
+            std::process::id()
+        ));
+        tokio::fs::write(&path, CONFIG).await.unwrap();
+        let changes = migrate_config_file(&path).await.unwrap();
+        assert_eq!(changes.len(), 1);
+        let backup = path.with_extension("toml.bak");
+        assert_eq!(tokio::fs::read_to_string(&backup).await.unwrap(), CONFIG);
+        let migrated = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(migrated.contains("schema_version"));
+        tokio::fs::remove_file(path).await.unwrap();
+        tokio::fs::remove_file(backup).await.unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use std::{str::FromStr, sync::Arc, time::Duration, time::SystemTime};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{
+    chat::{Chat, ChatSink},
+    config::{NotificationsConfig, Severity, WebhookConfig},
+    email::EmailDigest,
+    syslog::SyslogSink,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PowerAction {
+    #[serde(rename = "power_on")]
+    On,
+    #[serde(rename = "power_off")]
+    Off,
+    #[serde(rename = "power_cycle")]
+    Cycle,
+}
+
+impl PowerAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerAction::On => "power_on",
+            PowerAction::Off => "power_off",
+            PowerAction::Cycle => "power_cycle",
+        }
+    }
+}
+
+impl FromStr for PowerAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [PowerAction::On, PowerAction::Off, PowerAction::Cycle]
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| format!("unknown power action `{s}`"))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventResult {
+    Success,
+    Failure,
+    /// The port was powered on but never started drawing power.
+    NoPowerDraw,
+    /// The port was powered on but its link never came up.
+    NoLink,
+}
+
+impl EventResult {
+    pub fn severity(&self) -> Severity {
+        match self {
+            EventResult::Success => Severity::Info,
+            EventResult::NoPowerDraw | EventResult::NoLink => Severity::Warning,
+            EventResult::Failure => Severity::Error,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PowerEvent {
+    pub machine: String,
+    pub action: PowerAction,
+    pub result: EventResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub timestamp: String,
+}
+
+impl PowerEvent {
+    pub fn new<S: Into<String>>(machine: S, action: PowerAction, result: EventResult) -> Self {
+        Self {
+            machine: machine.into(),
+            action,
+            result,
+            error: None,
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        }
+    }
+
+    pub fn with_error<S: Into<String>>(mut self, error: S) -> Self {
+        self.error = Some(error.into());
+        self
+    }
+}
+
+/// Events buffered for a slow subscriber before it starts missing them.
+const EVENT_BUFFER: usize = 64;
+
+/// Sends power events to the configured notification sinks. Delivery happens in
+/// the background so a slow sink never delays the response to MaaS.
+#[derive(Clone)]
+pub struct Notifier {
+    webhook: Option<Arc<WebhookSink>>,
+    syslog: Option<Arc<SyslogSink>>,
+    chats: Vec<Arc<ChatSink>>,
+    email: Option<EmailDigest>,
+    events: broadcast::Sender<PowerEvent>,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self {
+            webhook: None,
+            syslog: None,
+            chats: Vec::new(),
+            email: None,
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationsConfig) -> anyhow::Result<Self> {
+        let webhook = config
+            .webhook
+            .as_ref()
+            .map(WebhookSink::new)
+            .transpose()?
+            .map(Arc::new);
+        let syslog = config
+            .syslog
+            .as_ref()
+            .map(SyslogSink::new)
+            .transpose()?
+            .map(Arc::new);
+        let chats = [
+            (Chat::Slack, &config.slack),
+            (Chat::Discord, &config.discord),
+        ]
+        .into_iter()
+        .filter_map(|(chat, config)| Some(ChatSink::new(chat, config.as_ref()?).map(Arc::new)))
+        .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            webhook,
+            syslog,
+            chats,
+            email: config.email.as_ref().map(EmailDigest::spawn),
+            ..Default::default()
+        })
+    }
+
+    /// Receives every event sent from now on.
+    #[cfg(feature = "grpc")]
+    pub fn subscribe(&self) -> broadcast::Receiver<PowerEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn notify(&self, event: PowerEvent) {
+        let notifier = self.clone();
+        tokio::spawn(async move { notifier.send(&event).await });
+    }
+
+    pub async fn send(&self, event: &PowerEvent) {
+        // Only fails when nobody is subscribed.
+        let _ = self.events.send(event.clone());
+        if let Some(webhook) = &self.webhook {
+            webhook.send(event).await;
+        }
+        if let Some(syslog) = &self.syslog {
+            syslog.send(event).await;
+        }
+        for chat in &self.chats {
+            chat.send(event).await;
+        }
+        if let Some(email) = &self.email {
+            email.send(event);
+        }
+    }
+}
+
+struct WebhookSink {
+    urls: Vec<String>,
+    client: Client,
+}
+
+impl WebhookSink {
+    fn new(config: &WebhookConfig) -> anyhow::Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        Ok(Self {
+            urls: config.urls.clone(),
+            client,
+        })
+    }
+
+    async fn send(&self, event: &PowerEvent) {
+        for url in &self.urls {
+            let result = self
+                .client
+                .post(url)
+                .json(event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                tracing::warn!("failed to send power event to webhook {url}: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventResult, Notifier, PowerAction, PowerEvent};
+    use crate::config::{NotificationsConfig, WebhookConfig};
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const MAAS_SYSTEM_ID: &str = "system-id";
+
+    #[tokio::test]
+    async fn should_post_event_to_every_webhook() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .and(body_partial_json(json!({
+                "machine": MAAS_SYSTEM_ID,
+                "action": "power_off",
+                "result": "failure",
+                "error": "boom",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        let url = format!("{}/hook", mock_server.uri());
+        let config = NotificationsConfig {
+            webhook: Some(WebhookConfig {
+                urls: vec![url.clone(), url],
+                timeout_secs: 1,
+            }),
+            ..Default::default()
+        };
+        let notifier = Notifier::new(&config).unwrap();
+        let event = PowerEvent::new(MAAS_SYSTEM_ID, PowerAction::Off, EventResult::Failure)
+            .with_error("boom");
+        notifier.send(&event).await;
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Context};
+use mac_address::MacAddress;
+
+use crate::{
+    config::Config,
+    exit::Failure,
+    unifi::{handler::UnifiHandler, models::Station},
+};
+
+/// The clients the controller sees on the port of a mapped machine.
+#[derive(Debug, PartialEq)]
+pub struct PortScan {
+    pub device: MacAddress,
+    pub port_id: usize,
+    pub maas_id: String,
+    /// The NIC MAC configured for the machine.
+    pub expected: Option<MacAddress>,
+    pub seen: Vec<Station>,
+    /// The device and port the expected MAC was seen on instead.
+    pub elsewhere: Option<(MacAddress, usize)>,
+}
+
+impl PortScan {
+    /// Whether the machine's NIC is missing from the port.
+    pub fn is_mismatch(&self) -> bool {
+        self.expected
+            .is_some_and(|expected| !self.seen.iter().any(|station| station.mac == expected))
+    }
+}
+
+/// Matches the clients the controller sees against every mapped port.
+pub fn scan(config: &Config, stations: &[Station]) -> Vec<PortScan> {
+    let location = |station: &Station| station.sw_mac.zip(station.sw_port);
+    config
+        .devices
+        .iter()
+        .flat_map(|device| {
+            device.machines.iter().map(|machine| {
+                let seen = stations
+                    .iter()
+                    .filter(|station| location(station) == Some((device.mac, machine.port_id)))
+                    .cloned()
+                    .collect();
+                let elsewhere = machine.mac.and_then(|expected| {
+                    stations
+                        .iter()
+                        .find(|station| station.mac == expected)
+                        .and_then(location)
+                        .filter(|location| *location != (device.mac, machine.port_id))
+                });
+                PortScan {
+                    device: device.mac,
+                    port_id: machine.port_id,
+                    maas_id: machine.maas_id.clone(),
+                    expected: machine.mac,
+                    seen,
+                    elsewhere,
+                }
+            })
+        })
+        .collect()
+}
+
+fn describe(station: &Station) -> String {
+    match &station.hostname {
+        Some(hostname) => format!("{} ({hostname})", station.mac),
+        None => station.mac.to_string(),
+    }
+}
+
+/// Prints what the controller sees on each mapped port, marking ports where
+/// the configured NIC MAC is missing with `!`. Fails if any port mismatches.
+pub async fn port_scan(config: &Config, controller: &UnifiHandler) -> anyhow::Result<()> {
+    let stations = controller
+        .clients()
+        .await
+        .map_err(|e| anyhow!("failed to list clients: {e:?}"))
+        .context(Failure::Unreachable)?;
+    let scans = scan(config, &stations);
+    println!(
+        "  {:<17}  {:>4}  {:<20}  {:<17}  seen",
+        "device", "port", "machine", "expected"
+    );
+    for scan in &scans {
+        let marker = if scan.is_mismatch() { "!" } else { " " };
+        let expected = scan.expected.map(|mac| mac.to_string()).unwrap_or_default();
+        let seen = match scan.seen.as_slice() {
+            [] => "nothing".to_owned(),
+            seen => seen.iter().map(describe).collect::<Vec<_>>().join(", "),
+        };
+        print!(
+            "{marker} {:<17}  {:>4}  {:<20}  {:<17}  {seen}",
+            scan.device.to_string(),
+            scan.port_id,
+            scan.maas_id,
+            expected
+        );
+        if let Some((device, port_id)) = scan.elsewhere {
+            print!(", expected MAC is on {device} port {port_id}");
+        }
+        println!();
+    }
+    let mismatches = scans.iter().filter(|scan| scan.is_mismatch()).count();
+    if mismatches > 0 {
+        return Err(
+            anyhow!("{mismatches} port(s) do not have the configured machine attached")
+                .context(Failure::Mapping),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::scan;
+    use crate::{config::Config, unifi::models::Station};
+    use mac_address::MacAddress;
+
+    const SWITCH: [u8; 6] = [0, 0, 0, 0, 0, 1];
+    const NODE_1: [u8; 6] = [0xaa, 0, 0, 0, 0, 1];
+    const NODE_2: [u8; 6] = [0xaa, 0, 0, 0, 0, 2];
+
+    fn station(mac: [u8; 6], port: usize) -> Station {
+        Station {
+            mac: MacAddress::from(mac),
+            sw_mac: Some(MacAddress::from(SWITCH)),
+            sw_port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_flag_machines_on_the_wrong_port() {
+        let config: Config = toml::from_str(
+            r#"
+            url = "https://localhost:8443"
+
+            [[devices]]
+            mac = "00:00:00:00:00:01"
+            machines = [
+                { maas_id = "node-1", port_id = 1, mac = "aa:00:00:00:00:01" },
+                { maas_id = "node-2", port_id = 2, mac = "aa:00:00:00:00:02" },
+                { maas_id = "node-3", port_id = 3 },
+            ]
+            "#,
+        )
+        .unwrap();
+        // node-1 and node-2 are cabled the wrong way round.
+        let scans = scan(&config, &[station(NODE_2, 1), station(NODE_1, 2)]);
+        assert!(scans.iter().take(2).all(|scan| scan.is_mismatch()));
+        assert_eq!(scans[0].seen, vec![station(NODE_2, 1)]);
+        assert_eq!(scans[0].elsewhere, Some((MacAddress::from(SWITCH), 2)));
+        assert!(!scans[2].is_mismatch());
+        assert!(scans[2].seen.is_empty());
+    }
+}
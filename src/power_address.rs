@@ -0,0 +1,74 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use mac_address::MacAddress;
+use reqwest::Url;
+
+/// The `power_address` MaaS keeps for a machine: the URL of its controller,
+/// optionally naming the device and port in the query, e.g.
+/// `https://unifi.local:8443?device=aa:bb:cc:dd:ee:ff&port=3`.
+#[derive(Debug, PartialEq)]
+pub struct PowerAddress {
+    pub controller: Url,
+    /// The device MAC and port, if the address names them.
+    pub port: Option<(MacAddress, usize)>,
+}
+
+impl PowerAddress {
+    /// Whether the address names the controller at `url`. Paths are ignored,
+    /// the same controller can be reached through a proxy path.
+    pub fn is_controller(&self, url: &str) -> bool {
+        Url::parse(url).is_ok_and(|url| url.origin() == self.controller.origin())
+    }
+}
+
+impl FromStr for PowerAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> anyhow::Result<Self> {
+        let controller = Url::parse(address).context("`power_address` is not a URL")?;
+        let query = |key: &str| {
+            controller
+                .query_pairs()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.into_owned())
+        };
+        let port = match (query("device"), query("port")) {
+            (Some(device), Some(port)) => Some((
+                MacAddress::from_str(&device)
+                    .map_err(|_| anyhow!("`{device}` is not a MAC address"))?,
+                port.parse()
+                    .map_err(|_| anyhow!("`{port}` is not a port number"))?,
+            )),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!(
+                    "`power_address` needs both a `device` and a `port`"
+                ))
+            }
+        };
+        Ok(Self { controller, port })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PowerAddress;
+    use mac_address::MacAddress;
+    use std::str::FromStr;
+
+    #[test]
+    fn should_parse_controller_device_and_port() {
+        let address =
+            PowerAddress::from_str("https://unifi.local:8443?device=aa:bb:cc:dd:ee:ff&port=3")
+                .unwrap();
+        let device = MacAddress::from_str("aa:bb:cc:dd:ee:ff").unwrap();
+        assert_eq!(address.port, Some((device, 3)));
+        assert!(address.is_controller("https://unifi.local:8443/proxy/network"));
+        assert!(!address.is_controller("https://unifi.local"));
+        let address = PowerAddress::from_str("https://unifi.local:8443").unwrap();
+        assert_eq!(address.port, None);
+        assert!(PowerAddress::from_str("https://unifi.local?port=3").is_err());
+        assert!(PowerAddress::from_str("unifi.local").is_err());
+    }
+}
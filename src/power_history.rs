@@ -0,0 +1,52 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    backend::BackendRegistry,
+    config::PowerHistoryConfig,
+    store::{PowerSample, Store},
+};
+
+/// Records the power draw of every machine whose backend can measure it.
+pub async fn sample_power(backends: &BackendRegistry, store: &Store) {
+    for target in backends.targets() {
+        let watts = match target.backend.power_draw(&target.machine).await {
+            Ok(Some(watts)) => watts,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::debug!(
+                    "failed to sample power draw of {}: {e:?}",
+                    target.machine.maas_id
+                );
+                continue;
+            }
+        };
+        let sample = PowerSample {
+            timestamp: SystemTime::now(),
+            watts,
+        };
+        if let Err(e) = store
+            .record_power_sample(&target.machine.maas_id, sample)
+            .await
+        {
+            tracing::warn!("failed to store power sample: {e}");
+        }
+    }
+}
+
+/// Samples power draw on an interval and prunes samples past their retention.
+pub fn spawn_sampler(backends: BackendRegistry, store: Store, config: PowerHistoryConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            sample_power(&backends, &store).await;
+            // A retention reaching back before the epoch keeps every sample.
+            let cutoff = SystemTime::now()
+                .checked_sub(Duration::from_secs(config.retention_secs))
+                .unwrap_or(UNIX_EPOCH);
+            if let Err(e) = store.prune_power_samples(cutoff).await {
+                tracing::warn!("failed to prune power samples: {e}");
+            }
+        }
+    });
+}
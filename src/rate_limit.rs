@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use crate::{config::RateLimitConfig, shared_state::SharedState};
+
+/// Limits the power actions per machine, so a client stuck retrying cannot
+/// keep cycling a machine. Counts are kept in the shared state so the limit
+/// holds across instances.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    shared: SharedState,
+    config: Option<RateLimitConfig>,
+}
+
+impl RateLimiter {
+    pub fn new(shared: SharedState, config: Option<RateLimitConfig>) -> Self {
+        Self { shared, config }
+    }
+
+    /// Counts a power action on the machine, returning how long until the
+    /// next one is allowed if it is over the limit.
+    pub async fn check(&self, system_id: &str) -> anyhow::Result<Option<Duration>> {
+        let Some(config) = &self.config else {
+            return Ok(None);
+        };
+        let (count, remaining) = self
+            .shared
+            .increment(
+                &format!("rate-limit:{system_id}"),
+                Duration::from_secs(config.window_secs),
+            )
+            .await?;
+        Ok((count > config.max_actions).then_some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RateLimiter;
+    use crate::{config::RateLimitConfig, shared_state::SharedState};
+
+    #[tokio::test]
+    async fn should_limit_each_machine_separately() {
+        let limiter = RateLimiter::new(
+            SharedState::default(),
+            Some(RateLimitConfig {
+                max_actions: 2,
+                window_secs: 60,
+            }),
+        );
+        assert!(limiter.check("a").await.unwrap().is_none());
+        assert!(limiter.check("a").await.unwrap().is_none());
+        assert!(limiter.check("a").await.unwrap().is_some());
+        assert!(limiter.check("b").await.unwrap().is_none());
+        assert!(RateLimiter::default().check("a").await.unwrap().is_none());
+    }
+}
@@ -1,32 +1,133 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwap;
+
 use crate::{
-    config::Config,
-    unifi::{client::UnifiError, handler::UnifiHandler, models::PowerStatus},
+    assets::{ui_asset, ui_index},
+    auth::{authenticate, Authenticator},
+    backend::{BackendError, BackendRegistry, Target},
+    backup::Backup,
+    config::{Config, Device, Driver, Machine},
+    etag::json_with_etag,
+    exit::Failure,
+    flap_guard::{Flap, FlapGuard},
+    graphql::{graphql, schema},
+    hooks::{run_hook, HookContext},
+    in_flight::{InFlight, InFlightAction, InFlightGuard},
+    jobs::{Job, JobStatus, Jobs},
+    leader::Leadership,
+    logging::{LogFilter, ACCESS_TARGET, AUDIT_TARGET},
+    metrics::Metrics,
+    notifications::{EventResult, Notifier, PowerAction, PowerEvent},
+    power_address::PowerAddress,
+    rate_limit::RateLimiter,
+    sessions::Sessions,
+    snapshot::{take_snapshot, StateSnapshot},
+    stats::{machine_stats, MachineStats},
+    store::{ActionRecord, Store},
+    unifi::{
+        client::UnifiError,
+        handler::{ControllerHealth, UnifiHandler},
+        models::{PoeMode, PowerStatus},
+    },
+    validation::{validate_config, Reconciled, ValidationReport},
+    watchdog::watch_power_on,
 };
 use async_trait::async_trait;
 use axum::{
-    extract::{FromRef, FromRequestParts},
+    extract::{FromRequestParts, MatchedPath, Path, Query},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, post},
     Extension, Json, Router,
 };
-use http::{request::Parts, StatusCode};
+use http::{
+    header::{LOCATION, RETRY_AFTER, WWW_AUTHENTICATE},
+    request::Parts,
+    HeaderMap, HeaderValue, Method, Request, StatusCode,
+};
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::compression::CompressionLayer;
 use tracing::instrument;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub config: &'static Config,
-    pub handler: UnifiHandler,
+    /// Swapped when the mapping source delivers a new mapping.
+    pub config: Arc<ArcSwap<Config>>,
+    pub backends: BackendRegistry,
+    pub metrics: Metrics,
+    pub notifier: Notifier,
+    pub store: Store,
+    pub controller: UnifiHandler,
+    /// Where the config was read from, restores write their mappings here.
+    /// `None` when the config came from a directory or the environment.
+    pub config_file: Option<PathBuf>,
+    pub in_flight: InFlight,
+    pub jobs: Jobs,
+    pub leadership: Leadership,
+    pub rate_limiter: RateLimiter,
+    pub flap_guard: FlapGuard,
+    pub log_filter: LogFilter,
+    /// Controller sessions for credentials sent with a request.
+    pub sessions: Sessions,
+    pub authenticator: Authenticator,
+    pub unknown_nics: UnknownNics,
+    pub reconciled: Reconciled,
 }
 
-impl FromRef<AppState> for UnifiHandler {
-    fn from_ref(state: &AppState) -> UnifiHandler {
-        state.handler.clone()
+impl AppState {
+    /// The state for a request acting through another controller session.
+    fn with_controller(&self, handler: UnifiHandler) -> AppState {
+        AppState {
+            backends: self.backends.with_controller(&handler),
+            controller: handler,
+            ..self.clone()
+        }
     }
 }
 
-enum AppError {
+pub(crate) enum AppError {
     Power(UnifiError),
+    BadRequest(String),
+    NotFound(String),
+    Store(String),
+    Backend(String),
+    Unsupported(String),
+    Hook(String),
+    Restore(String),
+    /// The controller refused the credentials sent with the request.
+    Credentials(String),
+    /// The request did not carry valid credentials for this service.
+    Unauthorized,
+    /// The client's role does not allow the request.
+    Forbidden(String),
+    /// The request is well formed but contradicts an earlier one, e.g. it
+    /// reuses an idempotency key for another action.
+    Unprocessable(String),
+    /// Another power action is still running against the machine.
+    Conflict(InFlightAction),
+    /// Another instance holds the leader lease, which lapses within the given
+    /// time if that instance has died.
+    Standby(Duration),
+    /// The machine had too many power actions, retry after the given time.
+    RateLimited(Duration),
+    /// The action would reverse the machine's last transition too soon.
+    Flapping(Flap),
+    /// The deadline the client set passed. A power action carries on running
+    /// after its request has timed out.
+    Timeout {
+        after: Duration,
+        carries_on: bool,
+    },
 }
 
 impl From<UnifiError> for AppError {
@@ -35,15 +136,93 @@ impl From<UnifiError> for AppError {
     }
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+impl From<BackendError> for AppError {
+    fn from(inner: BackendError) -> Self {
+        match inner {
+            BackendError::Unifi(e) => AppError::Power(e),
+            BackendError::Failed(e) => AppError::Backend(e),
+            BackendError::Unsupported(e) => AppError::Unsupported(e),
+        }
+    }
+}
+
+impl AppError {
+    pub(crate) fn status_and_message(&self) -> (StatusCode, String) {
+        match self {
+            AppError::BadRequest(error) => (StatusCode::BAD_REQUEST, error.clone()),
+            AppError::NotFound(error) => (StatusCode::NOT_FOUND, error.clone()),
+            AppError::Store(error) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read stored state: {error}"),
+            ),
+            AppError::Backend(error) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Power backend failed: {error}"),
+            ),
+            AppError::Unsupported(error) => (StatusCode::NOT_IMPLEMENTED, error.clone()),
+            AppError::Hook(error) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Hook failed, the power action was not run: {error}"),
+            ),
+            AppError::Conflict(running) => (
+                StatusCode::CONFLICT,
+                format!(
+                    "A {} of this machine has been in progress since {}",
+                    running.action.as_str(),
+                    running.started_at
+                ),
+            ),
+            AppError::Standby(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "This instance is a standby, power actions are run by the leader".to_owned(),
+            ),
+            AppError::RateLimited(retry_after) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Too many power actions on this machine, retry in {}s",
+                    retry_after_secs(*retry_after)
+                ),
+            ),
+            AppError::Flapping(flap) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "The machine was powered {} too recently, retry in {}s",
+                    if flap.last_on { "on" } else { "off" },
+                    retry_after_secs(flap.retry_after)
+                ),
+            ),
+            AppError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid credentials".to_owned(),
+            ),
+            AppError::Forbidden(error) => (StatusCode::FORBIDDEN, error.clone()),
+            AppError::Unprocessable(error) => (StatusCode::UNPROCESSABLE_ENTITY, error.clone()),
+            AppError::Credentials(error) => (
+                StatusCode::FORBIDDEN,
+                format!("The controller refused the request's credentials: {error}"),
+            ),
+            AppError::Restore(error) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to restore backup: {error}"),
+            ),
+            AppError::Timeout { after, carries_on } => (
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "The controller did not answer within the {}s `{REQUEST_TIMEOUT}`{}",
+                    after.as_secs_f64(),
+                    if *carries_on {
+                        ", the power action carries on"
+                    } else {
+                        ""
+                    }
+                ),
+            ),
             AppError::Power(UnifiError::DeviceListError(s)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Failed to list devices, error: {s}"),
             ),
             AppError::Power(UnifiError::FailedToConstructUrl(s)) => {
-                (StatusCode::UNPROCESSABLE_ENTITY, s)
+                (StatusCode::UNPROCESSABLE_ENTITY, s.clone())
             }
             AppError::Power(UnifiError::MissingSystemId) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -54,7 +233,7 @@ impl IntoResponse for AppError {
                 format!("Device with mac address {mac} was not found!"),
             ),
             AppError::Power(UnifiError::MachineNotFound(system_id)) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::NOT_FOUND,
                 format!("Machine with system id {system_id} was not found!"),
             ),
             AppError::Power(UnifiError::MachinePortIdIncorrect(port_id)) => (
@@ -65,19 +244,144 @@ impl IntoResponse for AppError {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Failed to power on a port on the device {device_id}!"),
             ),
+            AppError::Power(UnifiError::PoeBudgetExceeded(usage)) => (
+                StatusCode::CONFLICT,
+                format!(
+                    "The switch draws {:.1}W of its {:.1}W PoE budget, over the {:.0}% allowed \
+                    by `poe_budget.max_utilization`, power off another machine on it first",
+                    usage.draw_watts,
+                    usage.budget_watts,
+                    usage.max_utilization * 100.0
+                ),
+            ),
+            AppError::Power(UnifiError::Controller(error)) => match error.code.as_str() {
+                "api.err.NoPermission" => (
+                    StatusCode::BAD_GATEWAY,
+                    format!(
+                        "The controller account may not do this, give it a role that can \
+                        manage devices on the site: {error}"
+                    ),
+                ),
+                "api.err.InvalidPayload" => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!(
+                        "The controller rejected the change, check the port exists and \
+                        supports PoE: {error}"
+                    ),
+                ),
+                "api.err.LoginRequired" => (
+                    StatusCode::BAD_GATEWAY,
+                    format!(
+                        "The controller refused to log in again after the session expired: {error}"
+                    ),
+                ),
+                _ => (
+                    StatusCode::BAD_GATEWAY,
+                    format!("The controller answered with an error: {error}"),
+                ),
+            },
             AppError::Power(UnifiError::FailedToConvertSystemId(error)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Failed to convert system_id to string: {error}"),
             ),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = self.status_and_message();
+        let retry_after = self.retry_after();
+        let challenge = matches!(self, AppError::Unauthorized);
+        let body = match self {
+            AppError::Conflict(running) => json!({
+                "error": error_message,
+                "in_flight": running,
+            }),
+            AppError::Flapping(flap) => json!({
+                "error": error_message,
+                "anti_flap": flap,
+            }),
+            AppError::Power(UnifiError::PoeBudgetExceeded(usage)) => json!({
+                "error": error_message,
+                "poe_budget": usage,
+            }),
+            AppError::Power(UnifiError::Controller(error)) => json!({
+                "error": error_message,
+                "code": error.code,
+            }),
+            _ => json!({
+                "error": error_message,
+            }),
         };
-        let body = Json(json!({
-            "error": error_message,
-        }));
-        (status, body).into_response()
+        let mut response = (status, Json(body)).into_response();
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after_secs(retry_after).into());
+        }
+        if challenge {
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"maas-power-unifi\""),
+            );
+        }
+        response
+    }
+}
+
+impl AppError {
+    /// When a throttled or unavailable request is worth retrying.
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AppError::RateLimited(retry_after) | AppError::Standby(retry_after) => {
+                Some(*retry_after)
+            }
+            AppError::Flapping(flap) => Some(flap.retry_after),
+            _ => None,
+        }
     }
 }
 
+/// Whole seconds for a `Retry-After` header, rounded up so a client never
+/// retries too early.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
 const SYSTEM_ID: &str = "system_id";
+const NIC_MAC: &str = "mac_address";
+const POWER_ADDRESS: &str = "power_address";
+const POWER_USER: &str = "power_user";
+const POWER_PASS: &str = "power_pass";
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+const REQUEST_TIMEOUT: &str = "x-request-timeout";
+const DEFAULT_WINDOW: &str = "24h";
+/// How long a NIC found on no mapped port is answered without asking
+/// the controller again.
+const UNKNOWN_NIC_TTL: Duration = Duration::from_secs(30);
+
+/// NICs recently found on no mapped port, so a misconfigured
+/// machine polling by `mac_address` does not list the controller's clients on
+/// every request.
+#[derive(Clone, Default)]
+pub struct UnknownNics(Arc<Mutex<HashMap<MacAddress, Instant>>>);
+
+impl UnknownNics {
+    fn contains(&self, nic: MacAddress) -> bool {
+        let mut nics = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        nics.retain(|_, expires_at| *expires_at > now);
+        nics.contains_key(&nic)
+    }
+
+    fn insert(&self, nic: MacAddress) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(nic, Instant::now() + UNKNOWN_NIC_TTL);
+    }
+}
 
 struct ExtractSystemId(String);
 
@@ -86,209 +390,1752 @@ impl<S> FromRequestParts<S> for ExtractSystemId
 where
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        if let Some(system_id) = parts.headers.get(SYSTEM_ID) {
-            let system_id = system_id.to_str().map_err(|_e| {
-                (
-                    StatusCode::BAD_REQUEST,
-                    "Failed to convert system_id header to a string!",
-                )
-            })?;
-            Ok(ExtractSystemId(system_id.to_owned()))
+        let state = parts
+            .extensions
+            .get::<AppState>()
+            .expect("the state is layered on every route");
+        let system_id = if let Some(system_id) = parts.headers.get(SYSTEM_ID) {
+            system_id
+                .to_str()
+                .map_err(|_e| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        "Failed to convert system_id header to a string!",
+                    )
+                        .into_response()
+                })?
+                .to_owned()
+        } else if let Some(nic) = parts.headers.get(NIC_MAC) {
+            let nic = nic
+                .to_str()
+                .ok()
+                .and_then(|nic| MacAddress::from_str(nic).ok())
+                .ok_or_else(|| {
+                    AppError::BadRequest("`mac_address` header is not a MAC address".to_owned())
+                        .into_response()
+                })?;
+            system_id_of_nic(state, nic)
+                .await
+                .map_err(IntoResponse::into_response)?
         } else {
-            Err((StatusCode::BAD_REQUEST, "`system_id` header is missing"))
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "`system_id` or `mac_address` header is missing",
+            )
+                .into_response());
+        };
+        if let Some(address) = parts.headers.get(POWER_ADDRESS) {
+            let address = address
+                .to_str()
+                .map_err(anyhow::Error::from)
+                .and_then(PowerAddress::from_str)
+                .map_err(|e| AppError::BadRequest(format!("{e:#}")).into_response())?;
+            // Only power actions register a machine, a status query just checks.
+            let register = parts.method == Method::POST;
+            apply_power_address(state, &system_id, address, register)
+                .map_err(IntoResponse::into_response)?;
+        }
+        Ok(ExtractSystemId(system_id))
+    }
+}
+
+/// Checks the machine's `power_address` against this bridge. With `register`, a
+/// machine with no mapping is registered on the port the address names, so it
+/// can be configured in MaaS alone. A machine registered this way stays on its
+/// port, and a port that powers another machine is refused.
+fn apply_power_address(
+    state: &AppState,
+    system_id: &str,
+    address: PowerAddress,
+    register: bool,
+) -> Result<(), AppError> {
+    let config = state.config.load();
+    if !address.is_controller(&config.url) {
+        return Err(AppError::BadRequest(format!(
+            "`power_address` names the controller at {}, not the one of this bridge",
+            address.controller.origin().ascii_serialization()
+        )));
+    }
+    let Some((device_mac, port_id)) = address.port else {
+        return Ok(());
+    };
+    let mapped = config.devices.iter().find_map(|device| {
+        device
+            .machines
+            .iter()
+            .find(|machine| machine.maas_id == system_id)
+            .map(|machine| (device.mac, machine.port_id))
+    });
+    match mapped {
+        Some(mapped) if mapped == (device_mac, port_id) => Ok(()),
+        Some((mapped_mac, mapped_port)) => Err(AppError::BadRequest(format!(
+            "{system_id} is mapped to port {mapped_port} of {mapped_mac}, not the port of its `power_address`"
+        ))),
+        None if config.machines.iter().any(|machine| machine.maas_id == system_id) => {
+            Err(AppError::BadRequest(format!(
+                "{system_id} is not powered through a UniFi device, so has no `power_address`"
+            )))
+        }
+        None => {
+            if let Some(target) = state.backends.resolve(system_id) {
+                let registered = (target.backend.unifi_device(), target.machine.port_id);
+                return match registered {
+                    (Some(mac), port) if (mac, port) == (device_mac, port_id) => Ok(()),
+                    (Some(mac), port) => Err(AppError::BadRequest(format!(
+                        "{system_id} is registered on port {port} of {mac}, not the port of its `power_address`"
+                    ))),
+                    (None, _) => Err(AppError::BadRequest(format!(
+                        "{system_id} is not powered through a UniFi device, so has no `power_address`"
+                    ))),
+                };
+            }
+            if !config.devices.iter().any(|device| device.mac == device_mac) {
+                return Err(AppError::NotFound(format!(
+                    "Device {device_mac} is not configured"
+                )));
+            }
+            if let Some(holder) = state.backends.targets().into_iter().find(|target| {
+                target.backend.unifi_device() == Some(device_mac)
+                    && target.machine.port_id == port_id
+            }) {
+                return Err(port_taken(port_id, device_mac, &holder.machine.maas_id));
+            }
+            if !register {
+                return Ok(());
+            }
+            let machine = Machine {
+                maas_id: system_id.to_owned(),
+                port_id,
+                ..Default::default()
+            };
+            state
+                .backends
+                .register_port(machine, device_mac, || {
+                    BackendRegistry::unifi_poe(&config, state.controller.clone(), device_mac)
+                })
+                .map_err(|holder| port_taken(port_id, device_mac, &holder))
         }
     }
 }
 
+fn port_taken(port_id: usize, device: MacAddress, holder: &str) -> AppError {
+    AppError::BadRequest(format!(
+        "Port {port_id} of {device} already powers {holder}, so cannot be a `power_address` of another machine"
+    ))
+}
+
+/// Finds the machine mapped to the switch port a NIC is learned on. Only
+/// mapped ports count, an uplink learns every NIC behind the switch it leads to.
+async fn system_id_of_nic(state: &AppState, nic: MacAddress) -> Result<String, AppError> {
+    if state.unknown_nics.contains(nic) {
+        return Err(nic_not_found(nic));
+    }
+    let stations = state.controller.clients().await?;
+    let config = state.config.load();
+    stations
+        .iter()
+        .filter(|station| station.mac == nic)
+        .filter_map(|station| station.sw_mac.zip(station.sw_port))
+        .find_map(|(sw_mac, sw_port)| {
+            config
+                .devices
+                .iter()
+                .filter(|device| device.mac == sw_mac)
+                .flat_map(|device| &device.machines)
+                .find(|machine| machine.port_id == sw_port)
+                .map(|machine| machine.maas_id.clone())
+        })
+        .ok_or_else(|| {
+            state.unknown_nics.insert(nic);
+            nic_not_found(nic)
+        })
+}
+
+fn nic_not_found(nic: MacAddress) -> AppError {
+    AppError::NotFound(format!("{nic} is not connected to a mapped port"))
+}
+
 pub fn routes(state: AppState) -> Router {
-    Router::new()
-        .route("/power-status", get(power_status))
-        .route("/power-on", post(power_on))
-        .route("/power-off", post(power_off))
+    let config = state.config.load_full();
+    let concurrency = &config.concurrency;
+    let paths = &config.routes;
+    let status = paths
+        .power_status
+        .iter()
+        .fold(Router::new(), |router, path| {
+            router.route(path, get(power_status))
+        })
+        .route_layer(middleware::from_fn(request_credentials))
+        .route_layer(middleware::from_fn(read_deadline))
+        .route_layer(GlobalConcurrencyLimitLayer::new(concurrency.status));
+    let power = [
+        (&paths.power_on, post(power_on)),
+        (&paths.power_off, post(power_off)),
+        (&paths.power_cycle, post(power_cycle)),
+    ]
+    .into_iter()
+    .flat_map(|(paths, handler)| paths.iter().map(move |path| (path, handler.clone())))
+    .fold(Router::new(), |router, (path, handler)| {
+        router.route(path, handler)
+    })
+    .route_layer(middleware::from_fn(request_credentials))
+    .route_layer(middleware::from_fn(detach_power_action))
+    .route_layer(GlobalConcurrencyLimitLayer::new(concurrency.power));
+    let router = Router::new()
+        .merge(status)
+        .merge(power)
+        .route("/machines", get(machines))
+        .route("/devices", get(devices))
+        .route("/devices/:mac/ports", get(device_ports))
+        .route("/devices/:mac/poe-budget", get(device_poe_budget))
+        .route("/events/controller", get(controller_events))
+        .route("/jobs/:id", get(job))
+        .route("/machines/:system_id/power-history", get(power_history))
+        .route("/readyz", get(readyz))
+        .route("/stats", get(stats))
+        .route("/admin/state", get(admin_state))
+        .route("/admin/backup", get(admin_backup))
+        .route("/admin/restore", post(admin_restore))
+        .route("/admin/validate-config", post(admin_validate_config))
+        .route("/admin/logging", get(admin_logging).put(admin_set_logging))
+        .route("/graphql", post(graphql))
+        .route("/ui", get(ui_index))
+        .route("/ui/", get(ui_asset))
+        .route("/ui/*path", get(ui_asset))
+        .route_layer(middleware::from_fn(authenticate))
+        .route_layer(middleware::from_fn(track_metrics))
+        .layer(Extension(schema(state.clone())))
         .layer(Extension(state))
+        // Bodies are gzip or brotli encoded if the client accepts it, tiny
+        // bodies and images are left as they are.
+        .layer(CompressionLayer::new());
+    // The config has been validated, so the layer builds.
+    match config.cors.as_ref().and_then(|cors| cors.layer().ok()) {
+        Some(cors) => router.layer(cors),
+        None => router,
+    }
+}
+
+/// Counts a request whose client went away before it was answered. hyper
+/// drops the handler then, which cancels the controller calls of a read,
+/// power actions are detached so they carry on.
+struct CancelledRequest {
+    metrics: Option<Metrics>,
+    route: String,
+}
+
+impl Drop for CancelledRequest {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.metrics {
+            tracing::debug!("client left before {} was answered", self.route);
+            metrics.increment("http_requests_cancelled", &[("route", &self.route)]);
+        }
+    }
+}
+
+/// Records a request counter and a timing for every matched route.
+async fn track_metrics<B>(
+    Extension(AppState { metrics, .. }): Extension<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let start = Instant::now();
+    let mut cancelled = CancelledRequest {
+        metrics: Some(metrics.clone()),
+        route: route.clone(),
+    };
+    let response = next.run(request).await;
+    cancelled.metrics = None;
+    let status = response.status();
+    let labels = [("route", route.as_str()), ("status", status.as_str())];
+    metrics.increment("http_requests", &labels);
+    metrics.timing("http_request_duration", &labels, start.elapsed());
+    if !status.is_success() {
+        metrics.increment("http_request_errors", &labels);
+    }
+    tracing::info!(
+        target: ACCESS_TARGET,
+        "{method} {uri} {} {}ms",
+        status.as_u16(),
+        start.elapsed().as_millis()
+    );
+    response
+}
+
+/// The deadline a client sets with `X-Request-Timeout`, in seconds.
+fn request_timeout(headers: &HeaderMap) -> Result<Option<Duration>, AppError> {
+    let Some(value) = headers.get(REQUEST_TIMEOUT) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .filter(|timeout| !timeout.is_zero())
+        .map(Some)
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "`{REQUEST_TIMEOUT}` must be a positive number of seconds"
+            ))
+        })
+}
+
+/// Swaps in a controller session logged in with the request's `power_user`
+/// and `power_pass`, so the request acts as that account rather than the
+/// global one.
+async fn request_credentials<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().map(str::to_owned))
+    };
+    let (username, password) = match (header(POWER_USER), header(POWER_PASS)) {
+        (None, None) => return next.run(request).await,
+        (Some(Ok(username)), Some(Ok(password))) => (username, password),
+        _ => {
+            return AppError::BadRequest(
+                "`power_user` and `power_pass` must be sent together as strings".to_owned(),
+            )
+            .into_response()
+        }
+    };
+    let state = request
+        .extensions()
+        .get::<AppState>()
+        .expect("the state is layered on every route")
+        .clone();
+    let handler = match state.sessions.handler(&username, &password).await {
+        Ok(handler) => handler,
+        Err(e) if Failure::of_login(&e) == Failure::Auth => {
+            return AppError::Credentials(format!("{e:#}")).into_response()
+        }
+        Err(e) => {
+            return AppError::Backend(format!("failed to log in to the controller: {e:#}"))
+                .into_response()
+        }
+    };
+    request
+        .extensions_mut()
+        .insert(state.with_controller(handler));
+    next.run(request).await
+}
+
+/// Gives up on a status read at the client's deadline, which cancels the
+/// controller calls it is waiting on.
+async fn read_deadline<B>(request: Request<B>, next: Next<B>) -> Response {
+    let timeout = match request_timeout(request.headers()) {
+        Ok(Some(timeout)) => timeout,
+        Ok(None) => return next.run(request).await,
+        Err(e) => return e.into_response(),
+    };
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => AppError::Timeout {
+            after: timeout,
+            carries_on: false,
+        }
+        .into_response(),
+    }
+}
+
+/// Runs a power action in its own task, so it completes and is recorded even
+/// if the client disconnects or its deadline passes, rather than leaving a
+/// port half switched. The client's deadline only bounds the wait.
+async fn detach_power_action<B: Send + 'static>(request: Request<B>, next: Next<B>) -> Response {
+    let timeout = match request_timeout(request.headers()) {
+        Ok(timeout) => timeout,
+        Err(e) => return e.into_response(),
+    };
+    let action = tokio::spawn(next.run(request));
+    let response = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, action).await {
+            Ok(response) => response,
+            Err(_) => {
+                return AppError::Timeout {
+                    after: timeout,
+                    carries_on: true,
+                }
+                .into_response()
+            }
+        },
+        None => action.await,
+    };
+    response.unwrap_or_else(|e| {
+        tracing::error!("power action panicked: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
 }
 
-#[instrument(skip(handler))]
+#[instrument(skip(state))]
 async fn power_status(
-    Extension(AppState { config, handler }): Extension<AppState>,
+    Extension(state): Extension<AppState>,
     ExtractSystemId(system_id): ExtractSystemId,
-) -> Result<Json<PowerStatus>, AppError> {
-    let mac = config
-        .owning_device_mac(&system_id)
-        .ok_or(UnifiError::DeviceNotFound(system_id.to_owned()))?;
-    let machine = config
-        .machine(&system_id)
-        .ok_or(UnifiError::MachineNotFound(system_id.to_string()))?;
-    let device_id = handler.device_id(&mac).await?;
-    let device = handler.device(&device_id).await?;
-    device
-        .power_status(machine.port_id)
-        .map(Json)
-        .ok_or(UnifiError::DeviceNotFound("".to_owned()).into())
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let status = machine_status(&state, &system_id).await?;
+    Ok(json_with_etag(&headers, status))
+}
+
+pub(crate) async fn machine_status(
+    state: &AppState,
+    system_id: &str,
+) -> Result<PowerStatus, AppError> {
+    let target = state
+        .backends
+        .resolve(system_id)
+        .ok_or(UnifiError::MachineNotFound(system_id.to_owned()))?;
+    Ok(target.backend.status(&target.machine).await?)
+}
+
+#[derive(Deserialize)]
+struct PowerActionQuery {
+    #[serde(rename = "async")]
+    run_async: Option<bool>,
+}
+
+/// The optional `Idempotency-Key` header of an asynchronous power action.
+struct ExtractIdempotencyKey(Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ExtractIdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let key = parts
+            .headers
+            .get(IDEMPOTENCY_KEY)
+            .map(|key| key.to_str().map(str::to_owned))
+            .transpose()
+            .map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    "Failed to convert Idempotency-Key header to a string!",
+                )
+            })?;
+        Ok(ExtractIdempotencyKey(key))
+    }
 }
 
 async fn power_on(
-    Extension(AppState { config, handler }): Extension<AppState>,
+    Extension(state): Extension<AppState>,
     ExtractSystemId(system_id): ExtractSystemId,
-) -> Result<(), AppError> {
-    let mac = config
-        .owning_device_mac(&system_id)
-        .ok_or(UnifiError::DeviceNotFound(system_id.to_owned()))?;
-    let machine = config
-        .machine(&system_id)
-        .ok_or(UnifiError::MachineNotFound(system_id.to_string()))?;
-    let device_id = handler.device_id(&mac).await?;
-    Ok(handler.power_on(&device_id, machine.port_id).await?)
+    ExtractIdempotencyKey(key): ExtractIdempotencyKey,
+    Query(query): Query<PowerActionQuery>,
+) -> Result<Response, AppError> {
+    start_power_action(state, system_id, PowerAction::On, query, key).await
 }
 
 async fn power_off(
-    Extension(AppState { config, handler }): Extension<AppState>,
+    Extension(state): Extension<AppState>,
     ExtractSystemId(system_id): ExtractSystemId,
-) -> Result<(), AppError> {
-    let mac = config
-        .owning_device_mac(&system_id)
-        .ok_or(UnifiError::DeviceNotFound(system_id.to_owned()))?;
-    let machine = config
-        .machine(&system_id)
-        .ok_or(UnifiError::MachineNotFound(system_id.to_string()))?;
-    let device_id = handler.device_id(&mac).await?;
-    Ok(handler.power_off(&device_id, machine.port_id).await?)
+    ExtractIdempotencyKey(key): ExtractIdempotencyKey,
+    Query(query): Query<PowerActionQuery>,
+) -> Result<Response, AppError> {
+    start_power_action(state, system_id, PowerAction::Off, query, key).await
 }
 
-#[cfg(test)]
-mod test {
-    use crate::{
-        config::{self, Config, Machine},
-        router::{routes, AppState, PowerStatus},
-        unifi::{
-            self,
-            client::UnifiClient,
-            handler::UnifiHandler,
-            models::{DeviceId, Meta, PoeMode, Port, UnifiResponse},
-        },
-    };
-    use async_trait::async_trait;
-    use http::{Method, Request};
-    use hyper::{body, Body};
-    use mac_address::MacAddress;
-    use std::str::FromStr;
-    use tower::ServiceExt;
+async fn power_cycle(
+    Extension(state): Extension<AppState>,
+    ExtractSystemId(system_id): ExtractSystemId,
+    ExtractIdempotencyKey(key): ExtractIdempotencyKey,
+    Query(query): Query<PowerActionQuery>,
+) -> Result<Response, AppError> {
+    start_power_action(state, system_id, PowerAction::Cycle, query, key).await
+}
 
-    const UNIFI_DEVICE_MAC: &str = "00-00-00-00-00-00";
-    const MAAS_SYSTEM_ID_HEADER: &str = "system_id";
-    const MAAS_SYSTEM_ID: &str = "system-id";
-    const MACHINE_PORT: usize = 1;
+/// Runs a power action now, or as a background job answered with `202
+/// Accepted` and the job to poll. Either way the machine is claimed first so
+/// a conflicting action is refused straight away.
+async fn start_power_action(
+    state: AppState,
+    system_id: String,
+    action: PowerAction,
+    query: PowerActionQuery,
+    idempotency_key: Option<String>,
+) -> Result<Response, AppError> {
+    let config = state.config.load_full();
+    if !query.run_async.unwrap_or(config.async_power_actions) {
+        // The status is keyed by the same `system_id` header as the action.
+        let location = config.routes.power_status[0].clone();
+        let result = power_action_now(state, system_id, action).await?;
+        return Ok(([(LOCATION, location)], Json(result)).into_response());
+    }
+    if !state.leadership.is_leader() {
+        return Err(AppError::Standby(state.leadership.lease_ttl()));
+    }
+    // A repeated request is answered with the original job rather than run
+    // again, even if that job has finished.
+    if let Some(key) = &idempotency_key {
+        let existing = state
+            .jobs
+            .by_idempotency_key(key)
+            .await
+            .map_err(|e| AppError::Store(e.to_string()))?;
+        if let Some(job) = existing {
+            return repeated(job, &system_id, action);
+        }
+    }
+    let guard = claim(&state, &system_id, action).await?;
+    check_rate_limit(&state, &system_id).await?;
+    // A held action waits in its job, so the request is answered straight
+    // away.
+    if !state.flap_guard.delays() {
+        check_flap(&state, &system_id, action).await?;
+    }
+    let created = state
+        .jobs
+        .create(&system_id, action, idempotency_key)
+        .await
+        .map_err(|e| AppError::Store(e.to_string()))?;
+    match created {
+        Ok(job) => {
+            spawn_job(state, job.clone(), guard);
+            Ok(accepted(job))
+        }
+        // Another request with the key created its job in the meantime.
+        Err(job) => repeated(job, &system_id, action),
+    }
+}
 
-    #[derive(Clone)]
-    struct FakeUnifi {}
+/// Answers a repeated request with the job its idempotency key created, if
+/// that job is for the same machine and action.
+fn repeated(job: Job, system_id: &str, action: PowerAction) -> Result<Response, AppError> {
+    if job.system_id != system_id || job.action != action {
+        return Err(AppError::Unprocessable(format!(
+            "The idempotency key was used for {} of {}",
+            job.action.as_str(),
+            job.system_id
+        )));
+    }
+    Ok(accepted(job))
+}
 
-    #[async_trait]
-    impl UnifiClient for FakeUnifi {
-        async fn login(&self, _: &str, _: &str) -> anyhow::Result<()> {
+/// Claims the machine and runs a power action, answering once it has finished.
+pub(crate) async fn power_action_now(
+    state: AppState,
+    system_id: String,
+    action: PowerAction,
+) -> Result<PowerActionResult, AppError> {
+    if !state.leadership.is_leader() {
+        return Err(AppError::Standby(state.leadership.lease_ttl()));
+    }
+    let _guard = claim(&state, &system_id, action).await?;
+    check_rate_limit(&state, &system_id).await?;
+    check_flap(&state, &system_id, action).await?;
+    run_power_action(state, system_id, action).await
+}
+
+async fn claim(
+    state: &AppState,
+    system_id: &str,
+    action: PowerAction,
+) -> Result<InFlightGuard, AppError> {
+    state
+        .in_flight
+        .claim(system_id, action)
+        .await
+        .map_err(|e| AppError::Store(e.to_string()))?
+        .map_err(AppError::Conflict)
+}
+
+async fn check_rate_limit(state: &AppState, system_id: &str) -> Result<(), AppError> {
+    let limited = state
+        .rate_limiter
+        .check(system_id)
+        .await
+        .map_err(|e| AppError::Store(e.to_string()))?;
+    match limited {
+        Some(retry_after) => Err(AppError::RateLimited(retry_after)),
+        None => Ok(()),
+    }
+}
+
+/// Refuses an action that would reverse the machine's last transition too
+/// soon, or holds it until it is allowed.
+async fn check_flap(
+    state: &AppState,
+    system_id: &str,
+    action: PowerAction,
+) -> Result<(), AppError> {
+    let Some(min_interval) = anti_flap_interval(state, system_id) else {
+        return Ok(());
+    };
+    let flap = state
+        .flap_guard
+        .check(system_id, action, min_interval)
+        .await
+        .map_err(|e| AppError::Store(e.to_string()))?;
+    match flap {
+        Some(flap) if state.flap_guard.delays() => {
+            tracing::info!(
+                "holding {} of {system_id} for {}s, it was powered {} too recently",
+                action.as_str(),
+                retry_after_secs(flap.retry_after),
+                if flap.last_on { "on" } else { "off" }
+            );
+            tokio::time::sleep(flap.retry_after).await;
             Ok(())
         }
+        Some(flap) => Err(AppError::Flapping(flap)),
+        None => Ok(()),
+    }
+}
 
-        async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<unifi::models::Device>>> {
-            Ok(UnifiResponse {
-                meta: Meta { rc: "".to_owned() },
-                data: vec![unifi::models::Device {
-                    mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
-                    device_id: DeviceId::new(MAAS_SYSTEM_ID),
-                    port_table: vec![Port {
-                        port_idx: MACHINE_PORT,
-                        poe_mode: Some(PoeMode::Auto),
-                    }],
-                }],
-            })
+fn anti_flap_interval(state: &AppState, system_id: &str) -> Option<Duration> {
+    let target = state.backends.resolve(system_id)?;
+    state
+        .config
+        .load()
+        .anti_flap_secs(&target.machine)
+        .map(Duration::from_secs)
+}
+
+fn accepted(job: Job) -> Response {
+    let location = format!("/jobs/{}", job.id);
+    (StatusCode::ACCEPTED, [(LOCATION, location)], Json(job)).into_response()
+}
+
+fn spawn_job(state: AppState, job: Job, guard: InFlightGuard) {
+    tokio::spawn(async move {
+        let _guard = guard;
+        let jobs = state.jobs.clone();
+        jobs.update(&job.id, JobStatus::Running, None).await;
+        let result = match check_flap(&state, &job.system_id, job.action).await {
+            Ok(()) => run_power_action(state, job.system_id, job.action).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => jobs.update(&job.id, JobStatus::Succeeded, None).await,
+            Err(e) => {
+                let error = Some(e.status_and_message().1);
+                jobs.update(&job.id, JobStatus::Failed, error).await
+            }
         }
+    });
+}
 
-        async fn power_on(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
-            Ok(UnifiResponse {
-                data: (),
-                ..Default::default()
-            })
+/// Picks up the jobs that were queued or running when the service stopped.
+/// Power on and off are safe to repeat, but a cycle that was already running
+/// may have cut the power, so it is failed rather than cycling twice.
+pub async fn resume_jobs(state: &AppState) -> anyhow::Result<()> {
+    for job in state.jobs.unfinished().await? {
+        if job.status == JobStatus::Running && job.action == PowerAction::Cycle {
+            let error = "Interrupted by a restart, the machine may have been powered off";
+            state
+                .jobs
+                .update(&job.id, JobStatus::Failed, Some(error.to_owned()))
+                .await;
+            continue;
         }
+        match claim(state, &job.system_id, job.action).await {
+            Ok(guard) => {
+                tracing::info!("resuming {} job {}", job.action.as_str(), job.id);
+                spawn_job(state.clone(), job, guard);
+            }
+            Err(e) => {
+                let error = e.status_and_message().1;
+                state
+                    .jobs
+                    .update(&job.id, JobStatus::Failed, Some(error))
+                    .await;
+            }
+        }
+    }
+    Ok(())
+}
 
-        async fn power_off(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
-            Ok(UnifiResponse {
-                data: (),
-                ..Default::default()
-            })
+async fn job(
+    Extension(AppState { jobs, .. }): Extension<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Job>, AppError> {
+    jobs.get(&id)
+        .await
+        .map_err(|e| AppError::Store(e.to_string()))?
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Job {id} was not found")))
+}
+
+/// Runs a power action through the machine's backend, along with its hooks,
+/// notifications and the power on watchdog.
+async fn run_power_action(
+    AppState {
+        config,
+        backends,
+        metrics,
+        notifier,
+        store,
+        flap_guard,
+        ..
+    }: AppState,
+    system_id: String,
+    action: PowerAction,
+) -> Result<PowerActionResult, AppError> {
+    let config = config.load();
+    let powers_off = action != PowerAction::On;
+    let powers_on = action != PowerAction::Off;
+    let start = Instant::now();
+    let mut previous_status = None;
+    let result = async {
+        let target = backends
+            .resolve(&system_id)
+            .ok_or(UnifiError::MachineNotFound(system_id.to_owned()))?;
+        let hooks = config.hooks(&target.machine);
+        let context = HookContext {
+            system_id: &system_id,
+            action,
+            backend_env: target.backend.hook_env(&target.machine),
+        };
+        previous_status = read_status(&target).await;
+        if let Some(command) = hooks.pre_power_off.as_ref().filter(|_| powers_off) {
+            run_hook(command, &context, hooks.timeout_secs)
+                .await
+                .map_err(|e| AppError::Hook(e.to_string()))?;
+        }
+        match action {
+            PowerAction::On => target.backend.power_on(&target.machine).await?,
+            PowerAction::Off => target.backend.power_off(&target.machine).await?,
+            // A machine that must stay off for a while, e.g. to discharge,
+            // is held off for as long within a cycle too.
+            PowerAction::Cycle => match config.anti_flap_secs(&target.machine) {
+                Some(secs) if secs > 0 => {
+                    target.backend.power_off(&target.machine).await?;
+                    tokio::time::sleep(Duration::from_secs(secs)).await;
+                    target.backend.power_on(&target.machine).await?
+                }
+                _ => target.backend.power_cycle(&target.machine).await?,
+            },
+        }
+        if let Some(command) = hooks.post_power_on.filter(|_| powers_on) {
+            spawn_post_power_on_hook(command, context, hooks.timeout_secs);
+        }
+        Ok(target)
+    }
+    .await;
+    notifier.notify(power_event(&system_id, action, &result));
+    match &result {
+        Ok(_) => {
+            tracing::info!(target: AUDIT_TARGET, "{} of {system_id} succeeded", action.as_str())
+        }
+        Err(e) => tracing::warn!(
+            target: AUDIT_TARGET,
+            "{} of {system_id} failed: {}",
+            action.as_str(),
+            e.status_and_message().1
+        ),
+    }
+    if backends.resolve(&system_id).is_some() {
+        let record = ActionRecord {
+            action: action.as_str().to_owned(),
+            success: result.is_ok(),
+            timestamp: SystemTime::now(),
+        };
+        if let Err(e) = store.record_power_action(&system_id, record).await {
+            tracing::warn!("failed to record power action for {system_id}: {e}");
+        }
+    }
+    let target = result?;
+    if let Some(secs) = config.anti_flap_secs(&target.machine) {
+        let recorded = flap_guard
+            .record(&system_id, action, Duration::from_secs(secs))
+            .await;
+        if let Err(e) = recorded {
+            tracing::warn!("failed to record power transition for {system_id}: {e}");
         }
     }
+    let status = read_status(&target).await;
+    let changed = match action {
+        PowerAction::Cycle => Some(true),
+        _ => previous_status
+            .as_ref()
+            .zip(status.as_ref())
+            .map(|(a, b)| a != b),
+    };
+    let result = PowerActionResult {
+        system_id,
+        action,
+        previous_status,
+        status,
+        changed,
+        duration_ms: start.elapsed().as_millis() as u64,
+    };
+    if let Some(watchdog) = config.watchdog.filter(|_| powers_on) {
+        watch_power_on(target, metrics, notifier, watchdog);
+    }
+    Ok(result)
+}
 
-    #[tokio::test]
-    async fn should_get_power_status() {
-        let config = Box::leak(Box::new(Config {
-            url: "".to_owned(),
-            devices: vec![config::Device {
-                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
-                machines: vec![Machine {
-                    maas_id: MAAS_SYSTEM_ID.to_owned(),
-                    port_id: MACHINE_PORT,
-                }],
-            }],
-        }));
-        let client = Box::new(FakeUnifi {});
-        let handler = UnifiHandler { client };
-        let state = AppState { config, handler };
-        let request = Request::builder()
-            .method(Method::GET)
-            .uri("/power-status")
-            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
-            .body(Body::empty())
-            .unwrap();
-        let mut response = routes(state).oneshot(request).await.unwrap();
-        let body = response.body_mut();
-        let power_status =
-            serde_json::from_slice::<PowerStatus>(&body::to_bytes(body).await.unwrap()).unwrap();
-        assert_eq!(response.status(), 200);
-        assert_eq!(power_status.status, "running");
+/// Runs the post power on hook in the background, as the port is already
+/// powered and the hook's result cannot change the answer. The result goes to
+/// the audit log.
+fn spawn_post_power_on_hook(command: String, context: HookContext, timeout_secs: Option<u64>) {
+    let system_id = context.system_id.to_owned();
+    let (action, backend_env) = (context.action, context.backend_env);
+    tokio::spawn(async move {
+        let context = HookContext {
+            system_id: &system_id,
+            action,
+            backend_env,
+        };
+        match run_hook(&command, &context, timeout_secs).await {
+            Ok(()) => tracing::info!(
+                target: AUDIT_TARGET,
+                "post power on hook of {system_id} succeeded"
+            ),
+            Err(e) => tracing::warn!(
+                target: AUDIT_TARGET,
+                "post power on hook of {system_id} failed: {e}"
+            ),
+        }
+    });
+}
+
+/// The machine's status, `None` if it could not be read.
+async fn read_status(target: &Target) -> Option<String> {
+    match target.backend.status(&target.machine).await {
+        Ok(status) => Some(status.status),
+        Err(e) => {
+            tracing::debug!("failed to read status of {}: {e:?}", target.machine.maas_id);
+            None
+        }
     }
+}
 
-    #[tokio::test]
-    async fn should_power_on() {
-        let config = Box::leak(Box::new(Config {
-            url: "".to_owned(),
+/// What a power action did, so the caller need not read the status after it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PowerActionResult {
+    pub system_id: String,
+    pub action: PowerAction,
+    /// The status before the action, `None` if it could not be read.
+    pub previous_status: Option<String>,
+    /// The status after the action, `None` if it could not be read.
+    pub status: Option<String>,
+    /// Whether the action switched the power, `None` if a status is unknown.
+    /// A cycle always does.
+    pub changed: Option<bool>,
+    pub duration_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct WindowQuery {
+    window: Option<String>,
+}
+
+impl WindowQuery {
+    /// The window and when it started, counting back from `now`.
+    fn window(&self, now: SystemTime) -> Result<(Duration, SystemTime), AppError> {
+        let window = self.window.as_deref().unwrap_or(DEFAULT_WINDOW);
+        let duration = humantime::parse_duration(window)
+            .map_err(|e| AppError::BadRequest(format!("Invalid window `{window}`: {e}")))?;
+        let since = now
+            .checked_sub(duration)
+            .ok_or_else(|| AppError::BadRequest(format!("The window `{window}` is too long")))?;
+        Ok((duration, since))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PowerHistory {
+    pub system_id: String,
+    pub window_secs: u64,
+    pub samples: Vec<PowerHistorySample>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PowerHistorySample {
+    pub timestamp: String,
+    pub watts: f64,
+}
+
+async fn power_history(
+    Extension(AppState {
+        backends, store, ..
+    }): Extension<AppState>,
+    Path(system_id): Path<String>,
+    Query(query): Query<WindowQuery>,
+) -> Result<Json<PowerHistory>, AppError> {
+    backends
+        .resolve(&system_id)
+        .ok_or(UnifiError::MachineNotFound(system_id.to_owned()))?;
+    let (window, since) = query.window(SystemTime::now())?;
+    let samples = store
+        .power_samples(&system_id, since)
+        .await
+        .map_err(|e| AppError::Store(e.to_string()))?
+        .into_iter()
+        .map(|sample| PowerHistorySample {
+            timestamp: humantime::format_rfc3339_seconds(sample.timestamp).to_string(),
+            watts: sample.watts,
+        })
+        .collect();
+    Ok(Json(PowerHistory {
+        system_id,
+        window_secs: window.as_secs(),
+        samples,
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Stats {
+    pub window_secs: u64,
+    pub machines: Vec<MachineStats>,
+}
+
+async fn stats(
+    Extension(AppState {
+        backends, store, ..
+    }): Extension<AppState>,
+    Query(query): Query<WindowQuery>,
+) -> Result<Json<Stats>, AppError> {
+    let now = SystemTime::now();
+    let (window, since) = query.window(now)?;
+    let mut system_ids: Vec<_> = backends
+        .targets()
+        .into_iter()
+        .map(|target| target.machine.maas_id)
+        .collect();
+    system_ids.sort();
+    let mut machines = Vec::with_capacity(system_ids.len());
+    for system_id in system_ids {
+        let actions = store
+            .power_actions(&system_id, since)
+            .await
+            .map_err(|e| AppError::Store(e.to_string()))?;
+        let samples = store
+            .power_samples(&system_id, since)
+            .await
+            .map_err(|e| AppError::Store(e.to_string()))?;
+        machines.push(machine_stats(&system_id, &actions, &samples, since, now));
+    }
+    Ok(Json(Stats {
+        window_secs: window.as_secs(),
+        machines,
+    }))
+}
+
+async fn admin_state(
+    Extension(AppState {
+        config,
+        backends,
+        store,
+        controller,
+        in_flight,
+        ..
+    }): Extension<AppState>,
+) -> Json<StateSnapshot> {
+    let config = config.load();
+    Json(take_snapshot(&config.url, &controller, &backends, &store, &in_flight).await)
+}
+
+#[derive(Deserialize)]
+struct BackupQuery {
+    #[serde(default)]
+    state: bool,
+}
+
+async fn admin_backup(
+    Extension(AppState { config, store, .. }): Extension<AppState>,
+    Query(query): Query<BackupQuery>,
+) -> Result<Json<Backup>, AppError> {
+    let config = config.load();
+    let state = if query.state {
+        let state = store
+            .export_state()
+            .await
+            .map_err(|e| AppError::Store(e.to_string()))?;
+        Some(state)
+    } else {
+        None
+    };
+    Ok(Json(Backup::new(&config, state)))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RestoreReport {
+    pub devices: usize,
+    pub machines: usize,
+    pub state_restored: bool,
+    /// Restored mappings are written to the config file and take effect on the
+    /// next restart, restored state takes effect immediately.
+    pub restart_required: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogFilterBody {
+    /// Comma separated `target=level` directives.
+    filter: String,
+    /// Go back to the previous filter after this long.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reset_after_secs: Option<u64>,
+}
+
+async fn admin_logging(
+    Extension(AppState { log_filter, .. }): Extension<AppState>,
+) -> Json<LogFilterBody> {
+    Json(LogFilterBody {
+        filter: log_filter.current(),
+        reset_after_secs: None,
+    })
+}
+
+async fn admin_set_logging(
+    Extension(AppState { log_filter, .. }): Extension<AppState>,
+    Json(body): Json<LogFilterBody>,
+) -> Result<Json<LogFilterBody>, AppError> {
+    let previous = log_filter.current();
+    log_filter
+        .set(&body.filter)
+        .map_err(|e| AppError::BadRequest(format!("Invalid log filter `{}`: {e}", body.filter)))?;
+    tracing::info!("log filter set to `{}`", body.filter);
+    if let Some(secs) = body.reset_after_secs {
+        log_filter.reset_after(Duration::from_secs(secs), previous);
+    }
+    Ok(Json(body))
+}
+
+async fn admin_restore(
+    Extension(AppState {
+        config,
+        store,
+        config_file,
+        ..
+    }): Extension<AppState>,
+    Json(backup): Json<Backup>,
+) -> Result<Json<RestoreReport>, AppError> {
+    let config = config.load();
+    // A restore rewrites the config file, so it is never left open.
+    if config.auth.is_none() {
+        return Err(AppError::Forbidden(
+            "Restoring a backup needs `[auth]` to be configured".to_owned(),
+        ));
+    }
+    backup
+        .validate(&config.url)
+        .map_err(|e| AppError::BadRequest(format!("Invalid backup: {e}")))?;
+    let changed = backup.privileged_changes(&config);
+    if !changed.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "The backup changes the hooks or options of {}, change them in the config file instead",
+            changed.join(", ")
+        )));
+    }
+    let config_file = config_file.ok_or_else(|| {
+        AppError::Unsupported(
+            "The config was not read from a single file so there is no file to restore to"
+                .to_owned(),
+        )
+    })?;
+    backup
+        .write_config_file(&config_file)
+        .await
+        .map_err(|e| AppError::Restore(e.to_string()))?;
+    let report = RestoreReport {
+        devices: backup.devices.len(),
+        machines: backup.machines.len()
+            + backup
+                .devices
+                .iter()
+                .map(|device| device.machines.len())
+                .sum::<usize>(),
+        state_restored: backup.state.is_some(),
+        restart_required: true,
+    };
+    if let Some(state) = backup.state {
+        store
+            .import_state(state)
+            .await
+            .map_err(|e| AppError::Restore(e.to_string()))?;
+    }
+    Ok(Json(report))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MachineSummary {
+    pub system_id: String,
+    pub driver: Driver,
+    /// `None` if the status could not be read.
+    pub status: Option<String>,
+}
+
+/// Every machine with its power status, the statuses are read concurrently.
+async fn machines(
+    Extension(AppState { backends, .. }): Extension<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let mut targets = backends.targets();
+    targets.sort_by(|a, b| a.machine.maas_id.cmp(&b.machine.maas_id));
+    let statuses = targets.iter().map(|target| async move {
+        match target.backend.status(&target.machine).await {
+            Ok(status) => Some(status.status),
+            Err(e) => {
+                tracing::warn!("failed to read status of {}: {e:?}", target.machine.maas_id);
+                None
+            }
+        }
+    });
+    let statuses = futures::future::join_all(statuses).await;
+    let machines: Vec<_> = targets
+        .iter()
+        .zip(statuses)
+        .map(|(target, status)| MachineSummary {
+            system_id: target.machine.maas_id.clone(),
+            driver: target.machine.driver,
+            status,
+        })
+        .collect();
+    json_with_etag(&headers, machines)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeviceSummary {
+    pub mac: String,
+    /// The device's name on the controller.
+    pub name: Option<String>,
+    /// Whether the controller lists the device and can reach it.
+    pub reachable: bool,
+    /// In degrees Celsius, `None` if the device has no sensor.
+    pub temperature: Option<f64>,
+    pub overheating: Option<bool>,
+    pub fan_level: Option<u32>,
+    pub ports: Vec<PortSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PortSummary {
+    pub port_id: usize,
+    pub system_id: String,
+    /// The PoE state of the port, `None` if the controller does not report it.
+    pub status: Option<String>,
+}
+
+/// Every configured UniFi device and its mapped ports, as the controller sees
+/// them, from a single device list.
+async fn devices(
+    Extension(AppState {
+        config, controller, ..
+    }): Extension<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let config = config.load();
+    let controller_devices = controller.devices().await?;
+    let devices: Vec<_> = config
+        .devices
+        .iter()
+        .map(|device| {
+            let found = controller_devices
+                .iter()
+                .find(|controller_device| controller_device.mac == device.mac);
+            DeviceSummary {
+                mac: device.mac.to_string(),
+                name: found.and_then(|found| found.name.clone()),
+                reachable: found.is_some_and(|found| found.is_connected()),
+                temperature: found.and_then(|found| found.general_temperature),
+                overheating: found.and_then(|found| found.overheating),
+                fan_level: found.and_then(|found| found.fan_level),
+                ports: device
+                    .machines
+                    .iter()
+                    .map(|machine| PortSummary {
+                        port_id: machine.port_id,
+                        system_id: machine.maas_id.clone(),
+                        status: found
+                            .and_then(|found| found.power_status(machine.port_id))
+                            .map(|status| status.status),
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    Ok(json_with_etag(&headers, devices))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PortEntry {
+    pub port_id: usize,
+    pub name: Option<String>,
+    pub poe_mode: Option<PoeMode>,
+    /// Power drawn through the port in watts.
+    pub poe_watts: Option<f64>,
+    /// The MACs of the clients the controller learned on the port.
+    pub clients: Vec<String>,
+    /// The machine mapped to the port, if any.
+    pub system_id: Option<String>,
+}
+
+/// The live port table of one configured device, to help map new machines.
+async fn device_ports(
+    Extension(AppState {
+        config, controller, ..
+    }): Extension<AppState>,
+    Path(mac): Path<String>,
+) -> Result<Json<Vec<PortEntry>>, AppError> {
+    let config = config.load();
+    let device = configured_device(&config, &mac)?;
+    let mac = device.mac;
+    let (controller_devices, stations) =
+        tokio::try_join!(controller.devices(), controller.clients())?;
+    let controller_device = controller_devices
+        .into_iter()
+        .find(|controller_device| controller_device.mac == mac)
+        .ok_or(UnifiError::DeviceNotFound(mac.to_string()))?;
+    let ports = controller_device
+        .port_table
+        .into_iter()
+        .map(|port| PortEntry {
+            port_id: port.port_idx,
+            clients: stations
+                .iter()
+                .filter(|station| {
+                    station.sw_mac == Some(mac) && station.sw_port == Some(port.port_idx)
+                })
+                .map(|station| station.mac.to_string())
+                .collect(),
+            system_id: device
+                .machines
+                .iter()
+                .find(|machine| machine.port_id == port.port_idx)
+                .map(|machine| machine.maas_id.clone()),
+            name: port.name,
+            poe_mode: port.poe_mode,
+            poe_watts: port.poe_power,
+        })
+        .collect();
+    Ok(Json(ports))
+}
+
+/// The device in the mapping with the MAC address `mac`.
+fn configured_device<'a>(config: &'a Config, mac: &str) -> Result<&'a Device, AppError> {
+    let mac = MacAddress::from_str(mac)
+        .map_err(|_| AppError::BadRequest(format!("`{mac}` is not a MAC address")))?;
+    config
+        .devices
+        .iter()
+        .find(|device| device.mac == mac)
+        .ok_or_else(|| AppError::NotFound(format!("Device {mac} is not configured")))
+}
+
+/// How much of a switch's PoE budget is in use, in watts. The budget and
+/// what follows from it are `null` if the switch does not report one.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PoeBudget {
+    pub mac: String,
+    pub budget_watts: Option<f64>,
+    pub draw_watts: f64,
+    pub headroom_watts: Option<f64>,
+    /// The share of the budget drawn, from 0 to 1.
+    pub utilization: Option<f64>,
+}
+
+async fn device_poe_budget(
+    Extension(AppState {
+        config, controller, ..
+    }): Extension<AppState>,
+    Path(mac): Path<String>,
+) -> Result<Json<PoeBudget>, AppError> {
+    let config = config.load();
+    let mac = configured_device(&config, &mac)?.mac;
+    let device = controller
+        .devices()
+        .await?
+        .into_iter()
+        .find(|controller_device| controller_device.mac == mac)
+        .ok_or(UnifiError::DeviceNotFound(mac.to_string()))?;
+    let budget = device.total_max_power.filter(|budget| *budget > 0.0);
+    let draw = device.poe_watts();
+    Ok(Json(PoeBudget {
+        mac: mac.to_string(),
+        budget_watts: budget,
+        draw_watts: draw,
+        headroom_watts: budget.map(|budget| (budget - draw).max(0.0)),
+        utilization: budget.map(|budget| draw / budget),
+    }))
+}
+
+/// An event the controller logged about a configured switch or one of its
+/// mapped ports.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ControllerEventEntry {
+    pub timestamp: String,
+    /// The controller's event key, e.g. `EVT_SW_PoeOverload`.
+    pub key: String,
+    pub message: Option<String>,
+    pub mac: String,
+    /// `null` for events about the whole switch.
+    pub port_id: Option<usize>,
+    pub system_id: Option<String>,
+}
+
+/// The controller's recent events about configured switches and mapped ports,
+/// newest first, to read next to the power actions that caused them.
+async fn controller_events(
+    Extension(AppState {
+        config, controller, ..
+    }): Extension<AppState>,
+) -> Result<Json<Vec<ControllerEventEntry>>, AppError> {
+    let config = config.load();
+    let events = controller
+        .events()
+        .await?
+        .into_iter()
+        .filter_map(|event| {
+            let device = config
+                .devices
+                .iter()
+                .find(|device| Some(device.mac) == event.sw)?;
+            let machine = match event.port {
+                Some(port_id) => Some(
+                    device
+                        .machines
+                        .iter()
+                        .find(|machine| machine.port_id == port_id)?,
+                ),
+                None => None,
+            };
+            let time = UNIX_EPOCH + Duration::from_millis(event.time);
+            Some(ControllerEventEntry {
+                timestamp: humantime::format_rfc3339_seconds(time).to_string(),
+                key: event.key,
+                message: event.msg,
+                mac: device.mac.to_string(),
+                port_id: event.port,
+                system_id: machine.map(|machine| machine.maas_id.clone()),
+            })
+        })
+        .collect();
+    Ok(Json(events))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Readiness {
+    pub ready: bool,
+    pub problems: Vec<String>,
+    pub controller: ControllerHealth,
+}
+
+/// Ready once the controller can be reached and every mapped device and port
+/// exists on it, as of a check at most a few seconds old.
+async fn readyz(
+    Extension(AppState {
+        config,
+        controller,
+        reconciled,
+        ..
+    }): Extension<AppState>,
+) -> (StatusCode, Json<Readiness>) {
+    let config = config.load();
+    let problems = reconciled.problems(&config, &controller).await;
+    let status = if problems.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let readiness = Readiness {
+        ready: problems.is_empty(),
+        problems,
+        controller: controller.health(),
+    };
+    (status, Json(readiness))
+}
+
+/// Responds with 422 when the config is invalid so pipelines can fail on the
+/// status alone.
+async fn admin_validate_config(
+    Extension(AppState {
+        config, controller, ..
+    }): Extension<AppState>,
+    config_toml: String,
+) -> (StatusCode, Json<ValidationReport>) {
+    let url = config.load().redacted_url();
+    let report = validate_config(&config_toml, &controller, &url).await;
+    let status = if report.valid {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    (status, Json(report))
+}
+
+fn power_event<T>(
+    system_id: &str,
+    action: PowerAction,
+    result: &Result<T, AppError>,
+) -> PowerEvent {
+    match result {
+        Ok(_) => PowerEvent::new(system_id, action, EventResult::Success),
+        Err(e) => PowerEvent::new(system_id, action, EventResult::Failure)
+            .with_error(e.status_and_message().1),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use arc_swap::ArcSwap;
+
+    use crate::{
+        auth::Authenticator,
+        backend::BackendRegistry,
+        config::{
+            self, AuthConfig, BasicAuthConfig, Config, HooksConfig, Machine, Role, StatsdFlavor,
+            TokenConfig,
+        },
+        flap_guard::FlapGuard,
+        in_flight::InFlight,
+        jobs::{Job, JobStatus, Jobs},
+        leader::Leadership,
+        logging::LogFilter,
+        metrics::{
+            test_sink::{received, statsd},
+            Metrics,
+        },
+        notifications::{Notifier, PowerAction},
+        rate_limit::RateLimiter,
+        router::{
+            resume_jobs, routes, AppError, AppState, ControllerEventEntry, DeviceSummary,
+            MachineSummary, PoeBudget, PortEntry, PowerActionResult, PowerHistory, PowerStatus,
+            Readiness, RestoreReport, Stats, UnknownNics,
+        },
+        sessions::Sessions,
+        shared_state::SharedState,
+        snapshot::StateSnapshot,
+        store::{PowerSample, Store},
+        unifi::{
+            self,
+            client::{ControllerApiError, UnifiClient, UnifiError},
+            handler::UnifiHandler,
+            mock::MockUnifiClient,
+            models::{ControllerEvent, DeviceId, Meta, PoeMode, Port, Station, UnifiResponse},
+        },
+        validation::{Reconciled, ValidationReport},
+    };
+    use async_trait::async_trait;
+    use http::{Method, Request};
+    use hyper::{body, Body};
+    use mac_address::MacAddress;
+    use serde_json::{json, Value};
+    use std::{
+        str::FromStr,
+        sync::Arc,
+        time::{Duration, Instant, SystemTime},
+    };
+    use tower::ServiceExt;
+    use wiremock::{
+        matchers::{body_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const UNIFI_DEVICE_MAC: &str = "00-00-00-00-00-00";
+    const MAAS_SYSTEM_ID_HEADER: &str = "system_id";
+    const MAAS_SYSTEM_ID: &str = "system-id";
+    const MACHINE_PORT: usize = 1;
+    const MACHINE_NIC_MAC: &str = "AA:BB:CC:00:00:01";
+
+    #[derive(Clone, Default)]
+    struct FakeUnifi {
+        /// How long the controller takes to answer.
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl UnifiClient for FakeUnifi {
+        async fn login(&self, _: &str, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<unifi::models::Device>>> {
+            tokio::time::sleep(self.delay).await;
+            Ok(UnifiResponse {
+                meta: Meta {
+                    rc: "".to_owned(),
+                    ..Default::default()
+                },
+                data: vec![unifi::models::Device {
+                    mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                    device_id: DeviceId::new(MAAS_SYSTEM_ID),
+                    port_table: vec![Port {
+                        port_idx: MACHINE_PORT,
+                        poe_mode: Some(PoeMode::Auto),
+                        poe_power: Some(15.0),
+                        ..Default::default()
+                    }],
+                    name: Some("rack-1".to_owned()),
+                    state: Some(1),
+                    total_max_power: Some(60.0),
+                    general_temperature: Some(52.5),
+                    overheating: Some(false),
+                    ..Default::default()
+                }],
+            })
+        }
+
+        async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+            Ok(UnifiResponse {
+                data: vec![Station {
+                    mac: MacAddress::from_str(MACHINE_NIC_MAC).unwrap(),
+                    sw_mac: Some(MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap()),
+                    sw_port: Some(MACHINE_PORT),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+        }
+
+        async fn events(&self) -> anyhow::Result<UnifiResponse<Vec<ControllerEvent>>> {
+            let switch = MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap();
+            Ok(UnifiResponse {
+                data: vec![
+                    ControllerEvent {
+                        key: "EVT_SW_PoeOverload".to_owned(),
+                        time: 1_700_000_000_000,
+                        sw: Some(switch),
+                        port: Some(MACHINE_PORT),
+                        ..Default::default()
+                    },
+                    ControllerEvent {
+                        key: "EVT_SW_PoeOverload".to_owned(),
+                        time: 1_700_000_000_000,
+                        sw: Some(switch),
+                        port: Some(MACHINE_PORT + 1),
+                        ..Default::default()
+                    },
+                    ControllerEvent {
+                        key: "EVT_AD_Login".to_owned(),
+                        time: 1_700_000_000_000,
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            })
+        }
+
+        async fn power_on(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            tokio::time::sleep(self.delay).await;
+            Ok(UnifiResponse {
+                data: (),
+                ..Default::default()
+            })
+        }
+
+        async fn power_off(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            Ok(UnifiResponse {
+                data: (),
+                ..Default::default()
+            })
+        }
+    }
+
+    pub(crate) fn app_state(config: Config) -> AppState {
+        app_state_with(config, FakeUnifi::default())
+    }
+
+    fn app_state_with(
+        config: Config,
+        client: impl UnifiClient + Send + Sync + 'static,
+    ) -> AppState {
+        let client = Box::new(client);
+        let handler = UnifiHandler::new(client);
+        let store = Store::open(None).unwrap();
+        AppState {
+            backends: BackendRegistry::new(&config, handler.clone()).unwrap(),
+            metrics: Metrics::default(),
+            notifier: Notifier::default(),
+            controller: handler,
+            config_file: None,
+            in_flight: InFlight::default(),
+            jobs: Jobs::new(store.clone()),
+            store,
+            leadership: Leadership::default(),
+            rate_limiter: RateLimiter::default(),
+            flap_guard: FlapGuard::default(),
+            log_filter: LogFilter::default(),
+            sessions: Sessions::default(),
+            authenticator: Authenticator::new(config.auth.as_ref()).unwrap(),
+            unknown_nics: UnknownNics::default(),
+            reconciled: Reconciled::default(),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_get_power_status() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/power-status")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let power_status =
+            serde_json::from_slice::<PowerStatus>(&body::to_bytes(body).await.unwrap()).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(power_status.status, "running");
+    }
+
+    #[tokio::test]
+    async fn should_time_out_at_the_client_deadline() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let slow = FakeUnifi {
+            delay: Duration::from_millis(200),
+        };
+        let state = app_state_with(config, slow);
+        let request = |method: Method, uri: &str, timeout: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+                .header("x-request-timeout", timeout)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = routes(state.clone())
+            .oneshot(request(Method::GET, "/power-status", "0.05"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 504);
+        let response = routes(state.clone())
+            .oneshot(request(Method::POST, "/power-on", "0.05"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 504);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("carries on"));
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let last = state.store.last_power_action(MAAS_SYSTEM_ID).await.unwrap();
+        assert!(last.is_some());
+        let response = routes(state)
+            .oneshot(request(Method::GET, "/power-status", "soon"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_finish_power_actions_but_not_reads_when_the_client_leaves() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let slow = FakeUnifi {
+            delay: Duration::from_millis(200),
+        };
+        let (server, metrics) = statsd(StatsdFlavor::Dogstatsd, &[]);
+        let state = AppState {
+            metrics,
+            ..app_state_with(config, slow)
+        };
+        for (method, uri) in [(Method::GET, "/power-status"), (Method::POST, "/power-on")] {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+                .body(Body::empty())
+                .unwrap();
+            let gone = tokio::time::timeout(
+                Duration::from_millis(50),
+                routes(state.clone()).oneshot(request),
+            );
+            assert!(gone.await.is_err());
+        }
+        let cancelled = "maas.http_requests_cancelled:1|c|#route:/power-status".to_owned();
+        assert!(received(&server).contains(&cancelled));
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let last = state.store.last_power_action(MAAS_SYSTEM_ID).await.unwrap();
+        assert!(last.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_power_on() {
+        let config = Config {
+            url: "".to_owned(),
             devices: vec![config::Device {
                 mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
                 machines: vec![Machine {
                     maas_id: MAAS_SYSTEM_ID.to_owned(),
                     port_id: MACHINE_PORT,
+                    ..Default::default()
                 }],
             }],
-        }));
-        let client = Box::new(FakeUnifi {});
-        let handler = UnifiHandler { client };
-        let state = AppState { config, handler };
+            ..Default::default()
+        };
+        let state = app_state(config);
         let request = Request::builder()
             .method(Method::POST)
             .uri("/power-on")
             .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
             .body(Body::empty())
             .unwrap();
-        let response = routes(state).oneshot(request).await.unwrap();
+        let mut response = routes(state).oneshot(request).await.unwrap();
         assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["location"], "/power-status");
+        let body = body::to_bytes(response.body_mut()).await.unwrap();
+        let result = serde_json::from_slice::<PowerActionResult>(&body).unwrap();
+        assert_eq!(result.action, PowerAction::On);
+        assert_eq!(result.previous_status.as_deref(), Some("running"));
+        assert_eq!(result.status.as_deref(), Some("running"));
+        assert_eq!(result.changed, Some(false));
     }
 
     #[tokio::test]
     async fn should_power_off() {
-        let config = Box::leak(Box::new(Config {
+        let config = Config {
             url: "".to_owned(),
             devices: vec![config::Device {
                 mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
                 machines: vec![Machine {
                     maas_id: MAAS_SYSTEM_ID.to_owned(),
                     port_id: MACHINE_PORT,
+                    ..Default::default()
                 }],
             }],
-        }));
-        let client = Box::new(FakeUnifi {});
-        let handler = UnifiHandler { client };
-        let state = AppState { config, handler };
+            ..Default::default()
+        };
+        let state = app_state(config);
         let request = Request::builder()
             .method(Method::POST)
             .uri("/power-off")
@@ -298,4 +2145,1333 @@ mod test {
         let response = routes(state).oneshot(request).await.unwrap();
         assert_eq!(response.status(), 200);
     }
+
+    #[tokio::test]
+    async fn should_power_cycle() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-cycle")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_not_power_off_if_pre_power_off_hook_fails() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            hooks: HooksConfig {
+                pre_power_off: Some("exit 1".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let client = MockUnifiClient::new(&config);
+        let port_on = |client: MockUnifiClient| async move {
+            let devices = client.devices().await.unwrap().data;
+            devices[0].port(MACHINE_PORT).unwrap().up
+        };
+        let device_id = client.devices().await.unwrap().data[0]
+            .device_id
+            .to_string();
+        client.power_on(&device_id, MACHINE_PORT).await.unwrap();
+        let state = app_state_with(config, client.clone());
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-off")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 500);
+        assert_eq!(port_on(client).await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn should_hold_a_cycled_machine_off_for_its_anti_flap_interval() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    anti_flap_secs: Some(1),
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let client = MockUnifiClient::new(&config);
+        let port_on = |client: MockUnifiClient| async move {
+            let devices = client.devices().await.unwrap().data;
+            devices[0].port(MACHINE_PORT).unwrap().up
+        };
+        let device_id = client.devices().await.unwrap().data[0]
+            .device_id
+            .to_string();
+        client.power_on(&device_id, MACHINE_PORT).await.unwrap();
+        let state = app_state_with(config, client.clone());
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-cycle")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let start = Instant::now();
+        let cycle = tokio::spawn(routes(state).oneshot(request));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(port_on(client.clone()).await, Some(false));
+        let response = cycle.await.unwrap().unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(port_on(client).await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn should_answer_before_the_post_power_on_hook_finishes() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            hooks: HooksConfig {
+                post_power_on: Some("sleep 10".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-on")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            routes(app_state(config)).oneshot(request),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_get_power_history() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let sample = PowerSample {
+            timestamp: SystemTime::now(),
+            watts: 4.5,
+        };
+        state
+            .store
+            .record_power_sample(MAAS_SYSTEM_ID, sample)
+            .await
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!(
+                "/machines/{MAAS_SYSTEM_ID}/power-history?window=1h"
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state.clone()).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let history =
+            serde_json::from_slice::<PowerHistory>(&body::to_bytes(body).await.unwrap()).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(history.window_secs, 3600);
+        assert_eq!(history.samples.len(), 1);
+        assert_eq!(history.samples[0].watts, 4.5);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!(
+                "/machines/{MAAS_SYSTEM_ID}/power-history?window=500000000000y"
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_get_stats_of_power_actions() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-cycle")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/stats?window=1h")
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let stats = serde_json::from_slice::<Stats>(&body::to_bytes(body).await.unwrap()).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(stats.window_secs, 3600);
+        assert_eq!(stats.machines.len(), 1);
+        assert_eq!(stats.machines[0].system_id, MAAS_SYSTEM_ID);
+        assert_eq!(stats.machines[0].power_cycles, 1);
+        assert_eq!(stats.machines[0].energy_wh, None);
+    }
+
+    #[tokio::test]
+    async fn should_export_state_snapshot() {
+        let config = Config {
+            url: "https://unifi".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-on")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        routes(state.clone()).oneshot(request).await.unwrap();
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/admin/state")
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let snapshot =
+            serde_json::from_slice::<StateSnapshot>(&body::to_bytes(body).await.unwrap()).unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(snapshot.controller.reachable);
+        assert_eq!(snapshot.controller.devices, 1);
+        let machine = &snapshot.machines[0];
+        assert_eq!(machine.status.as_deref(), Some("running"));
+        assert_eq!(machine.last_action.as_ref().unwrap().action, "power_on");
+        assert!(machine.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_restore_backup() {
+        let config = Config {
+            url: "https://unifi".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            auth: Some(AuthConfig {
+                basic: Some(BasicAuthConfig {
+                    username: "maas".to_owned(),
+                    password: "secret".to_owned(),
+                    role: Role::Power,
+                }),
+                tokens: Vec::new(),
+                jwt: None,
+            }),
+            ..Default::default()
+        };
+        let config_file = std::env::temp_dir().join(format!(
+            "maas-power-unifi-restore-{}.toml",
+            std::process::id()
+        ));
+        tokio::fs::write(&config_file, "url = \"https://unifi\"\ndevices = []\n")
+            .await
+            .unwrap();
+        let state = AppState {
+            config_file: Some(config_file.clone()),
+            ..app_state(config)
+        };
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/admin/backup?state=true")
+            // maas:secret
+            .header("authorization", "Basic bWFhczpzZWNyZXQ=")
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let backup = body::to_bytes(response.body_mut()).await.unwrap();
+        let restore = |backup: Vec<u8>| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/admin/restore")
+                .header("content-type", "application/json")
+                .header("authorization", "Basic bWFhczpzZWNyZXQ=")
+                .body(Body::from(backup))
+                .unwrap()
+        };
+        let mut hooked = serde_json::from_slice::<Value>(&backup).unwrap();
+        hooked["devices"][0]["machines"][0]["hooks"] = json!({"post_power_on": "id"});
+        let response = routes(state.clone())
+            .oneshot(restore(serde_json::to_vec(&hooked).unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let mut response = routes(state)
+            .oneshot(restore(backup.to_vec()))
+            .await
+            .unwrap();
+        let body = response.body_mut();
+        let report =
+            serde_json::from_slice::<RestoreReport>(&body::to_bytes(body).await.unwrap()).unwrap();
+        let restored = config::read_config_file(config_file.clone()).await.unwrap();
+        tokio::fs::remove_file(&config_file).await.unwrap();
+        assert_eq!(report.machines, 1);
+        assert!(report.state_restored);
+        assert_eq!(
+            restored.machine(MAAS_SYSTEM_ID).unwrap().port_id,
+            MACHINE_PORT
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_candidate_config() {
+        let config = Config::default();
+        let state = app_state(config);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/admin/validate-config")
+            .body(Body::from("url = \"https://unifi\"\ndevices = []\n[[machines]]\nmaas_id = \"a\"\ndriver = \"wol\"\n"))
+            .unwrap();
+        let mut response = routes(state).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let report =
+            serde_json::from_slice::<ValidationReport>(&body::to_bytes(body).await.unwrap())
+                .unwrap();
+        assert_eq!(response.status(), 422);
+        assert!(!report.valid);
+        assert_eq!(report.issues.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_not_be_ready_if_port_is_missing_on_controller() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT + 1,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/readyz")
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let readiness =
+            serde_json::from_slice::<Readiness>(&body::to_bytes(body).await.unwrap()).unwrap();
+        assert_eq!(response.status(), 503);
+        assert!(!readiness.ready);
+        assert_eq!(readiness.problems.len(), 1);
+        assert!(readiness.controller.healthy);
+        assert!(readiness.controller.last_device_fetch.is_some());
+        assert_eq!(readiness.controller.last_login, None);
+    }
+
+    #[tokio::test]
+    async fn should_conflict_with_action_in_progress() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let _guard = state
+            .in_flight
+            .claim(MAAS_SYSTEM_ID, PowerAction::Off)
+            .await
+            .unwrap()
+            .unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-on")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let body: serde_json::Value =
+            serde_json::from_slice(&body::to_bytes(body).await.unwrap()).unwrap();
+        assert_eq!(response.status(), 409);
+        assert_eq!(body["in_flight"]["action"], "power_off");
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_power_actions_per_machine() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = AppState {
+            rate_limiter: RateLimiter::new(
+                SharedState::default(),
+                Some(config::RateLimitConfig {
+                    max_actions: 1,
+                    window_secs: 60,
+                }),
+            ),
+            ..app_state(config)
+        };
+        let request = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/power-cycle")
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = routes(state.clone()).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let response = routes(state).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers()["retry-after"], "60");
+    }
+
+    #[tokio::test]
+    async fn should_refuse_power_on_right_after_power_off() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    anti_flap_secs: Some(30),
+                    ..Default::default()
+                }],
+            }],
+            anti_flap: Some(config::AntiFlapConfig {
+                min_interval_secs: 60,
+                on_violation: config::FlapViolation::Reject,
+            }),
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = |uri| {
+            Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = routes(state.clone())
+            .oneshot(request("/power-off"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = routes(state.clone())
+            .oneshot(request("/power-off"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let mut response = routes(state).oneshot(request("/power-on")).await.unwrap();
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers()["retry-after"], "30");
+        let body: serde_json::Value =
+            serde_json::from_slice(&body::to_bytes(response.body_mut()).await.unwrap()).unwrap();
+        assert_eq!(
+            body["anti_flap"],
+            serde_json::json!({"last_on": false, "min_interval_secs": 30, "retry_after_secs": 30})
+        );
+    }
+
+    #[tokio::test]
+    async fn should_list_machines_with_status() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let request = Request::builder()
+            .uri("/machines")
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(app_state(config)).oneshot(request).await.unwrap();
+        let body = response.body_mut();
+        let machines =
+            serde_json::from_slice::<Vec<MachineSummary>>(&body::to_bytes(body).await.unwrap())
+                .unwrap();
+        assert_eq!(machines.len(), 1);
+        assert_eq!(machines[0].system_id, MAAS_SYSTEM_ID);
+        assert!(machines[0].status.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_compress_machines_if_accepted() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let router = routes(app_state(config));
+        let request = Request::builder()
+            .uri("/machines")
+            .header("accept-encoding", "br;q=0.5, gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        let request = Request::builder()
+            .uri("/machines")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert!(!response.headers().contains_key("content-encoding"));
+    }
+
+    #[tokio::test]
+    async fn should_answer_unchanged_machines_with_not_modified() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let router = routes(app_state(config));
+        let request = Request::builder()
+            .uri("/machines")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let etag = response.headers()["etag"].clone();
+        let request = Request::builder()
+            .uri("/machines")
+            .header("if-none-match", etag)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 304);
+        assert!(body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_list_devices_as_the_controller_sees_them() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![
+                config::Device {
+                    mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                    machines: vec![Machine {
+                        maas_id: MAAS_SYSTEM_ID.to_owned(),
+                        port_id: MACHINE_PORT,
+                        ..Default::default()
+                    }],
+                },
+                config::Device {
+                    mac: MacAddress::from_str("11:11:11:11:11:11").unwrap(),
+                    machines: vec![Machine {
+                        maas_id: "missing".to_owned(),
+                        port_id: 2,
+                        ..Default::default()
+                    }],
+                },
+            ],
+            ..Default::default()
+        };
+        let request = Request::builder()
+            .uri("/devices")
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(app_state(config)).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let devices = serde_json::from_slice::<Vec<DeviceSummary>>(&body).unwrap();
+        assert_eq!(devices[0].name.as_deref(), Some("rack-1"));
+        assert!(devices[0].reachable);
+        assert_eq!(devices[0].temperature, Some(52.5));
+        assert_eq!(devices[0].overheating, Some(false));
+        assert_eq!(devices[0].fan_level, None);
+        assert_eq!(devices[0].ports[0].system_id, MAAS_SYSTEM_ID);
+        assert_eq!(devices[0].ports[0].status.as_deref(), Some("running"));
+        assert!(!devices[1].reachable);
+        assert_eq!(devices[1].ports[0].status, None);
+    }
+
+    #[tokio::test]
+    async fn should_list_the_ports_of_a_configured_device() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let router = routes(app_state(config));
+        let get = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = router
+            .clone()
+            .oneshot(get(format!("/devices/{UNIFI_DEVICE_MAC}/ports")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let ports = serde_json::from_slice::<Vec<PortEntry>>(&body).unwrap();
+        assert_eq!(ports[0].port_id, MACHINE_PORT);
+        assert_eq!(ports[0].poe_mode, Some(PoeMode::Auto));
+        assert_eq!(ports[0].clients, vec![MACHINE_NIC_MAC.to_owned()]);
+        assert_eq!(ports[0].system_id.as_deref(), Some(MAAS_SYSTEM_ID));
+        let response = router
+            .clone()
+            .oneshot(get("/devices/11:11:11:11:11:11/ports".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        let response = router
+            .oneshot(get("/devices/switch/ports".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_report_the_poe_budget_of_a_configured_device() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![],
+            }],
+            ..Default::default()
+        };
+        let request = Request::builder()
+            .uri(format!("/devices/{UNIFI_DEVICE_MAC}/poe-budget"))
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(app_state(config)).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let budget = serde_json::from_slice::<PoeBudget>(&body).unwrap();
+        assert_eq!(
+            budget,
+            PoeBudget {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap().to_string(),
+                budget_watts: Some(60.0),
+                draw_watts: 15.0,
+                headroom_watts: Some(45.0),
+                utilization: Some(0.25),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn should_list_controller_events_of_mapped_ports() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let request = Request::builder()
+            .uri("/events/controller")
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(app_state(config)).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let events = serde_json::from_slice::<Vec<ControllerEventEntry>>(&body).unwrap();
+        assert_eq!(
+            events,
+            vec![ControllerEventEntry {
+                timestamp: "2023-11-14T22:13:20Z".to_owned(),
+                key: "EVT_SW_PoeOverload".to_owned(),
+                message: None,
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap().to_string(),
+                port_id: Some(MACHINE_PORT),
+                system_id: Some(MAAS_SYSTEM_ID.to_owned()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_address_machines_by_nic_mac() {
+        let config = |machines| Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines,
+            }],
+            ..Default::default()
+        };
+        let status = |nic: &str| {
+            Request::builder()
+                .uri("/power-status")
+                .header("mac_address", nic)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let mapped = app_state(config(vec![Machine {
+            maas_id: MAAS_SYSTEM_ID.to_owned(),
+            port_id: MACHINE_PORT,
+            ..Default::default()
+        }]));
+        let response = routes(mapped.clone())
+            .oneshot(status(MACHINE_NIC_MAC))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(mapped.backends.targets().len(), 1);
+        let unmapped = app_state(config(vec![]));
+        let response = routes(unmapped.clone())
+            .oneshot(status(MACHINE_NIC_MAC))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        assert!(unmapped.backends.targets().is_empty());
+        let response = routes(unmapped.clone())
+            .oneshot(status("11:11:11:11:11:11"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        let unknown = MacAddress::from_str("11:11:11:11:11:11").unwrap();
+        assert!(unmapped.unknown_nics.contains(unknown));
+    }
+
+    #[tokio::test]
+    async fn should_check_and_apply_the_power_address() {
+        let config = Config {
+            url: "https://unifi.local:8443".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let status = |system_id: &str, address: &str| {
+            Request::builder()
+                .uri("/power-status")
+                .header(MAAS_SYSTEM_ID_HEADER, system_id)
+                .header("power_address", address)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let on_port =
+            |port: usize| format!("https://unifi.local:8443?device={UNIFI_DEVICE_MAC}&port={port}");
+        let response = routes(state.clone())
+            .oneshot(status(MAAS_SYSTEM_ID, &on_port(MACHINE_PORT)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = routes(state.clone())
+            .oneshot(status(MAAS_SYSTEM_ID, &on_port(2)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = routes(state.clone())
+            .oneshot(status(MAAS_SYSTEM_ID, "https://other.local:8443"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        // A status query does not register the machine, a power action does.
+        let free_port = MACHINE_PORT + 1;
+        let response = routes(state.clone())
+            .oneshot(status("unmapped", &on_port(free_port)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+        assert!(state.backends.resolve("unmapped").is_none());
+        let power_on = |system_id: &str, address: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/power-on")
+                .header(MAAS_SYSTEM_ID_HEADER, system_id)
+                .header("power_address", address)
+                .body(Body::empty())
+                .unwrap()
+        };
+        // The port of a mapped machine cannot be driven under another id.
+        let response = routes(state.clone())
+            .oneshot(power_on("unmapped", &on_port(MACHINE_PORT)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert!(state.backends.resolve("unmapped").is_none());
+        let response = routes(state.clone())
+            .oneshot(power_on("unmapped", &on_port(free_port)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let registered = state.backends.resolve("unmapped").unwrap();
+        let mapped = state.backends.resolve(MAAS_SYSTEM_ID).unwrap();
+        assert!(Arc::ptr_eq(&registered.backend, &mapped.backend));
+        let response = routes(state.clone())
+            .oneshot(power_on("unmapped", &on_port(MACHINE_PORT + 2)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            state.backends.resolve("unmapped").unwrap().machine.port_id,
+            free_port
+        );
+        let response = routes(state.clone())
+            .oneshot(power_on("another", &on_port(free_port)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert!(state.backends.resolve("another").is_none());
+    }
+
+    #[tokio::test]
+    async fn should_act_as_the_account_sent_with_the_request() {
+        let controller = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/login"))
+            .and(body_json(
+                json!({"username": "rack-1", "password": "secret"}),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&controller)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/login"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&controller)
+            .await;
+        let devices = json!({
+            "meta": {"rc": "ok"},
+            "data": [{
+                "mac": "00:00:00:00:00:00",
+                "device_id": "device-id",
+                "port_table": [{"port_idx": MACHINE_PORT, "poe_mode": "off"}],
+            }],
+        });
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(devices))
+            .mount(&controller)
+            .await;
+        let config = Config {
+            url: controller.uri(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = AppState {
+            sessions: Sessions::new(&config.url, &config.controller),
+            ..app_state(config)
+        };
+        let status = |credentials: &[(&str, &str)]| {
+            let request = Request::builder()
+                .uri("/power-status")
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID);
+            credentials
+                .iter()
+                .fold(request, |request, (name, value)| {
+                    request.header(*name, *value)
+                })
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = routes(state.clone())
+            .oneshot(status(&[
+                ("power_user", "rack-1"),
+                ("power_pass", "secret"),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let status_of = serde_json::from_slice::<PowerStatus>(&body).unwrap();
+        assert_eq!(status_of.status, "stopped");
+        let response = routes(state.clone())
+            .oneshot(status(&[("power_user", "rack-1"), ("power_pass", "wrong")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let response = routes(state)
+            .oneshot(status(&[("power_user", "rack-1")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_serve_power_status_on_configured_paths() {
+        let mut config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        config.routes.power_status = vec!["/power-query".to_owned(), "/status".to_owned()];
+        let router = routes(app_state(config));
+        for (uri, status) in [
+            ("/power-query", 200),
+            ("/status", 200),
+            ("/power-status", 404),
+        ] {
+            let request = Request::builder()
+                .uri(uri)
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    #[test]
+    fn should_map_controller_error_codes_to_statuses() {
+        let status = |code: &str| {
+            AppError::Power(UnifiError::Controller(ControllerApiError {
+                status: 400,
+                code: code.to_owned(),
+                msg: None,
+            }))
+            .status_and_message()
+        };
+        let (no_permission, message) = status("api.err.NoPermission");
+        assert_eq!(no_permission, 502);
+        assert!(message.contains("give it a role"), "{message}");
+        assert_eq!(status("api.err.InvalidPayload").0, 422);
+        assert_eq!(status("api.err.Something").0, 502);
+    }
+
+    #[tokio::test]
+    async fn should_require_credentials_and_the_role_for_each_route() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            auth: Some(AuthConfig {
+                basic: Some(BasicAuthConfig {
+                    username: "maas".to_owned(),
+                    password: "secret".to_owned(),
+                    role: Role::Power,
+                }),
+                tokens: vec![TokenConfig {
+                    token: "dashboard".to_owned(),
+                    role: Role::Read,
+                }],
+                jwt: None,
+            }),
+            ..Default::default()
+        };
+        let router = routes(app_state(config));
+        let request = |uri: &str, authorization: Option<&str>| {
+            let request = Request::builder()
+                .uri(uri)
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID);
+            authorization
+                .into_iter()
+                .fold(request, |request, value| {
+                    request.header("authorization", value)
+                })
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router
+            .clone()
+            .oneshot(request("/power-status", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        assert!(response.headers().contains_key("www-authenticate"));
+        // maas:secret
+        let response = router
+            .clone()
+            .oneshot(request("/power-status", Some("Basic bWFhczpzZWNyZXQ=")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = router
+            .clone()
+            .oneshot(request("/power-status", Some("Bearer dashboard")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let mut power_on = request("/power-on", Some("Bearer dashboard"));
+        *power_on.method_mut() = Method::POST;
+        let response = router.clone().oneshot(power_on).await.unwrap();
+        assert_eq!(response.status(), 403);
+        let response = router
+            .clone()
+            .oneshot(request("/admin/backup", Some("Bearer dashboard")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let response = router.oneshot(request("/readyz", None)).await.unwrap();
+        assert_ne!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn should_change_log_filter() {
+        let config = Config::default();
+        let state = app_state(config);
+        let put = |filter: &str| {
+            Request::builder()
+                .method(Method::PUT)
+                .uri("/admin/logging")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "filter": filter }).to_string()))
+                .unwrap()
+        };
+        let response = routes(state.clone())
+            .oneshot(put("maas_power_unifi=trace,reqwest=debug"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let request = Request::builder()
+            .uri("/admin/logging")
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state.clone()).oneshot(request).await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["filter"], "maas_power_unifi=trace,reqwest=debug");
+        let response = routes(state).oneshot(put("reqwest=loud")).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_answer_cors_preflight() {
+        let config = Config {
+            url: "".to_owned(),
+            cors: Some(config::CorsConfig {
+                allowed_origins: vec!["https://dashboard.example.com".to_owned()],
+                allowed_methods: vec!["GET".to_owned()],
+                allowed_headers: vec!["system_id".to_owned()],
+            }),
+            ..Default::default()
+        };
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/power-status")
+            .header("origin", "https://dashboard.example.com")
+            .header("access-control-request-method", "GET")
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(app_state(config)).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://dashboard.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_only_serve_status_on_standby() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = AppState {
+            leadership: Leadership::standby(),
+            ..app_state(config)
+        };
+        let request = |method, uri| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = routes(state.clone())
+            .oneshot(request(Method::GET, "/power-status"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = routes(state)
+            .oneshot(request(Method::POST, "/power-on"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers()["retry-after"], "15");
+    }
+
+    #[tokio::test]
+    async fn should_run_power_action_as_job() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/power-on?async=true")
+            .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+            .body(Body::empty())
+            .unwrap();
+        let mut response = routes(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 202);
+        let location = response.headers()["location"].to_str().unwrap().to_owned();
+        let job =
+            serde_json::from_slice::<Job>(&body::to_bytes(response.body_mut()).await.unwrap())
+                .unwrap();
+        assert_eq!(location, format!("/jobs/{}", job.id));
+        let mut status = job.status;
+        for _ in 0..50 {
+            let request = Request::builder()
+                .method(Method::GET)
+                .uri(&location)
+                .body(Body::empty())
+                .unwrap();
+            let mut response = routes(state.clone()).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), 200);
+            let job =
+                serde_json::from_slice::<Job>(&body::to_bytes(response.body_mut()).await.unwrap())
+                    .unwrap();
+            status = job.status;
+            if status == JobStatus::Succeeded {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(status, JobStatus::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn should_answer_a_repeated_idempotency_key_with_its_job() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let request = |uri: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header(MAAS_SYSTEM_ID_HEADER, MAAS_SYSTEM_ID)
+                .header("Idempotency-Key", "key")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let job = |mut response: axum::response::Response| async move {
+            serde_json::from_slice::<Job>(&body::to_bytes(response.body_mut()).await.unwrap())
+                .unwrap()
+        };
+        let response = routes(state.clone())
+            .oneshot(request("/power-on?async=true"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 202);
+        let first = job(response).await;
+        let response = routes(state.clone())
+            .oneshot(request("/power-on?async=true"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 202);
+        assert_eq!(job(response).await.id, first.id);
+        let response = routes(state)
+            .oneshot(request("/power-off?async=true"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn should_resume_jobs_after_restart() {
+        let config = Config {
+            url: "".to_owned(),
+            devices: vec![config::Device {
+                mac: MacAddress::from_str(UNIFI_DEVICE_MAC).unwrap(),
+                machines: vec![Machine {
+                    maas_id: MAAS_SYSTEM_ID.to_owned(),
+                    port_id: MACHINE_PORT,
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let state = app_state(config);
+        let queued = state
+            .jobs
+            .create(MAAS_SYSTEM_ID, PowerAction::On, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let interrupted = state
+            .jobs
+            .create("other", PowerAction::Cycle, None)
+            .await
+            .unwrap()
+            .unwrap();
+        state
+            .jobs
+            .update(&interrupted.id, JobStatus::Running, None)
+            .await;
+        resume_jobs(&state).await.unwrap();
+        let interrupted = state.jobs.get(&interrupted.id).await.unwrap().unwrap();
+        assert_eq!(interrupted.status, JobStatus::Failed);
+        let mut status = JobStatus::Queued;
+        for _ in 0..50 {
+            status = state.jobs.get(&queued.id).await.unwrap().unwrap().status;
+            if status.is_finished() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(status, JobStatus::Succeeded);
+    }
 }
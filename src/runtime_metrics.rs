@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use tokio::runtime::{Handle, RuntimeMetrics};
+
+use crate::metrics::Metrics;
+
+/// Records gauges of the tokio runtime every `interval`, to tell a starved
+/// runtime apart from a slow controller when requests take long. Poll times
+/// are only recorded in builds with `--cfg tokio_unstable`.
+pub fn spawn_runtime_sampler(metrics: Metrics, interval: Duration) {
+    let runtime = Handle::current().metrics();
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        let mut busy = total_busy(&runtime);
+        let mut sampled_at = Instant::now();
+        loop {
+            ticks.tick().await;
+            let workers = runtime.num_workers();
+            metrics.gauge("tokio_workers", &[], workers as f64);
+            metrics.gauge("tokio_alive_tasks", &[], runtime.num_alive_tasks() as f64);
+            metrics.gauge(
+                "tokio_global_queue_depth",
+                &[],
+                runtime.global_queue_depth() as f64,
+            );
+            let now_busy = total_busy(&runtime);
+            let elapsed = sampled_at.elapsed().as_secs_f64() * workers as f64;
+            if elapsed > 0.0 {
+                // The share of the workers' time spent running tasks.
+                let ratio = now_busy.saturating_sub(busy).as_secs_f64() / elapsed;
+                metrics.gauge("tokio_busy_ratio", &[], ratio);
+            }
+            busy = now_busy;
+            sampled_at = Instant::now();
+            #[cfg(tokio_unstable)]
+            if workers > 0 {
+                let poll_time: Duration = (0..workers)
+                    .map(|worker| runtime.worker_mean_poll_time(worker))
+                    .sum();
+                let mean = poll_time.as_secs_f64() * 1_000_000.0 / workers as f64;
+                metrics.gauge("tokio_mean_poll_time_us", &[], mean);
+            }
+        }
+    });
+}
+
+fn total_busy(runtime: &RuntimeMetrics) -> Duration {
+    (0..runtime.num_workers())
+        .map(|worker| runtime.worker_total_busy_duration(worker))
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::spawn_runtime_sampler;
+    use crate::{
+        config::StatsdFlavor,
+        metrics::test_sink::{received, statsd},
+    };
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn should_record_runtime_gauges() {
+        let (server, metrics) = statsd(StatsdFlavor::Dogstatsd, &[]);
+        spawn_runtime_sampler(metrics.clone(), Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let gauges = received(&server);
+        assert!(
+            gauges.contains(&"maas.tokio_workers:2|g".to_owned()),
+            "{gauges:?}"
+        );
+        assert!(gauges
+            .iter()
+            .any(|gauge| gauge.starts_with("maas.tokio_alive_tasks:")));
+        assert!(gauges
+            .iter()
+            .any(|gauge| gauge.starts_with("maas.tokio_busy_ratio:")));
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::Router;
+use hyper::server::accept;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{Instant, Sleep},
+};
+
+use crate::config::ServerConfig;
+
+/// Serves the API on every address in `listen`. All of them are bound before
+/// serving, so a taken or missing address fails startup.
+pub async fn serve(config: &ServerConfig, app: Router) -> anyhow::Result<()> {
+    let listeners = config
+        .listen
+        .iter()
+        .map(|addr| {
+            bind(*addr, config.backlog).with_context(|| format!("failed to listen on {addr}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    serve_on(listeners, config, app).await
+}
+
+fn bind(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)
+}
+
+async fn serve_on(
+    listeners: Vec<TcpListener>,
+    config: &ServerConfig,
+    app: Router,
+) -> anyhow::Result<()> {
+    // Shared by every listener, so the cap is on the whole server.
+    let slots = config
+        .max_connections
+        .map(|max| Arc::new(Semaphore::new(max)));
+    let keep_alive = Duration::from_secs(config.keep_alive_secs);
+    let servers = listeners.into_iter().map(|listener| {
+        let app = app.clone();
+        let slots = slots.clone();
+        async move {
+            tracing::info!("serving HTTP on {}", listener.local_addr()?);
+            let connections = futures::stream::unfold(listener, move |listener| {
+                let slots = slots.clone();
+                async move {
+                    // Stop accepting while full, so new connections queue in
+                    // the backlog rather than piling onto the runtime.
+                    let slot = match slots {
+                        Some(slots) => Some(slots.acquire_owned().await.expect("never closed")),
+                        None => None,
+                    };
+                    let connection = Connection {
+                        stream: next_connection(&listener).await,
+                        idle: (!keep_alive.is_zero()).then(|| IdleTimeout::new(keep_alive)),
+                        _slot: slot,
+                    };
+                    Some((io::Result::Ok(connection), listener))
+                }
+            });
+            axum::Server::builder(accept::from_stream(connections))
+                .http1_only(!config.http2)
+                .http1_keepalive(!keep_alive.is_zero())
+                .http2_keep_alive_interval((!keep_alive.is_zero()).then_some(keep_alive))
+                .serve(app.into_make_service())
+                .await?;
+            anyhow::Ok(())
+        }
+    });
+    futures::future::try_join_all(servers).await?;
+    Ok(())
+}
+
+async fn next_connection(listener: &TcpListener) -> TcpStream {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                if let Err(e) = stream.set_nodelay(true) {
+                    tracing::debug!("failed to set TCP_NODELAY: {e}");
+                }
+                return stream;
+            }
+            // Such as running out of file descriptors, which can pass.
+            Err(e) => {
+                tracing::warn!("failed to accept a connection: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// An accepted connection, holding a slot of `max_connections` until it is
+/// closed.
+struct Connection {
+    stream: TcpStream,
+    idle: Option<IdleTimeout>,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+/// Closes an HTTP/1 connection that has sat idle since its last response, by
+/// reporting the end of the stream. hyper 0.14 only times out the headers of
+/// a request it has started reading. HTTP/2 connections are kept, they are
+/// checked with pings instead.
+struct IdleTimeout {
+    after: Duration,
+    sleep: Pin<Box<Sleep>>,
+    /// Set by writing a response, cleared by reading a request, so a slow
+    /// request is never cut off.
+    armed: bool,
+    /// Whether anything has been read yet, to spot the HTTP/2 preface.
+    started: bool,
+}
+
+impl IdleTimeout {
+    fn new(after: Duration) -> Self {
+        Self {
+            after,
+            sleep: Box::pin(tokio::time::sleep(after)),
+            armed: true,
+            started: false,
+        }
+    }
+}
+
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0";
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        match Pin::new(&mut this.stream).poll_read(cx, buf) {
+            Poll::Pending => match &mut this.idle {
+                Some(idle) if idle.armed => idle.sleep.as_mut().poll(cx).map(Ok),
+                _ => Poll::Pending,
+            },
+            Poll::Ready(result) => {
+                let read = &buf.filled()[filled..];
+                if let Some(idle) = &mut this.idle {
+                    if !idle.started && read.starts_with(HTTP2_PREFACE) {
+                        this.idle = None;
+                    } else if !read.is_empty() {
+                        idle.started = true;
+                        idle.armed = false;
+                    }
+                }
+                Poll::Ready(result)
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let written = ready!(Pin::new(&mut this.stream).poll_write(cx, buf));
+        if let (Ok(1..), Some(idle)) = (&written, &mut this.idle) {
+            idle.armed = true;
+            idle.sleep.as_mut().reset(Instant::now() + idle.after);
+        }
+        Poll::Ready(written)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bind, serve_on};
+    use crate::config::ServerConfig;
+    use axum::{routing::get, Router};
+    use hyper::{client::HttpConnector, Body, Client, Request, Version};
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    fn spawn_server(listen: &[&str], config: ServerConfig) -> Vec<String> {
+        let listeners: Vec<_> = listen
+            .iter()
+            .map(|addr| bind(addr.parse().unwrap(), 16).unwrap())
+            .collect();
+        let addresses = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().to_string())
+            .collect();
+        let app = Router::new().route("/", get(|| async { "ok" })).route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(1500)).await;
+                "ok"
+            }),
+        );
+        tokio::spawn(async move { serve_on(listeners, &config, app).await });
+        addresses
+    }
+
+    async fn version(client: &Client<HttpConnector>, address: &str) -> hyper::Result<Version> {
+        get_path(client, address, "/").await
+    }
+
+    async fn get_path(
+        client: &Client<HttpConnector>,
+        address: &str,
+        path: &str,
+    ) -> hyper::Result<Version> {
+        let request = Request::get(format!("http://{address}{path}"))
+            .body(Body::empty())
+            .unwrap();
+        client
+            .request(request)
+            .await
+            .map(|response| response.version())
+    }
+
+    #[tokio::test]
+    async fn should_serve_http2_with_prior_knowledge_unless_disabled() {
+        let client = Client::builder().http2_only(true).build_http();
+        let addresses = spawn_server(&["127.0.0.1:0"], ServerConfig::default());
+        assert_eq!(
+            version(&client, &addresses[0]).await.unwrap(),
+            Version::HTTP_2
+        );
+        let config = ServerConfig {
+            http2: false,
+            ..Default::default()
+        };
+        let addresses = spawn_server(&["127.0.0.1:0"], config);
+        assert!(version(&client, &addresses[0]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_serve_on_every_address() {
+        let client = Client::new();
+        let addresses = spawn_server(&["127.0.0.1:0", "[::1]:0"], ServerConfig::default());
+        assert!(addresses[1].starts_with("[::1]:"));
+        for address in &addresses {
+            assert_eq!(version(&client, address).await.unwrap(), Version::HTTP_11);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_hold_connections_over_the_limit() {
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..Default::default()
+        };
+        let addresses = spawn_server(&["127.0.0.1:0"], config);
+        let open = TcpStream::connect(&addresses[0]).await.unwrap();
+        let client = Client::new();
+        let request =
+            tokio::time::timeout(Duration::from_millis(200), version(&client, &addresses[0]));
+        assert!(request.await.is_err());
+        drop(open);
+        version(&client, &addresses[0]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_close_idle_connections_but_not_slow_requests() {
+        let config = ServerConfig {
+            keep_alive_secs: 1,
+            ..Default::default()
+        };
+        let addresses = spawn_server(&["127.0.0.1:0"], config);
+        let mut idle = TcpStream::connect(&addresses[0]).await.unwrap();
+        idle.write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(3), idle.read_to_end(&mut response));
+        read.await.unwrap().unwrap();
+        assert!(response.ends_with(b"ok"));
+        get_path(&Client::new(), &addresses[0], "/slow")
+            .await
+            .unwrap();
+    }
+}
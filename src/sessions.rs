@@ -0,0 +1,87 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    config::ControllerConfig,
+    unifi::{client::UnifiClient, failover, handler::UnifiHandler},
+};
+
+/// Controller sessions for the credentials MaaS sends with a request, kept so
+/// a rack's account logs in once rather than on every call.
+#[derive(Clone, Default)]
+pub struct Sessions {
+    url: String,
+    controller: ControllerConfig,
+    /// Sessions log in through clones of this client when set, rather than
+    /// a client made for `url`.
+    client: Option<Box<dyn UnifiClient + Send + Sync>>,
+    handlers: Arc<Mutex<HashMap<(String, String), UnifiHandler>>>,
+}
+
+impl Sessions {
+    pub fn new(url: &str, controller: &ControllerConfig) -> Self {
+        Self {
+            url: url.to_owned(),
+            controller: controller.clone(),
+            client: None,
+            handlers: Arc::default(),
+        }
+    }
+
+    pub fn with_client(mut self, client: Box<dyn UnifiClient + Send + Sync>) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// A handler logged in as `username`. Held across the login, so requests
+    /// racing with the same credentials share one session.
+    pub async fn handler(&self, username: &str, password: &str) -> anyhow::Result<UnifiHandler> {
+        let mut handlers = self.handlers.lock().await;
+        let key = (username.to_owned(), password.to_owned());
+        if let Some(handler) = handlers.get(&key) {
+            return Ok(handler.clone());
+        }
+        let client = match &self.client {
+            Some(client) => client.clone(),
+            None => failover::controller_client(&self.url, &self.controller)?,
+        };
+        let handler = UnifiHandler::new(client).with_retry(self.controller.retry);
+        handler.login(username, password).await?;
+        handlers.insert(key, handler.clone());
+        Ok(handler)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Sessions;
+    use crate::config::ControllerConfig;
+    use wiremock::{
+        matchers::{body_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn should_log_in_once_per_credentials() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/login"))
+            .and(body_json(
+                serde_json::json!({"username": "rack-1", "password": "secret"}),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/login"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&mock_server)
+            .await;
+        let sessions = Sessions::new(&mock_server.uri(), &ControllerConfig::default());
+        sessions.handler("rack-1", "secret").await.unwrap();
+        sessions.handler("rack-1", "secret").await.unwrap();
+        assert!(sessions.handler("rack-1", "wrong").await.is_err());
+    }
+}
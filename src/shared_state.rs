@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use redis::aio::ConnectionManager;
+
+use crate::config::RedisConfig;
+
+/// Short lived keys and counters that every instance must agree on, kept in
+/// memory or, for several instances behind a load balancer, in Redis.
+#[derive(Clone)]
+pub enum SharedState {
+    Memory(Arc<Mutex<HashMap<String, Entry>>>),
+    Redis {
+        connection: ConnectionManager,
+        prefix: String,
+    },
+}
+
+pub struct Entry {
+    value: String,
+    expires_at: Instant,
+}
+
+impl Default for SharedState {
+    fn default() -> Self {
+        SharedState::Memory(Default::default())
+    }
+}
+
+impl SharedState {
+    pub async fn connect(config: &RedisConfig) -> anyhow::Result<Self> {
+        let client = redis::Client::open(config.url.as_str())?;
+        Ok(SharedState::Redis {
+            connection: ConnectionManager::new(client).await?,
+            prefix: config.key_prefix.clone(),
+        })
+    }
+
+    /// Sets `key` to `value` for `ttl` unless it is already set, in which case
+    /// the current value is returned.
+    pub async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> anyhow::Result<Option<String>> {
+        match self {
+            SharedState::Memory(entries) => {
+                let mut entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                entries.retain(|_, entry| entry.expires_at > now);
+                if let Some(entry) = entries.get(key) {
+                    return Ok(Some(entry.value.clone()));
+                }
+                entries.insert(
+                    key.to_owned(),
+                    Entry {
+                        value: value.to_owned(),
+                        expires_at: now + ttl,
+                    },
+                );
+                Ok(None)
+            }
+            SharedState::Redis { connection, prefix } => {
+                let key = format!("{prefix}:{key}");
+                let (set, current): (Option<String>, String) = redis::pipe()
+                    .atomic()
+                    .cmd("SET")
+                    .arg(&key)
+                    .arg(value)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(ttl.as_millis() as u64)
+                    .cmd("GET")
+                    .arg(&key)
+                    .query_async(&mut connection.clone())
+                    .await?;
+                Ok(set.is_none().then_some(current))
+            }
+        }
+    }
+
+    /// Sets `key` to `value` for `ttl`, replacing any current value.
+    pub async fn set(&self, key: &str, value: &str, ttl: Duration) -> anyhow::Result<()> {
+        match self {
+            SharedState::Memory(entries) => {
+                let mut entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+                entries.insert(
+                    key.to_owned(),
+                    Entry {
+                        value: value.to_owned(),
+                        expires_at: Instant::now() + ttl,
+                    },
+                );
+                Ok(())
+            }
+            SharedState::Redis { connection, prefix } => Ok(redis::cmd("SET")
+                .arg(format!("{prefix}:{key}"))
+                .arg(value)
+                .arg("PX")
+                .arg(ttl.as_millis() as u64)
+                .query_async(&mut connection.clone())
+                .await?),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match self {
+            SharedState::Memory(entries) => {
+                let entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+                Ok(entries
+                    .get(key)
+                    .filter(|entry| entry.expires_at > Instant::now())
+                    .map(|entry| entry.value.clone()))
+            }
+            SharedState::Redis { connection, prefix } => Ok(redis::cmd("GET")
+                .arg(format!("{prefix}:{key}"))
+                .query_async(&mut connection.clone())
+                .await?),
+        }
+    }
+
+    /// Counts a hit on `key` in a window of `window` started by the first hit,
+    /// returning the hits so far and how long until the window ends.
+    pub async fn increment(&self, key: &str, window: Duration) -> anyhow::Result<(u64, Duration)> {
+        match self {
+            SharedState::Memory(entries) => {
+                let mut entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                entries.retain(|_, entry| entry.expires_at > now);
+                let entry = entries.entry(key.to_owned()).or_insert_with(|| Entry {
+                    value: "0".to_owned(),
+                    expires_at: now + window,
+                });
+                let count = entry.value.parse::<u64>()? + 1;
+                entry.value = count.to_string();
+                Ok((count, entry.expires_at - now))
+            }
+            SharedState::Redis { connection, prefix } => {
+                let key = format!("{prefix}:{key}");
+                let mut connection = connection.clone();
+                // One script, so a crash between the two calls cannot leave a
+                // counter that never expires.
+                let (count, ttl): (u64, u64) = redis::Script::new(
+                    r#"
+                    local count = redis.call("INCR", KEYS[1])
+                    local ttl = redis.call("PTTL", KEYS[1])
+                    if ttl < 0 then
+                        redis.call("PEXPIRE", KEYS[1], ARGV[1])
+                        ttl = tonumber(ARGV[1])
+                    end
+                    return {count, ttl}
+                    "#,
+                )
+                .key(&key)
+                .arg(window.as_millis() as u64)
+                .invoke_async(&mut connection)
+                .await?;
+                Ok((count, Duration::from_millis(ttl)))
+            }
+        }
+    }
+
+    /// Deletes `key` if it still holds `value`, so a key that expired and was
+    /// set again by someone else is left alone. Redis is updated in the
+    /// background so this can be called on drop.
+    pub fn remove_if(&self, key: &str, value: &str) {
+        match self {
+            SharedState::Memory(entries) => {
+                let mut entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+                if entries.get(key).is_some_and(|entry| entry.value == value) {
+                    entries.remove(key);
+                }
+            }
+            SharedState::Redis { connection, prefix } => {
+                let key = format!("{prefix}:{key}");
+                let value = value.to_owned();
+                let mut connection = connection.clone();
+                tokio::spawn(async move {
+                    let deleted: redis::RedisResult<()> = redis::Script::new(
+                        r#"
+                        if redis.call("GET", KEYS[1]) == ARGV[1] then
+                            redis.call("DEL", KEYS[1])
+                        end
+                        "#,
+                    )
+                    .key(&key)
+                    .arg(value)
+                    .invoke_async(&mut connection)
+                    .await;
+                    if let Err(e) = deleted {
+                        tracing::warn!("failed to delete `{key}` from Redis: {e}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SharedState;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn should_keep_first_value_until_it_expires() {
+        let state = SharedState::default();
+        let ttl = Duration::from_millis(50);
+        assert_eq!(state.set_if_absent("key", "a", ttl).await.unwrap(), None);
+        assert_eq!(
+            state.set_if_absent("key", "b", ttl).await.unwrap(),
+            Some("a".to_owned())
+        );
+        tokio::time::sleep(ttl).await;
+        assert_eq!(state.set_if_absent("key", "b", ttl).await.unwrap(), None);
+        // `a` expired and `b` took its place, so removing `a` keeps `b`.
+        state.remove_if("key", "a");
+        assert_eq!(
+            state.set_if_absent("key", "c", ttl).await.unwrap(),
+            Some("b".to_owned())
+        );
+        state.remove_if("key", "b");
+        assert_eq!(state.set_if_absent("key", "c", ttl).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn should_count_hits_within_window() {
+        let state = SharedState::default();
+        let window = Duration::from_millis(50);
+        assert_eq!(state.increment("key", window).await.unwrap().0, 1);
+        let (count, remaining) = state.increment("key", window).await.unwrap();
+        assert_eq!(count, 2);
+        assert!(remaining <= window);
+        tokio::time::sleep(window).await;
+        assert_eq!(state.increment("key", window).await.unwrap().0, 1);
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::BackendRegistry,
+    config::Driver,
+    in_flight::{InFlight, InFlightAction},
+    router::AppState,
+    store::Store,
+    unifi::handler::UnifiHandler,
+};
+
+/// Everything the service knows about the machines it manages, for bug reports
+/// and for capturing state before a restart.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateSnapshot {
+    pub taken_at: String,
+    pub controller: ControllerHealth,
+    pub machines: Vec<MachineSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ControllerHealth {
+    pub url: String,
+    pub reachable: bool,
+    pub devices: usize,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MachineSnapshot {
+    pub system_id: String,
+    pub driver: Driver,
+    pub status: Option<String>,
+    pub power_draw_watts: Option<f64>,
+    pub last_action: Option<LastAction>,
+    /// The power action running against the machine right now.
+    #[serde(default)]
+    pub in_flight: Option<InFlightAction>,
+    /// Errors hit while gathering the above, the snapshot is still returned.
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LastAction {
+    pub action: String,
+    pub success: bool,
+    pub timestamp: String,
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+pub async fn controller_health(url: &str, controller: &UnifiHandler) -> ControllerHealth {
+    let (devices, error) = match controller.devices().await {
+        Ok(devices) => (Some(devices.len()), None),
+        Err(e) => (None, Some(format!("{e:?}"))),
+    };
+    ControllerHealth {
+        url: url.to_owned(),
+        reachable: devices.is_some(),
+        devices: devices.unwrap_or_default(),
+        error,
+    }
+}
+
+/// Queries every machine's backend and the store. Failures are recorded in the
+/// snapshot rather than failing it, a partial snapshot is more useful than none.
+pub async fn take_snapshot(
+    url: &str,
+    controller: &UnifiHandler,
+    backends: &BackendRegistry,
+    store: &Store,
+    in_flight: &InFlight,
+) -> StateSnapshot {
+    let mut targets = backends.targets();
+    targets.sort_by(|a, b| a.machine.maas_id.cmp(&b.machine.maas_id));
+    let mut machines = Vec::with_capacity(targets.len());
+    for target in targets {
+        let machine = &target.machine;
+        let mut errors = Vec::new();
+        let status = match target.backend.status(machine).await {
+            Ok(status) => Some(status.status),
+            Err(e) => {
+                errors.push(format!("status: {e:?}"));
+                None
+            }
+        };
+        let power_draw_watts = match target.backend.power_draw(machine).await {
+            Ok(watts) => watts,
+            Err(e) => {
+                errors.push(format!("power draw: {e:?}"));
+                None
+            }
+        };
+        let last_action = match store.last_power_action(&machine.maas_id).await {
+            Ok(record) => record.map(|record| LastAction {
+                action: record.action,
+                success: record.success,
+                timestamp: rfc3339(record.timestamp),
+            }),
+            Err(e) => {
+                errors.push(format!("last action: {e}"));
+                None
+            }
+        };
+        let running = match in_flight.running(&machine.maas_id).await {
+            Ok(running) => running,
+            Err(e) => {
+                errors.push(format!("in flight: {e}"));
+                None
+            }
+        };
+        machines.push(MachineSnapshot {
+            system_id: machine.maas_id.clone(),
+            driver: machine.driver,
+            status,
+            power_draw_watts,
+            last_action,
+            in_flight: running,
+            errors,
+        });
+    }
+    StateSnapshot {
+        taken_at: rfc3339(SystemTime::now()),
+        controller: controller_health(url, controller).await,
+        machines,
+    }
+}
+
+/// Takes a snapshot whenever the process receives `SIGUSR1`, to see what a
+/// live instance is doing without restarting it. The snapshot is written to
+/// `state_dump_path` if set, otherwise logged.
+#[cfg(unix)]
+pub fn dump_on_sigusr1(state: AppState) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            let snapshot = take_snapshot(
+                &state.config.load().url,
+                &state.controller,
+                &state.backends,
+                &state.store,
+                &state.in_flight,
+            )
+            .await;
+            let json = match serde_json::to_string_pretty(&snapshot) {
+                Ok(json) => json,
+                Err(e) => {
+                    tracing::warn!("failed to serialize state snapshot: {e}");
+                    continue;
+                }
+            };
+            match &state.config.load().state_dump_path {
+                Some(path) => match tokio::fs::write(path, json).await {
+                    Ok(()) => tracing::info!("wrote state snapshot to {}", path.display()),
+                    Err(e) => {
+                        tracing::warn!("failed to write state snapshot to {}: {e}", path.display())
+                    }
+                },
+                None => tracing::info!("state snapshot: {json}"),
+            }
+        }
+    });
+    Ok(())
+}
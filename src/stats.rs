@@ -0,0 +1,143 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    notifications::PowerAction,
+    store::{ActionRecord, PowerSample},
+};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MachineStats {
+    pub system_id: String,
+    /// How long the machine was powered within the window, based on the
+    /// power actions run through this service.
+    pub on_time_secs: u64,
+    pub power_ons: usize,
+    pub power_offs: usize,
+    pub power_cycles: usize,
+    pub failed_actions: usize,
+    /// Energy used within the window, estimated from the power history. `None`
+    /// when there are no samples for the machine.
+    pub energy_wh: Option<f64>,
+}
+
+/// Summarises the actions and power samples of a machine within the window
+/// starting at `since`. `actions` may start with the last action before the
+/// window, which gives the state the machine was in when the window started.
+pub fn machine_stats(
+    system_id: &str,
+    actions: &[ActionRecord],
+    samples: &[PowerSample],
+    since: SystemTime,
+    now: SystemTime,
+) -> MachineStats {
+    let in_window = |action: &&ActionRecord| action.timestamp >= since;
+    let count = |name: &str| {
+        actions
+            .iter()
+            .filter(in_window)
+            .filter(|action| action.success && action.action == name)
+            .count()
+    };
+    MachineStats {
+        system_id: system_id.to_owned(),
+        on_time_secs: on_time(actions, since, now).as_secs(),
+        power_ons: count(PowerAction::On.as_str()),
+        power_offs: count(PowerAction::Off.as_str()),
+        power_cycles: count(PowerAction::Cycle.as_str()),
+        failed_actions: actions
+            .iter()
+            .filter(in_window)
+            .filter(|action| !action.success)
+            .count(),
+        energy_wh: energy_wh(samples),
+    }
+}
+
+fn on_time(actions: &[ActionRecord], since: SystemTime, now: SystemTime) -> Duration {
+    let mut total = Duration::ZERO;
+    let mut on_since = None;
+    for action in actions.iter().filter(|action| action.success) {
+        let at = action.timestamp.max(since);
+        if action.action == PowerAction::Off.as_str() {
+            if let Some(start) = on_since.take() {
+                total += at.duration_since(start).unwrap_or_default();
+            }
+        } else if on_since.is_none() {
+            on_since = Some(at);
+        }
+    }
+    if let Some(start) = on_since {
+        total += now.duration_since(start).unwrap_or_default();
+    }
+    total
+}
+
+/// Integrates the samples with the trapezoidal rule.
+fn energy_wh(samples: &[PowerSample]) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let joules: f64 = samples
+        .windows(2)
+        .map(|pair| {
+            let elapsed = pair[1]
+                .timestamp
+                .duration_since(pair[0].timestamp)
+                .unwrap_or_default();
+            (pair[0].watts + pair[1].watts) / 2.0 * elapsed.as_secs_f64()
+        })
+        .sum();
+    Some(joules / 3600.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::machine_stats;
+    use crate::store::{ActionRecord, PowerSample};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const MAAS_SYSTEM_ID: &str = "system-id";
+
+    fn action(name: &str, success: bool, secs: u64) -> ActionRecord {
+        ActionRecord {
+            action: name.to_owned(),
+            success,
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    fn sample(secs: u64, watts: f64) -> PowerSample {
+        PowerSample {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            watts,
+        }
+    }
+
+    #[test]
+    fn should_count_on_time_from_before_window() {
+        let actions = vec![
+            action("power_on", true, 0),
+            action("power_off", true, 200),
+            action("power_on", false, 300),
+            action("power_cycle", true, 400),
+        ];
+        let since = UNIX_EPOCH + Duration::from_secs(100);
+        let now = UNIX_EPOCH + Duration::from_secs(500);
+        let stats = machine_stats(MAAS_SYSTEM_ID, &actions, &[], since, now);
+        assert_eq!(stats.on_time_secs, 200);
+        assert_eq!(stats.power_ons, 0);
+        assert_eq!(stats.power_offs, 1);
+        assert_eq!(stats.power_cycles, 1);
+        assert_eq!(stats.failed_actions, 1);
+        assert_eq!(stats.energy_wh, None);
+    }
+
+    #[test]
+    fn should_estimate_energy_from_samples() {
+        let samples = vec![sample(0, 10.0), sample(1800, 10.0), sample(3600, 30.0)];
+        let stats = machine_stats(MAAS_SYSTEM_ID, &[], &samples, UNIX_EPOCH, UNIX_EPOCH);
+        assert_eq!(stats.energy_wh, Some(15.0));
+    }
+}
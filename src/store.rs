@@ -0,0 +1,566 @@
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::jobs::{self, Job, JobStatus};
+
+const MIGRATIONS: &str = "
+    CREATE TABLE IF NOT EXISTS power_samples (
+        maas_id TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        watts REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS power_samples_machine
+        ON power_samples (maas_id, timestamp);
+    CREATE TABLE IF NOT EXISTS power_actions (
+        maas_id TEXT NOT NULL,
+        action TEXT NOT NULL,
+        success INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS power_actions_machine
+        ON power_actions (maas_id, timestamp);
+    CREATE TABLE IF NOT EXISTS jobs (
+        id TEXT PRIMARY KEY,
+        maas_id TEXT NOT NULL,
+        action TEXT NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        idempotency_key TEXT UNIQUE,
+        attempts INTEGER NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        finished_at INTEGER
+    );
+";
+
+const JOB_COLUMNS: &str =
+    "id, maas_id, action, status, error, idempotency_key, attempts, created_at, updated_at";
+
+/// Reads a text column into anything that parses from a string.
+fn parse_column<T>(row: &Row, index: usize) -> rusqlite::Result<T>
+where
+    T: FromStr<Err = String>,
+{
+    row.get::<_, String>(index)?
+        .parse()
+        .map_err(|e: String| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, e.into()))
+}
+
+fn job_from_row(row: &Row) -> rusqlite::Result<Job> {
+    Ok(Job {
+        id: row.get(0)?,
+        system_id: row.get(1)?,
+        action: parse_column(row, 2)?,
+        status: parse_column(row, 3)?,
+        error: row.get(4)?,
+        idempotency_key: row.get(5)?,
+        attempts: row.get(6)?,
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerSample {
+    pub timestamp: SystemTime,
+    pub watts: f64,
+}
+
+/// A power action that was run against a machine, e.g. `power_on`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionRecord {
+    pub action: String,
+    pub success: bool,
+    pub timestamp: SystemTime,
+}
+
+/// Everything in the store, in a form that can be moved between hosts.
+/// Timestamps are unix seconds.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct StoredState {
+    pub power_actions: Vec<StoredAction>,
+    pub power_samples: Vec<StoredSample>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct StoredAction {
+    pub maas_id: String,
+    pub action: String,
+    pub success: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct StoredSample {
+    pub maas_id: String,
+    pub timestamp: i64,
+    pub watts: f64,
+}
+
+/// Persistent state kept in sqlite. Without a configured path the database
+/// only lives in memory and is lost on restart.
+#[derive(Clone)]
+pub struct Store {
+    connection: Arc<Mutex<Connection>>,
+}
+
+fn to_unix(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+fn from_unix(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+impl Store {
+    pub fn open(path: Option<&Path>) -> anyhow::Result<Self> {
+        let connection = match path {
+            Some(path) => Connection::open(path)?,
+            None => Connection::open_in_memory()?,
+        };
+        connection.execute_batch(MIGRATIONS)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `f` on a blocking thread so sqlite never stalls the runtime.
+    async fn call<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = connection
+                .lock()
+                .map_err(|_| anyhow!("sqlite connection lock poisoned"))?;
+            Ok(f(&connection)?)
+        })
+        .await?
+    }
+
+    pub async fn record_power_sample(
+        &self,
+        maas_id: &str,
+        sample: PowerSample,
+    ) -> anyhow::Result<()> {
+        let maas_id = maas_id.to_owned();
+        self.call(move |connection| {
+            connection.execute(
+                "INSERT INTO power_samples (maas_id, timestamp, watts) VALUES (?1, ?2, ?3)",
+                params![maas_id, to_unix(sample.timestamp), sample.watts],
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Samples for a machine taken at or after `since`, oldest first.
+    pub async fn power_samples(
+        &self,
+        maas_id: &str,
+        since: SystemTime,
+    ) -> anyhow::Result<Vec<PowerSample>> {
+        let maas_id = maas_id.to_owned();
+        self.call(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT timestamp, watts FROM power_samples
+                 WHERE maas_id = ?1 AND timestamp >= ?2 ORDER BY timestamp",
+            )?;
+            let samples = statement
+                .query_map(params![maas_id, to_unix(since)], |row| {
+                    Ok(PowerSample {
+                        timestamp: from_unix(row.get(0)?),
+                        watts: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(samples)
+        })
+        .await
+    }
+
+    pub async fn record_power_action(
+        &self,
+        maas_id: &str,
+        record: ActionRecord,
+    ) -> anyhow::Result<()> {
+        let maas_id = maas_id.to_owned();
+        self.call(move |connection| {
+            connection.execute(
+                "INSERT INTO power_actions (maas_id, action, success, timestamp)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    maas_id,
+                    record.action,
+                    record.success,
+                    to_unix(record.timestamp)
+                ],
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Actions run against a machine at or after `since`, oldest first. The
+    /// last successful action before `since` is included first so callers know
+    /// the state the machine was in at the start of the window.
+    pub async fn power_actions(
+        &self,
+        maas_id: &str,
+        since: SystemTime,
+    ) -> anyhow::Result<Vec<ActionRecord>> {
+        let maas_id = maas_id.to_owned();
+        self.call(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT action, success, timestamp FROM (
+                     SELECT action, success, timestamp FROM power_actions
+                     WHERE maas_id = ?1 AND timestamp < ?2 AND success
+                     ORDER BY timestamp DESC, rowid DESC LIMIT 1
+                 )
+                 UNION ALL
+                 SELECT action, success, timestamp FROM (
+                     SELECT action, success, timestamp FROM power_actions
+                     WHERE maas_id = ?1 AND timestamp >= ?2
+                     ORDER BY timestamp, rowid
+                 )",
+            )?;
+            let actions = statement
+                .query_map(params![maas_id, to_unix(since)], |row| {
+                    Ok(ActionRecord {
+                        action: row.get(0)?,
+                        success: row.get(1)?,
+                        timestamp: from_unix(row.get(2)?),
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(actions)
+        })
+        .await
+    }
+
+    /// The most recent action run against a machine, successful or not.
+    pub async fn last_power_action(&self, maas_id: &str) -> anyhow::Result<Option<ActionRecord>> {
+        let maas_id = maas_id.to_owned();
+        self.call(move |connection| {
+            connection
+                .query_row(
+                    "SELECT action, success, timestamp FROM power_actions
+                     WHERE maas_id = ?1 ORDER BY timestamp DESC, rowid DESC LIMIT 1",
+                    params![maas_id],
+                    |row| {
+                        Ok(ActionRecord {
+                            action: row.get(0)?,
+                            success: row.get(1)?,
+                            timestamp: from_unix(row.get(2)?),
+                        })
+                    },
+                )
+                .optional()
+        })
+        .await
+    }
+
+    pub async fn export_state(&self) -> anyhow::Result<StoredState> {
+        self.call(|connection| {
+            let power_actions = connection
+                .prepare(
+                    "SELECT maas_id, action, success, timestamp FROM power_actions
+                     ORDER BY timestamp, rowid",
+                )?
+                .query_map([], |row| {
+                    Ok(StoredAction {
+                        maas_id: row.get(0)?,
+                        action: row.get(1)?,
+                        success: row.get(2)?,
+                        timestamp: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let power_samples = connection
+                .prepare(
+                    "SELECT maas_id, timestamp, watts FROM power_samples
+                     ORDER BY timestamp, rowid",
+                )?
+                .query_map([], |row| {
+                    Ok(StoredSample {
+                        maas_id: row.get(0)?,
+                        timestamp: row.get(1)?,
+                        watts: row.get(2)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(StoredState {
+                power_actions,
+                power_samples,
+            })
+        })
+        .await
+    }
+
+    /// Replaces everything in the store with `state`.
+    pub async fn import_state(&self, state: StoredState) -> anyhow::Result<()> {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| anyhow!("sqlite connection lock poisoned"))?;
+            let transaction = connection.transaction()?;
+            transaction.execute_batch("DELETE FROM power_actions; DELETE FROM power_samples;")?;
+            for action in state.power_actions {
+                transaction.execute(
+                    "INSERT INTO power_actions (maas_id, action, success, timestamp)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        action.maas_id,
+                        action.action,
+                        action.success,
+                        action.timestamp
+                    ],
+                )?;
+            }
+            for sample in state.power_samples {
+                transaction.execute(
+                    "INSERT INTO power_samples (maas_id, timestamp, watts) VALUES (?1, ?2, ?3)",
+                    params![sample.maas_id, sample.timestamp, sample.watts],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Inserts `job`, or returns the job already holding its idempotency key.
+    pub async fn insert_job(&self, job: Job) -> anyhow::Result<Result<(), Job>> {
+        self.call(move |connection| {
+            let inserted = connection.execute(
+                &format!(
+                    "INSERT INTO jobs ({JOB_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                     ON CONFLICT (idempotency_key) DO NOTHING"
+                ),
+                params![
+                    job.id,
+                    job.system_id,
+                    job.action.as_str(),
+                    job.status.as_str(),
+                    job.error,
+                    job.idempotency_key,
+                    job.attempts,
+                    job.created_at,
+                    job.updated_at
+                ],
+            )?;
+            if inserted == 1 {
+                return Ok(Ok(()));
+            }
+            connection
+                .query_row(
+                    &format!("SELECT {JOB_COLUMNS} FROM jobs WHERE idempotency_key = ?1"),
+                    params![job.idempotency_key],
+                    job_from_row,
+                )
+                .map(Err)
+        })
+        .await
+    }
+
+    pub async fn job(&self, id: &str) -> anyhow::Result<Option<Job>> {
+        let id = id.to_owned();
+        self.call(move |connection| {
+            connection
+                .query_row(
+                    &format!("SELECT {JOB_COLUMNS} FROM jobs WHERE id = ?1"),
+                    params![id],
+                    job_from_row,
+                )
+                .optional()
+        })
+        .await
+    }
+
+    pub async fn job_by_idempotency_key(&self, key: &str) -> anyhow::Result<Option<Job>> {
+        let key = key.to_owned();
+        self.call(move |connection| {
+            connection
+                .query_row(
+                    &format!("SELECT {JOB_COLUMNS} FROM jobs WHERE idempotency_key = ?1"),
+                    params![key],
+                    job_from_row,
+                )
+                .optional()
+        })
+        .await
+    }
+
+    /// Jobs still queued or running, oldest first.
+    pub async fn unfinished_jobs(&self) -> anyhow::Result<Vec<Job>> {
+        self.call(|connection| {
+            connection
+                .prepare(&format!(
+                    "SELECT {JOB_COLUMNS} FROM jobs
+                     WHERE status IN ('queued', 'running') ORDER BY created_at, rowid"
+                ))?
+                .query_map([], job_from_row)?
+                .collect()
+        })
+        .await
+    }
+
+    /// Sets the status of a job, counting an attempt each time it starts
+    /// running.
+    pub async fn update_job(
+        &self,
+        id: &str,
+        status: JobStatus,
+        error: Option<String>,
+    ) -> anyhow::Result<()> {
+        let id = id.to_owned();
+        let finished_at = status.is_finished().then(|| to_unix(SystemTime::now()));
+        self.call(move |connection| {
+            connection.execute(
+                "UPDATE jobs SET status = ?2, error = ?3, updated_at = ?4, finished_at = ?5,
+                     attempts = attempts + (?2 = 'running')
+                 WHERE id = ?1",
+                params![id, status.as_str(), error, jobs::now(), finished_at],
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Forgets jobs that finished before `before`.
+    pub async fn prune_jobs(&self, before: SystemTime) -> anyhow::Result<usize> {
+        self.call(move |connection| {
+            connection.execute(
+                "DELETE FROM jobs WHERE finished_at < ?1",
+                params![to_unix(before)],
+            )
+        })
+        .await
+    }
+
+    pub async fn prune_power_samples(&self, before: SystemTime) -> anyhow::Result<usize> {
+        self.call(move |connection| {
+            connection.execute(
+                "DELETE FROM power_samples WHERE timestamp < ?1",
+                params![to_unix(before)],
+            )
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ActionRecord, PowerSample, Store, StoredAction, StoredState};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const MAAS_SYSTEM_ID: &str = "system-id";
+
+    fn at(secs: u64, watts: f64) -> PowerSample {
+        PowerSample {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            watts,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_return_samples_within_window() {
+        let store = Store::open(None).unwrap();
+        store
+            .record_power_sample(MAAS_SYSTEM_ID, at(100, 1.0))
+            .await
+            .unwrap();
+        store
+            .record_power_sample(MAAS_SYSTEM_ID, at(200, 2.0))
+            .await
+            .unwrap();
+        store
+            .record_power_sample("other", at(200, 3.0))
+            .await
+            .unwrap();
+        let samples = store
+            .power_samples(MAAS_SYSTEM_ID, UNIX_EPOCH + Duration::from_secs(150))
+            .await
+            .unwrap();
+        assert_eq!(samples, vec![at(200, 2.0)]);
+    }
+
+    #[tokio::test]
+    async fn should_prune_old_samples() {
+        let store = Store::open(None).unwrap();
+        store
+            .record_power_sample(MAAS_SYSTEM_ID, at(100, 1.0))
+            .await
+            .unwrap();
+        let pruned = store.prune_power_samples(SystemTime::now()).await.unwrap();
+        assert_eq!(pruned, 1);
+    }
+
+    #[tokio::test]
+    async fn should_include_last_action_before_window() {
+        let store = Store::open(None).unwrap();
+        let action = |name: &str, success, secs| ActionRecord {
+            action: name.to_owned(),
+            success,
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+        };
+        store
+            .record_power_action(MAAS_SYSTEM_ID, action("power_on", true, 100))
+            .await
+            .unwrap();
+        store
+            .record_power_action(MAAS_SYSTEM_ID, action("power_off", false, 110))
+            .await
+            .unwrap();
+        store
+            .record_power_action(MAAS_SYSTEM_ID, action("power_off", true, 200))
+            .await
+            .unwrap();
+        let actions = store
+            .power_actions(MAAS_SYSTEM_ID, UNIX_EPOCH + Duration::from_secs(150))
+            .await
+            .unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                action("power_on", true, 100),
+                action("power_off", true, 200)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_replace_state_on_import() {
+        let store = Store::open(None).unwrap();
+        store
+            .record_power_sample(MAAS_SYSTEM_ID, at(100, 1.0))
+            .await
+            .unwrap();
+        let state = StoredState {
+            power_actions: vec![StoredAction {
+                maas_id: MAAS_SYSTEM_ID.to_owned(),
+                action: "power_on".to_owned(),
+                success: true,
+                timestamp: 100,
+            }],
+            power_samples: Vec::new(),
+        };
+        store.import_state(state).await.unwrap();
+        let exported = store.export_state().await.unwrap();
+        assert_eq!(exported.power_actions.len(), 1);
+        assert!(exported.power_samples.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+use std::{fs, io, sync::Arc, time::SystemTime};
+
+use anyhow::{bail, Context};
+use reqwest::Url;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket},
+    sync::{mpsc, Mutex},
+};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+    config::{Severity, SyslogConfig},
+    logging::{ACCESS_TARGET, AUDIT_TARGET},
+    notifications::PowerEvent,
+};
+
+/// The enterprise number in structured data IDs, the one RFC 5612 sets aside
+/// for documentation as this project has none of its own.
+const ENTERPRISE_NUMBER: u32 = 32473;
+
+/// Sends power events as RFC 5424 messages to a syslog server over UDP, TCP
+/// or a local Unix socket. The connection is made on the first event, and
+/// made again after a failure.
+pub struct SyslogSink {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+enum Transport {
+    Udp(String),
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixDatagram),
+}
+
+impl SyslogSink {
+    pub fn new(config: &SyslogConfig) -> anyhow::Result<Self> {
+        let url = Url::parse(&config.address)
+            .with_context(|| format!("`{}` is not a syslog address", config.address))?;
+        let host_and_port = || -> anyhow::Result<String> {
+            let host = url.host_str().context("the syslog address has no host")?;
+            Ok(format!("{host}:{}", url.port().unwrap_or(514)))
+        };
+        let transport = match url.scheme() {
+            "udp" => Transport::Udp(host_and_port()?),
+            "tcp" => Transport::Tcp(host_and_port()?),
+            #[cfg(unix)]
+            "unix" => Transport::Unix(url.path().to_owned()),
+            scheme => bail!("syslog over `{scheme}` is not supported, use udp, tcp or unix"),
+        };
+        Ok(Self {
+            transport,
+            facility: config.facility,
+            hostname: fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|hostname| hostname.trim().to_owned())
+                .unwrap_or_else(|_| "-".to_owned()),
+            app_name: config.app_name.clone(),
+            connection: Mutex::default(),
+        })
+    }
+
+    pub async fn send(&self, event: &PowerEvent) {
+        if let Err(e) = self.deliver(&self.format(event)).await {
+            tracing::warn!("failed to send power event to syslog: {e}");
+        }
+    }
+
+    async fn deliver(&self, message: &str) -> io::Result<()> {
+        let mut connection = self.connection.lock().await;
+        let written = self.write(&mut connection, message).await;
+        if written.is_err() {
+            // Dropped, so the next message connects afresh.
+            *connection = None;
+        }
+        written
+    }
+
+    async fn write(&self, connection: &mut Option<Connection>, message: &str) -> io::Result<()> {
+        if connection.is_none() {
+            *connection = Some(self.connect().await?);
+        }
+        match connection.as_mut().expect("connected above") {
+            Connection::Udp(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+            // Octet counting framing, RFC 6587.
+            Connection::Tcp(stream) => {
+                let framed = format!("{} {message}", message.len());
+                stream.write_all(framed.as_bytes()).await
+            }
+            #[cfg(unix)]
+            Connection::Unix(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+        }
+    }
+
+    async fn connect(&self) -> io::Result<Connection> {
+        match &self.transport {
+            Transport::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(address).await?;
+                Ok(Connection::Udp(socket))
+            }
+            Transport::Tcp(address) => Ok(Connection::Tcp(TcpStream::connect(address).await?)),
+            #[cfg(unix)]
+            Transport::Unix(path) => {
+                let socket = tokio::net::UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Connection::Unix(socket))
+            }
+        }
+    }
+
+    fn format(&self, event: &PowerEvent) -> String {
+        let severity = match event.result.severity() {
+            Severity::Info => 5,
+            Severity::Warning => 4,
+            Severity::Error => 3,
+        };
+        let priority = u32::from(self.facility) * 8 + severity;
+        let timestamp = humantime::format_rfc3339_millis(SystemTime::now());
+        let result = serde_json::to_value(event.result)
+            .ok()
+            .and_then(|result| result.as_str().map(str::to_owned))
+            .unwrap_or_default();
+        let mut data = format!(
+            "[power@{ENTERPRISE_NUMBER} machine=\"{}\" action=\"{}\" result=\"{result}\"",
+            escape(&event.machine),
+            event.action.as_str(),
+        );
+        if let Some(error) = &event.error {
+            data.push_str(&format!(" error=\"{}\"", escape(error)));
+        }
+        data.push(']');
+        let mut text = format!("{} of {}: {result}", event.action.as_str(), event.machine);
+        if let Some(error) = &event.error {
+            text.push_str(&format!(", {error}"));
+        }
+        format!(
+            "<{priority}>1 {timestamp} {} {} {} {} {data} {text}",
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            event.action.as_str(),
+        )
+    }
+}
+
+/// Writes log lines to syslog, a message per event with the stream as its
+/// message ID. Lines are sent in order by a background task, so logging never
+/// waits on the network, and dropped while the queue is full.
+#[derive(Clone)]
+pub struct SyslogLog {
+    sink: Arc<SyslogSink>,
+    lines: mpsc::Sender<String>,
+}
+
+/// How many lines can wait to be sent.
+const QUEUE: usize = 1024;
+
+impl SyslogLog {
+    pub fn spawn(sink: SyslogSink) -> Self {
+        let sink = Arc::new(sink);
+        let (lines, mut queue) = mpsc::channel::<String>(QUEUE);
+        let sender = sink.clone();
+        tokio::spawn(async move {
+            let mut failing = false;
+            while let Some(message) = queue.recv().await {
+                match sender.deliver(&message).await {
+                    Ok(()) => failing = false,
+                    // Not logged, the warning would come straight back here.
+                    Err(e) if !failing => {
+                        failing = true;
+                        eprintln!("failed to send log to syslog: {e}");
+                    }
+                    Err(_) => {}
+                }
+            }
+        });
+        Self { sink, lines }
+    }
+
+    fn line(&self, severity: u32, msg_id: &'static str) -> SyslogLine {
+        SyslogLine {
+            log: self.clone(),
+            severity,
+            msg_id,
+            text: Vec::new(),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogLog {
+    type Writer = SyslogLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.line(6, "app")
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let severity = match *meta.level() {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+        let msg_id = match meta.target() {
+            ACCESS_TARGET => "access",
+            AUDIT_TARGET => "audit",
+            _ => "app",
+        };
+        self.line(severity, msg_id)
+    }
+}
+
+/// An event being written, queued as a message once it is complete.
+pub struct SyslogLine {
+    log: SyslogLog,
+    severity: u32,
+    msg_id: &'static str,
+    text: Vec<u8>,
+}
+
+impl io::Write for SyslogLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.text.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogLine {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.text);
+        let text = text.trim_end();
+        if text.is_empty() {
+            return;
+        }
+        let sink = &self.log.sink;
+        let message = format!(
+            "<{}>1 {} {} {} {} {} - {text}",
+            u32::from(sink.facility) * 8 + self.severity,
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            sink.hostname,
+            sink.app_name,
+            std::process::id(),
+            self.msg_id,
+        );
+        let _ = self.log.lines.try_send(message);
+    }
+}
+
+/// Escapes a structured data value, RFC 5424 section 6.3.3.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SyslogLog, SyslogSink};
+    use crate::{
+        config::SyslogConfig,
+        logging::AUDIT_TARGET,
+        notifications::{EventResult, PowerAction, PowerEvent},
+    };
+    use tokio::net::UdpSocket;
+    use tracing_subscriber::{fmt, layer::SubscriberExt};
+
+    #[tokio::test]
+    async fn should_send_rfc5424_messages_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = SyslogConfig {
+            address: format!("udp://{}", server.local_addr().unwrap()),
+            facility: 16,
+            app_name: "maas-power-unifi".to_owned(),
+        };
+        let sink = SyslogSink::new(&config).unwrap();
+        let event =
+            PowerEvent::new("abc\"123", PowerAction::Off, EventResult::Failure).with_error("boom");
+        sink.send(&event).await;
+        let mut buffer = [0; 1024];
+        let read = server.recv(&mut buffer).await.unwrap();
+        let message = String::from_utf8_lossy(&buffer[..read]);
+        // local0 and err.
+        assert!(message.starts_with("<131>1 "), "{message}");
+        assert!(
+            message.contains(" maas-power-unifi ")
+                && message.contains(r#"[power@32473 machine="abc\"123" action="power_off" result="failure" error="boom"]"#)
+                && message.ends_with("power_off of abc\"123: failure, boom"),
+            "{message}"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_send_log_lines_with_their_severity_and_stream() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = SyslogConfig {
+            address: format!("udp://{}", server.local_addr().unwrap()),
+            facility: 16,
+            app_name: "maas-power-unifi".to_owned(),
+        };
+        let log = SyslogLog::spawn(SyslogSink::new(&config).unwrap());
+        let subscriber = tracing_subscriber::registry().with(
+            fmt::layer()
+                .with_writer(log)
+                .with_ansi(false)
+                .without_time(),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(target: AUDIT_TARGET, "power_off of abc123 failed");
+        });
+        let mut buffer = [0; 1024];
+        let read = server.recv(&mut buffer).await.unwrap();
+        let message = String::from_utf8_lossy(&buffer[..read]);
+        // local0 and warning.
+        assert!(message.starts_with("<132>1 "), "{message}");
+        assert!(
+            message.contains(" audit - ") && message.ends_with("power_off of abc123 failed"),
+            "{message}"
+        );
+    }
+}
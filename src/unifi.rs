@@ -1,4 +1,9 @@
 pub mod client;
+#[cfg(test)]
+mod contract;
+pub mod failover;
 pub mod handler;
+pub mod least_privilege;
+pub mod mock;
 pub mod models;
 pub mod self_hosted;
@@ -1,6 +1,7 @@
-use super::models::{Device, UnifiResponse};
+use super::models::{Account, ControllerEvent, Device, Site, Station, UnifiResponse};
 use async_trait::async_trait;
 use dyn_clone::DynClone;
+use std::fmt::Display;
 
 #[derive(Debug)]
 pub enum UnifiError {
@@ -12,14 +13,97 @@ pub enum UnifiError {
     MachinePortIdIncorrect(usize),
     FailedToPowerOn(String),
     FailedToConvertSystemId(String),
+    /// The controller answered with an error of its own.
+    Controller(ControllerApiError),
+    /// Powering on another port could take the switch over its PoE budget.
+    PoeBudgetExceeded(PoeBudgetUse),
 }
 
+/// How much of a switch's PoE budget is in use, in watts.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct PoeBudgetUse {
+    pub draw_watts: f64,
+    pub budget_watts: f64,
+    /// The share of the budget power ons are allowed up to.
+    pub max_utilization: f64,
+}
+
+/// An error the controller reported in its response, e.g.
+/// `{"meta":{"rc":"error","msg":"api.err.NoPermission"}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControllerApiError {
+    /// The HTTP status the controller answered with.
+    pub status: u16,
+    /// e.g. `api.err.NoPermission`.
+    pub code: String,
+    /// A description, only some endpoints give one.
+    pub msg: Option<String>,
+}
+
+impl ControllerApiError {
+    /// The session expired, logging in again fixes it.
+    pub fn is_login_required(&self) -> bool {
+        self.code == "api.err.LoginRequired"
+    }
+}
+
+/// Whether a failed request could succeed if sent again: the controller
+/// could not be reached or answered in time, failed itself, or the session
+/// expired. A missing port or a lack of permission fails the same way every
+/// time.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            return e.is_connect()
+                || e.is_timeout()
+                || e.status().is_some_and(|status| status.is_server_error());
+        }
+        if let Some(e) = e.downcast_ref::<ControllerApiError>() {
+            return e.is_login_required() || e.status >= 500;
+        }
+        false
+    })
+}
+
+impl Display for ControllerApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (HTTP {})", self.code, self.status)?;
+        if let Some(msg) = &self.msg {
+            write!(f, ": {msg}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ControllerApiError {}
+
 #[async_trait]
 pub trait UnifiClient: DynClone {
     async fn login(&self, username: &str, password: &str) -> anyhow::Result<()>;
 
     async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<Device>>>;
 
+    /// The clients currently connected to the site.
+    async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>>;
+
+    /// The events the controller logged recently, newest first. Controllers
+    /// without an event log report none.
+    async fn events(&self) -> anyhow::Result<UnifiResponse<Vec<ControllerEvent>>> {
+        Ok(UnifiResponse::default())
+    }
+
+    /// The sites the account can manage. Controllers without sites report
+    /// none.
+    async fn sites(&self) -> anyhow::Result<UnifiResponse<Vec<Site>>> {
+        Ok(UnifiResponse::default())
+    }
+
+    /// The logged in account and its role on the site. Controllers without
+    /// roles report none.
+    async fn account(&self) -> anyhow::Result<UnifiResponse<Vec<Account>>> {
+        Ok(UnifiResponse::default())
+    }
+
     async fn power_on(
         &self,
         device_id: &str,
@@ -31,5 +115,11 @@ pub trait UnifiClient: DynClone {
         device_id: &str,
         port_number: usize,
     ) -> anyhow::Result<UnifiResponse<()>>;
+
+    /// Opens a connection to the controller, or keeps the pooled one open,
+    /// so power actions do not wait on DNS and TLS.
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 dyn_clone::clone_trait_object!(UnifiClient);
@@ -0,0 +1,94 @@
+//! Checks the controller API still looks the way the client expects, against
+//! a live controller. Ignored by default, run with the `UNIFI_CONTRACT_*`
+//! variables set:
+//!
+//! ```shell
+//! cargo test contract -- --ignored --test-threads 1
+//! ```
+
+use super::{
+    client::UnifiClient,
+    models::{Device, PoeMode},
+    self_hosted::{http_client, UnifiSelfHostedClient},
+};
+use crate::config::{parse_mac, ControllerConfig};
+use std::time::Duration;
+
+fn var(name: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| panic!("`{name}` must be set for contract tests"))
+}
+
+async fn logged_in() -> UnifiSelfHostedClient {
+    let client = UnifiSelfHostedClient::new(
+        var("UNIFI_CONTRACT_URL"),
+        http_client(&ControllerConfig::default()).unwrap(),
+    )
+    .unwrap();
+    client
+        .login(
+            &var("UNIFI_CONTRACT_USERNAME"),
+            &var("UNIFI_CONTRACT_PASSWORD"),
+        )
+        .await
+        .expect("login");
+    client
+}
+
+async fn test_device(client: &UnifiSelfHostedClient) -> Device {
+    let mac = parse_mac(&var("UNIFI_CONTRACT_DEVICE")).unwrap();
+    client
+        .devices()
+        .await
+        .expect("device list")
+        .data
+        .into_iter()
+        .find(|device| device.mac == mac)
+        .unwrap_or_else(|| panic!("the controller does not list {mac}"))
+}
+
+/// Waits for the controller to report `mode` on the test port, it applies
+/// overrides in the background.
+async fn wait_for_poe_mode(client: &UnifiSelfHostedClient, port_id: usize, mode: PoeMode) {
+    for _ in 0..30 {
+        let device = test_device(client).await;
+        if device.port(port_id).and_then(|port| port.poe_mode) == Some(mode) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    panic!("port {port_id} never reported {mode:?}");
+}
+
+#[tokio::test]
+#[ignore = "needs a live controller"]
+async fn should_list_devices_clients_and_events() {
+    let client = logged_in().await;
+    let device = test_device(&client).await;
+    assert!(!device.port_table.is_empty(), "the device has no ports");
+    assert!(device.is_connected(), "the device is not connected");
+    client.clients().await.expect("client list");
+    client.events().await.expect("event list");
+}
+
+/// Powers the designated test port off and on again, leaving it as found.
+/// Only point `UNIFI_CONTRACT_PORT` at a port nothing depends on.
+#[tokio::test]
+#[ignore = "needs a live controller and powers a port off"]
+async fn should_toggle_the_test_port() {
+    let client = logged_in().await;
+    let port_id: usize = var("UNIFI_CONTRACT_PORT").parse().unwrap();
+    let device = test_device(&client).await;
+    let port = device
+        .port(port_id)
+        .unwrap_or_else(|| panic!("the device has no port {port_id}"));
+    assert!(port.supports_poe(), "port {port_id} cannot supply PoE");
+    let original = port.poe_mode.unwrap_or(PoeMode::Auto);
+    let device_id = device.device_id.to_string();
+    client.power_off(&device_id, port_id).await.unwrap();
+    wait_for_poe_mode(&client, port_id, PoeMode::Off).await;
+    client.power_on(&device_id, port_id).await.unwrap();
+    wait_for_poe_mode(&client, port_id, PoeMode::Auto).await;
+    if original == PoeMode::Off {
+        client.power_off(&device_id, port_id).await.unwrap();
+    }
+}
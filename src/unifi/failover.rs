@@ -0,0 +1,248 @@
+use super::{
+    client::UnifiClient,
+    least_privilege::LeastPrivilegeClient,
+    models::{Account, ControllerEvent, Device, Site, Station, UnifiResponse},
+    self_hosted::{self, UnifiSelfHostedClient, DEFAULT_SITE},
+};
+use crate::config::ControllerConfig;
+use async_trait::async_trait;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The client for `controller.site` on the controller at `url`, failing over
+/// to `controller.standby_url` if one is set.
+pub fn controller_client(
+    url: &str,
+    controller: &ControllerConfig,
+) -> anyhow::Result<Box<dyn UnifiClient + Send + Sync>> {
+    let client = site_client(url, controller)?;
+    if controller.least_privilege {
+        return Ok(Box::new(LeastPrivilegeClient::new(client)));
+    }
+    Ok(client)
+}
+
+fn site_client(
+    url: &str,
+    controller: &ControllerConfig,
+) -> anyhow::Result<Box<dyn UnifiClient + Send + Sync>> {
+    let site = controller.site.as_deref().unwrap_or(DEFAULT_SITE);
+    let primary =
+        UnifiSelfHostedClient::new(url, self_hosted::http_client(controller)?)?.with_site(site)?;
+    let Some(standby_url) = &controller.standby_url else {
+        return Ok(Box::new(primary));
+    };
+    let standby = UnifiSelfHostedClient::new(standby_url, self_hosted::http_client(controller)?)?
+        .with_site(site)?;
+    Ok(Box::new(FailoverClient::new(
+        Box::new(primary),
+        Box::new(standby),
+        Duration::from_secs(controller.failback_secs),
+    )))
+}
+
+/// Whether the controller could not be reached at all, rather than answering
+/// with an error.
+fn is_unreachable(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        e.downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
+}
+
+/// Sends requests to the primary controller, or to a standby for the same
+/// site while the primary cannot be reached. Every `failback` the primary's
+/// `/status` is checked, and the primary is used again once it answers.
+#[derive(Clone)]
+pub struct FailoverClient {
+    primary: Box<dyn UnifiClient + Send + Sync>,
+    standby: Box<dyn UnifiClient + Send + Sync>,
+    failback: Duration,
+    /// When the primary was last found unreachable, `None` while it is used.
+    primary_down: Arc<Mutex<Option<Instant>>>,
+}
+
+impl FailoverClient {
+    pub fn new(
+        primary: Box<dyn UnifiClient + Send + Sync>,
+        standby: Box<dyn UnifiClient + Send + Sync>,
+        failback: Duration,
+    ) -> Self {
+        Self {
+            primary,
+            standby,
+            failback,
+            primary_down: Arc::default(),
+        }
+    }
+
+    fn set_primary_down(&self, down: Option<Instant>) {
+        *self.primary_down.lock().unwrap_or_else(|e| e.into_inner()) = down;
+    }
+
+    fn fail_over(&self, error: &anyhow::Error) {
+        tracing::warn!("the primary controller cannot be reached, using the standby: {error:#}");
+        self.set_primary_down(Some(Instant::now()));
+    }
+
+    /// Whether to send a request to the standby, checking the primary first
+    /// if it has been down for longer than `failback`.
+    async fn on_standby(&self) -> bool {
+        let down = *self.primary_down.lock().unwrap_or_else(|e| e.into_inner());
+        match down {
+            None => false,
+            Some(since) if since.elapsed() < self.failback => true,
+            Some(_) => match self.primary.warm_up().await {
+                Ok(()) => {
+                    tracing::info!("the primary controller answers again, failing back");
+                    self.set_primary_down(None);
+                    false
+                }
+                Err(e) => {
+                    tracing::debug!("the primary controller still cannot be reached: {e:#}");
+                    self.set_primary_down(Some(Instant::now()));
+                    true
+                }
+            },
+        }
+    }
+}
+
+/// Sends a request to the primary unless it is down, and to the standby if
+/// it is or turns out to be.
+macro_rules! with_failover {
+    ($self:ident, $method:ident($($arg:expr),*)) => {{
+        if !$self.on_standby().await {
+            match $self.primary.$method($($arg),*).await {
+                Err(e) if is_unreachable(&e) => $self.fail_over(&e),
+                result => return result,
+            }
+        }
+        $self.standby.$method($($arg),*).await
+    }};
+}
+
+#[async_trait]
+impl UnifiClient for FailoverClient {
+    /// Logs in to both, so the standby has a session ready.
+    async fn login(&self, username: &str, password: &str) -> anyhow::Result<()> {
+        let (primary, standby) = tokio::join!(
+            self.primary.login(username, password),
+            self.standby.login(username, password)
+        );
+        match primary {
+            Err(e) if is_unreachable(&e) => {
+                self.fail_over(&e);
+                standby
+            }
+            primary => {
+                if let Err(e) = standby {
+                    tracing::warn!("failed to log in to the standby controller: {e:#}");
+                }
+                primary
+            }
+        }
+    }
+
+    async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<Device>>> {
+        with_failover!(self, devices())
+    }
+
+    async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+        with_failover!(self, clients())
+    }
+
+    async fn events(&self) -> anyhow::Result<UnifiResponse<Vec<ControllerEvent>>> {
+        with_failover!(self, events())
+    }
+
+    async fn sites(&self) -> anyhow::Result<UnifiResponse<Vec<Site>>> {
+        with_failover!(self, sites())
+    }
+
+    async fn account(&self) -> anyhow::Result<UnifiResponse<Vec<Account>>> {
+        with_failover!(self, account())
+    }
+
+    async fn power_on(
+        &self,
+        device_id: &str,
+        port_number: usize,
+    ) -> anyhow::Result<UnifiResponse<()>> {
+        with_failover!(self, power_on(device_id, port_number))
+    }
+
+    async fn power_off(
+        &self,
+        device_id: &str,
+        port_number: usize,
+    ) -> anyhow::Result<UnifiResponse<()>> {
+        with_failover!(self, power_off(device_id, port_number))
+    }
+
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        with_failover!(self, warm_up())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FailoverClient;
+    use crate::unifi::{client::UnifiClient, self_hosted::UnifiSelfHostedClient};
+    use std::time::{Duration, Instant};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn controller(devices: u64) -> MockServer {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "meta": {"rc": "ok"},
+                "data": []
+            })))
+            .expect(devices)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/status"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        mock_server
+    }
+
+    fn client(url: &str) -> Box<UnifiSelfHostedClient> {
+        Box::new(UnifiSelfHostedClient::new(url, reqwest::Client::new()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_fail_over_while_primary_is_unreachable() {
+        let standby = controller(2).await;
+        let failover = FailoverClient::new(
+            client("http://127.0.0.1:1"),
+            client(&standby.uri()),
+            Duration::ZERO,
+        );
+        failover.devices().await.unwrap();
+        // The primary is checked again and still down.
+        failover.devices().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_fail_back_once_primary_answers() {
+        let primary = controller(1).await;
+        let standby = controller(0).await;
+        let failover = FailoverClient::new(
+            client(&primary.uri()),
+            client(&standby.uri()),
+            Duration::ZERO,
+        );
+        failover.set_primary_down(Some(Instant::now()));
+        failover.devices().await.unwrap();
+        assert!(failover.primary_down.lock().unwrap().is_none());
+    }
+}
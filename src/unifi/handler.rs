@@ -1,68 +1,408 @@
 use super::{
-    client::{UnifiClient, UnifiError},
-    models::{Device, DeviceId},
+    client::{is_transient, ControllerApiError, UnifiClient, UnifiError},
+    models::{ControllerEvent, Device, DeviceId, Station},
 };
+use crate::config::{Jitter, RetryConfig, RetryPolicy};
+use anyhow::{bail, Context};
 use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Keeps an error the controller reported as it is, rather than as text.
+fn unifi_error(e: anyhow::Error, otherwise: fn(String) -> UnifiError) -> UnifiError {
+    match e.downcast::<ControllerApiError>() {
+        Ok(api_error) => UnifiError::Controller(api_error),
+        Err(e) => otherwise(e.to_string()),
+    }
+}
 
 #[derive(Clone)]
 pub struct UnifiHandler {
     pub client: Box<dyn UnifiClient + Send + Sync>,
+    /// Device IDs by switch MAC, which practically never change. An ID is
+    /// forgotten when the controller fails a request for it.
+    device_ids: Arc<RwLock<HashMap<MacAddress, DeviceId>>>,
+    health: Arc<RwLock<ControllerHealth>>,
+    cache_stats: Arc<RwLock<CacheStats>>,
+    /// The username and password of the last login, to log in again with
+    /// when the session expires.
+    credentials: Arc<RwLock<Option<(String, String)>>>,
+    /// Why the account cannot power ports, as of the last check.
+    permission_problem: Arc<RwLock<Option<String>>>,
+    retry: RetryConfig,
+}
+
+/// The pause before sending a request again after `attempt` tries, doubling
+/// each time.
+fn retry_delay(policy: &RetryPolicy, attempt: u32) -> Duration {
+    let delay = policy
+        .base_delay_ms
+        .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)))
+        .min(policy.max_delay_ms);
+    let random = |bound: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(attempt);
+        hasher.finish() % (bound + 1)
+    };
+    Duration::from_millis(match policy.jitter {
+        Jitter::None => delay,
+        Jitter::Full => random(delay),
+        Jitter::Equal => delay / 2 + random(delay - delay / 2),
+    })
+}
+
+/// How the controller has been answering, times are in seconds since the
+/// epoch.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ControllerHealth {
+    /// Whether the last login or device list succeeded.
+    pub healthy: bool,
+    pub last_login: Option<u64>,
+    pub last_device_fetch: Option<u64>,
+}
+
+/// How the device ID cache has been used since startup.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// Device IDs currently cached.
+    pub size: usize,
+    pub hits: u64,
+    /// Lookups that had to fetch the device list.
+    pub misses: u64,
+    /// Device lists fetched, each refreshing every ID.
+    pub refreshes: u64,
+    /// IDs forgotten after a request for them failed.
+    pub evictions: u64,
+    /// When the cache was last refreshed, in seconds since the epoch.
+    pub last_refresh: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
 impl UnifiHandler {
-    pub async fn power_on(&self, device_id: &DeviceId, port_id: usize) -> Result<(), UnifiError> {
-        self.client
-            .power_on(&device_id.to_string(), port_id)
+    pub fn new(client: Box<dyn UnifiClient + Send + Sync>) -> Self {
+        Self {
+            client,
+            device_ids: Arc::default(),
+            health: Arc::default(),
+            cache_stats: Arc::default(),
+            credentials: Arc::default(),
+            permission_problem: Arc::default(),
+            retry: RetryConfig::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> anyhow::Result<()> {
+        let result = self.client.login(username, password).await;
+        let mut health = self.health.write().unwrap_or_else(|e| e.into_inner());
+        health.healthy = result.is_ok();
+        if result.is_ok() {
+            health.last_login = Some(now());
+            *self.credentials.write().unwrap_or_else(|e| e.into_inner()) =
+                Some((username.to_owned(), password.to_owned()));
+        }
+        result
+    }
+
+    /// Sends a request, and again while it fails in a way that can pass,
+    /// logging in again first if the session expired. Other failures, such
+    /// as a missing port, are returned straight away.
+    async fn with_session<'a, T, F, Fut>(
+        &'a self,
+        policy: &RetryPolicy,
+        request: F,
+    ) -> anyhow::Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>> + 'a,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match request().await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+            if attempt >= policy.attempts || !is_transient(&error) {
+                return Err(error);
+            }
+            let expired = error
+                .downcast_ref::<ControllerApiError>()
+                .is_some_and(ControllerApiError::is_login_required);
+            if expired {
+                let credentials = self
+                    .credentials
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone();
+                let Some((username, password)) = credentials else {
+                    return Err(error);
+                };
+                tracing::info!("the controller session expired, logging in again");
+                self.login(&username, &password).await?;
+            } else {
+                tracing::debug!("retrying a failed controller request: {error:#}");
+                tokio::time::sleep(retry_delay(policy, attempt)).await;
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Checks the account can power ports on the site rather than only list
+    /// them, a read-only account otherwise only shows up as failed power
+    /// actions. A controller which cannot say is taken to allow it.
+    pub async fn check_permissions(&self) -> Option<String> {
+        let problem = match self
+            .with_session(&self.retry.reads, || self.client.account())
             .await
-            .map(|_| ())
-            .map_err(|e| UnifiError::FailedToPowerOn(e.to_string()))
+        {
+            Ok(response) => response
+                .data
+                .first()
+                .and_then(|account| account.permission_problem()),
+            Err(e) => {
+                tracing::debug!("failed to read the account's role on the site: {e:#}");
+                None
+            }
+        };
+        *self
+            .permission_problem
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = problem.clone();
+        problem
+    }
+
+    pub fn permission_problem(&self) -> Option<String> {
+        self.permission_problem
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn health(&self) -> ControllerHealth {
+        *self.health.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub async fn power_on(&self, device_id: &DeviceId, port_id: usize) -> Result<(), UnifiError> {
+        let device = device_id.to_string();
+        self.with_session(&self.retry.mutations, || {
+            self.client.power_on(&device, port_id)
+        })
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            self.forget_device_id(device_id);
+            unifi_error(e, UnifiError::FailedToPowerOn)
+        })
     }
 
     pub async fn power_off(&self, device_id: &DeviceId, port_id: usize) -> Result<(), UnifiError> {
-        self.client
-            .power_off(&device_id.to_string(), port_id)
-            .await
-            .map(|_| ())
-            .map_err(|e| UnifiError::FailedToPowerOn(e.to_string()))
+        let device = device_id.to_string();
+        self.with_session(&self.retry.mutations, || {
+            self.client.power_off(&device, port_id)
+        })
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            self.forget_device_id(device_id);
+            unifi_error(e, UnifiError::FailedToPowerOn)
+        })
     }
 
     // Given a device mac, return the ID in the unifi controller
     pub async fn device_id(&self, device_mac: &MacAddress) -> Result<DeviceId, UnifiError> {
-        let response = self
-            .client
-            .devices()
-            .await
-            .map_err(|e| UnifiError::DeviceListError(e.to_string()))?;
-        let device = response
-            .data
+        let cached = self
+            .device_ids
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(device_mac)
+            .cloned();
+        {
+            let mut stats = self.cache_stats.write().unwrap_or_else(|e| e.into_inner());
+            match cached {
+                Some(_) => stats.hits += 1,
+                None => stats.misses += 1,
+            }
+        }
+        if let Some(device_id) = cached {
+            return Ok(device_id);
+        }
+        self.devices()
+            .await?
             .into_iter()
             .find(|device| device.mac == *device_mac)
-            .ok_or(UnifiError::DeviceNotFound(device_mac.to_string()))?;
-        Ok(device.device_id)
+            .map(|device| device.device_id)
+            .ok_or(UnifiError::DeviceNotFound(device_mac.to_string()))
+    }
+
+    fn forget_device_id(&self, device_id: &DeviceId) {
+        let mut device_ids = self.device_ids.write().unwrap_or_else(|e| e.into_inner());
+        let before = device_ids.len();
+        device_ids.retain(|_, cached| cached != device_id);
+        let evicted = (before - device_ids.len()) as u64;
+        drop(device_ids);
+        self.cache_stats
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .evictions += evicted;
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let size = self
+            .device_ids
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .len();
+        CacheStats {
+            size,
+            ..*self.cache_stats.read().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    /// Every device on the controller, remembering their IDs so the startup
+    /// check leaves the first power action nothing to look up.
+    pub async fn devices(&self) -> Result<Vec<Device>, UnifiError> {
+        let result = self
+            .with_session(&self.retry.reads, || self.client.devices())
+            .await;
+        let mut health = self.health.write().unwrap_or_else(|e| e.into_inner());
+        health.healthy = result.is_ok();
+        if result.is_ok() {
+            health.last_device_fetch = Some(now());
+        }
+        drop(health);
+        if let Ok(response) = &result {
+            self.device_ids
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(
+                    response
+                        .data
+                        .iter()
+                        .map(|device| (device.mac, device.device_id.clone())),
+                );
+            let mut stats = self.cache_stats.write().unwrap_or_else(|e| e.into_inner());
+            stats.refreshes += 1;
+            stats.last_refresh = Some(now());
+        }
+        result
+            .map(|response| response.data)
+            .map_err(|e| unifi_error(e, UnifiError::DeviceListError))
+    }
+
+    pub async fn clients(&self) -> Result<Vec<Station>, UnifiError> {
+        self.with_session(&self.retry.reads, || self.client.clients())
+            .await
+            .map(|response| response.data)
+            .map_err(|e| unifi_error(e, UnifiError::DeviceListError))
+    }
+
+    pub async fn events(&self) -> Result<Vec<ControllerEvent>, UnifiError> {
+        self.with_session(&self.retry.reads, || self.client.events())
+            .await
+            .map(|response| response.data)
+            .map_err(|e| unifi_error(e, UnifiError::DeviceListError))
+    }
+
+    /// The short name of the site called `name`, matched against the name
+    /// the controller shows or the short name itself.
+    pub async fn resolve_site(&self, name: &str) -> anyhow::Result<String> {
+        let sites = self
+            .with_session(&self.retry.reads, || self.client.sites())
+            .await
+            .context("failed to list the sites on the controller")?
+            .data;
+        if let Some(site) = sites.iter().find(|site| {
+            site.name == name
+                || site
+                    .desc
+                    .as_deref()
+                    .is_some_and(|desc| desc.eq_ignore_ascii_case(name))
+        }) {
+            return Ok(site.name.clone());
+        }
+        let available = sites
+            .iter()
+            .map(|site| match &site.desc {
+                Some(desc) => format!("`{desc}` ({})", site.name),
+                None => format!("`{}`", site.name),
+            })
+            .collect::<Vec<_>>();
+        bail!(
+            "the controller has no site named `{name}`, the account can see: {}",
+            if available.is_empty() {
+                "none".to_owned()
+            } else {
+                available.join(", ")
+            }
+        )
     }
 
     pub async fn device(&self, device_id: &DeviceId) -> Result<Device, UnifiError> {
-        self.client
+        let device = self
             .devices()
-            .await
-            .map_err(|e| UnifiError::DeviceListError(e.to_string()))?
-            .data
+            .await?
             .into_iter()
-            .find(|device| device.device_id == *device_id)
-            .ok_or(UnifiError::DeviceNotFound(device_id.to_string()))
+            .find(|device| device.device_id == *device_id);
+        device.ok_or_else(|| {
+            self.forget_device_id(device_id);
+            UnifiError::DeviceNotFound(device_id.to_string())
+        })
+    }
+
+    /// Warms the connection to the controller every `interval`, starting now.
+    pub fn keep_warm(&self, interval: Duration) {
+        let handler = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if let Err(e) = handler.client.warm_up().await {
+                    tracing::debug!("failed to warm the controller connection: {e:?}");
+                }
+            }
+        });
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::config::{Jitter, RetryConfig, RetryPolicy};
     use crate::unifi::{
         self,
         client::UnifiClient,
-        handler::UnifiHandler,
-        models::{DeviceId, Meta, PoeMode, Port, UnifiResponse},
+        handler::{retry_delay, UnifiHandler},
+        models::{DeviceId, Meta, PoeMode, Port, Station, UnifiResponse},
+        self_hosted::UnifiSelfHostedClient,
     };
     use async_trait::async_trait;
     use mac_address::MacAddress;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
 
     const UNIFI_DEVICE_MAC: [u8; 6] = [00, 00, 00, 00, 00, 00];
     const UNIFI_DEVICE_ID: &str = "device-id";
@@ -72,7 +412,9 @@ mod test {
     struct FakeUnifiClient {}
 
     #[derive(Clone)]
-    struct FailingUnifiClient {}
+    struct FailingUnifiClient {
+        device_lists: Arc<AtomicUsize>,
+    }
 
     #[async_trait]
     impl UnifiClient for FakeUnifiClient {
@@ -82,18 +424,27 @@ mod test {
 
         async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<unifi::models::Device>>> {
             Ok(UnifiResponse {
-                meta: Meta { rc: "".to_owned() },
+                meta: Meta {
+                    rc: "".to_owned(),
+                    ..Default::default()
+                },
                 data: vec![unifi::models::Device {
                     mac: MacAddress::from(UNIFI_DEVICE_MAC),
                     device_id: DeviceId::new(UNIFI_DEVICE_ID),
                     port_table: vec![Port {
                         port_idx: MACHINE_PORT,
                         poe_mode: Some(PoeMode::Auto),
+                        ..Default::default()
                     }],
+                    ..Default::default()
                 }],
             })
         }
 
+        async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+            Ok(UnifiResponse::default())
+        }
+
         async fn power_on(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
             Ok(UnifiResponse {
                 data: (),
@@ -116,19 +467,29 @@ mod test {
         }
 
         async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<unifi::models::Device>>> {
+            self.device_lists.fetch_add(1, Ordering::SeqCst);
             Ok(UnifiResponse {
-                meta: Meta { rc: "".to_owned() },
+                meta: Meta {
+                    rc: "".to_owned(),
+                    ..Default::default()
+                },
                 data: vec![unifi::models::Device {
                     mac: MacAddress::from(UNIFI_DEVICE_MAC),
                     device_id: DeviceId::new(UNIFI_DEVICE_ID),
                     port_table: vec![Port {
                         port_idx: MACHINE_PORT,
                         poe_mode: Some(PoeMode::Auto),
+                        ..Default::default()
                     }],
+                    ..Default::default()
                 }],
             })
         }
 
+        async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+            Ok(UnifiResponse::default())
+        }
+
         async fn power_on(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
             Err(anyhow::anyhow!("failed"))
         }
@@ -141,7 +502,7 @@ mod test {
     #[tokio::test]
     async fn should_get_device_id() {
         let client = Box::new(FakeUnifiClient {});
-        let handler = UnifiHandler { client };
+        let handler = UnifiHandler::new(client);
         let device_id = handler
             .device_id(&MacAddress::from(UNIFI_DEVICE_MAC))
             .await
@@ -149,10 +510,189 @@ mod test {
         assert_eq!(device_id, DeviceId::new(UNIFI_DEVICE_ID));
     }
 
+    #[tokio::test]
+    async fn should_cache_device_id_until_a_request_fails() {
+        let device_lists = Arc::new(AtomicUsize::new(0));
+        let client = Box::new(FailingUnifiClient {
+            device_lists: device_lists.clone(),
+        });
+        let handler = UnifiHandler::new(client);
+        let mac = MacAddress::from(UNIFI_DEVICE_MAC);
+        let device_id = handler.device_id(&mac).await.unwrap();
+        assert_eq!(handler.device_id(&mac).await.unwrap(), device_id);
+        assert_eq!(device_lists.load(Ordering::SeqCst), 1);
+        assert!(handler.power_on(&device_id, MACHINE_PORT).await.is_err());
+        handler.device_id(&mac).await.unwrap();
+        assert_eq!(device_lists.load(Ordering::SeqCst), 2);
+        let stats = handler.cache_stats();
+        assert_eq!(
+            (stats.size, stats.hits, stats.misses, stats.evictions),
+            (1, 1, 2, 1)
+        );
+        assert_eq!(stats.refreshes, 2);
+    }
+
+    #[tokio::test]
+    async fn should_remember_device_ids_from_device_list() {
+        let device_lists = Arc::new(AtomicUsize::new(0));
+        let client = Box::new(FailingUnifiClient {
+            device_lists: device_lists.clone(),
+        });
+        let handler = UnifiHandler::new(client);
+        handler.devices().await.unwrap();
+        handler
+            .device_id(&MacAddress::from(UNIFI_DEVICE_MAC))
+            .await
+            .unwrap();
+        assert_eq!(device_lists.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_log_in_again_when_the_session_expires() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/login"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "meta": {"rc": "error", "msg": "api.err.LoginRequired"},
+                "data": []
+            })))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "meta": {"rc": "ok"},
+                "data": []
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let handler = UnifiHandler::new(Box::new(client));
+        handler.login("admin", "secret").await.unwrap();
+        assert!(handler.devices().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_resolve_sites_by_name() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/self/sites"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "meta": {"rc": "ok"},
+                "data": [
+                    {"_id": "1", "name": "default", "desc": "Default"},
+                    {"_id": "2", "name": "k3x9tq1z", "desc": "Rack Room"}
+                ]
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let handler = UnifiHandler::new(Box::new(client));
+        assert_eq!(handler.resolve_site("rack room").await.unwrap(), "k3x9tq1z");
+        assert_eq!(handler.resolve_site("k3x9tq1z").await.unwrap(), "k3x9tq1z");
+        let error = handler.resolve_site("Lab").await.unwrap_err().to_string();
+        assert!(error.contains("`Default` (default), `Rack Room` (k3x9tq1z)"));
+    }
+
+    #[tokio::test]
+    async fn should_report_a_read_only_account() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/self"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "meta": {"rc": "ok"},
+                "data": [{"name": "maas", "site_role": "readonly", "is_super": false}]
+            })))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/self"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "meta": {"rc": "ok"},
+                "data": [{"name": "maas", "site_role": "admin", "is_super": false}]
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let handler = UnifiHandler::new(Box::new(client));
+        let problem = handler.check_permissions().await.unwrap();
+        assert!(problem.contains("`maas` has the `readonly` role"));
+        assert_eq!(handler.permission_problem(), Some(problem));
+        assert_eq!(handler.check_permissions().await, None);
+        assert_eq!(handler.permission_problem(), None);
+    }
+
+    #[tokio::test]
+    async fn should_retry_only_transient_failures() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "meta": {"rc": "ok"},
+                "data": []
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "meta": {"rc": "error", "msg": "api.err.NoPermission"}
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let client = UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let policy = RetryPolicy {
+            attempts: 3,
+            base_delay_ms: 1,
+            max_delay_ms: 1,
+            jitter: Jitter::None,
+        };
+        let handler = UnifiHandler::new(Box::new(client)).with_retry(RetryConfig {
+            reads: policy,
+            mutations: policy,
+        });
+        assert!(handler.devices().await.unwrap().is_empty());
+        let device_id = DeviceId::new(UNIFI_DEVICE_ID);
+        assert!(handler.power_on(&device_id, MACHINE_PORT).await.is_err());
+    }
+
+    #[test]
+    fn should_keep_retry_delays_within_jitter_bounds() {
+        let mut policy = RetryPolicy {
+            attempts: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 300,
+            jitter: Jitter::None,
+        };
+        let delays: Vec<_> = (1..=3).map(|n| retry_delay(&policy, n)).collect();
+        assert_eq!(delays, [100, 200, 300].map(Duration::from_millis));
+        policy.jitter = Jitter::Equal;
+        let delay = retry_delay(&policy, 2);
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        policy.jitter = Jitter::Full;
+        assert!(retry_delay(&policy, 2) <= Duration::from_millis(200));
+    }
+
     #[tokio::test]
     async fn should_get_device() {
         let client = Box::new(FakeUnifiClient {});
-        let handler = UnifiHandler { client };
+        let handler = UnifiHandler::new(client);
         let device = handler
             .device(&DeviceId::new(UNIFI_DEVICE_ID))
             .await
@@ -163,7 +703,7 @@ mod test {
     #[tokio::test]
     async fn should_power_on() {
         let client = Box::new(FakeUnifiClient {});
-        let handler = UnifiHandler { client };
+        let handler = UnifiHandler::new(client);
         handler
             .power_on(&DeviceId::new(UNIFI_DEVICE_ID), MACHINE_PORT)
             .await
@@ -172,8 +712,10 @@ mod test {
 
     #[tokio::test]
     async fn should_error_if_power_on_fails() {
-        let client = Box::new(FailingUnifiClient {});
-        let handler = UnifiHandler { client };
+        let client = Box::new(FailingUnifiClient {
+            device_lists: Arc::default(),
+        });
+        let handler = UnifiHandler::new(client);
         let result = handler
             .power_on(&DeviceId::new(UNIFI_DEVICE_ID), MACHINE_PORT)
             .await;
@@ -183,7 +725,7 @@ mod test {
     #[tokio::test]
     async fn should_power_off() {
         let client = Box::new(FakeUnifiClient {});
-        let handler = UnifiHandler { client };
+        let handler = UnifiHandler::new(client);
         handler
             .power_off(&DeviceId::new(UNIFI_DEVICE_ID), MACHINE_PORT)
             .await
@@ -192,8 +734,10 @@ mod test {
 
     #[tokio::test]
     async fn should_error_if_power_off_fails() {
-        let client = Box::new(FailingUnifiClient {});
-        let handler = UnifiHandler { client };
+        let client = Box::new(FailingUnifiClient {
+            device_lists: Arc::default(),
+        });
+        let handler = UnifiHandler::new(client);
         let result = handler
             .power_off(&DeviceId::new(UNIFI_DEVICE_ID), MACHINE_PORT)
             .await;
@@ -0,0 +1,118 @@
+use super::{
+    client::UnifiClient,
+    models::{Account, ControllerEvent, Device, Site, Station, UnifiResponse},
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+
+/// The only controller endpoints least-privilege mode sends requests to.
+/// `/status` answers without a login, so needs no role at all.
+pub const ENDPOINTS: [&str; 4] = ["/status", "/api/login", "stat/device", "rest/device"];
+
+fn not_used(endpoint: &str) -> anyhow::Error {
+    anyhow!("`{endpoint}` is not used with `controller.least_privilege` set")
+}
+
+/// Sends only the requests powering mapped ports needs: the device list and
+/// port overrides. Anything else, such as the client list or the event log,
+/// fails without a request, so the account can be limited to managing
+/// devices on the site.
+#[derive(Clone)]
+pub struct LeastPrivilegeClient {
+    inner: Box<dyn UnifiClient + Send + Sync>,
+}
+
+impl LeastPrivilegeClient {
+    pub fn new(inner: Box<dyn UnifiClient + Send + Sync>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl UnifiClient for LeastPrivilegeClient {
+    async fn login(&self, username: &str, password: &str) -> anyhow::Result<()> {
+        self.inner.login(username, password).await
+    }
+
+    async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<Device>>> {
+        self.inner.devices().await
+    }
+
+    async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+        Err(not_used("stat/sta"))
+    }
+
+    async fn events(&self) -> anyhow::Result<UnifiResponse<Vec<ControllerEvent>>> {
+        Err(not_used("stat/event"))
+    }
+
+    async fn sites(&self) -> anyhow::Result<UnifiResponse<Vec<Site>>> {
+        Err(not_used("/api/self/sites"))
+    }
+
+    async fn account(&self) -> anyhow::Result<UnifiResponse<Vec<Account>>> {
+        Err(not_used("self"))
+    }
+
+    async fn power_on(
+        &self,
+        device_id: &str,
+        port_number: usize,
+    ) -> anyhow::Result<UnifiResponse<()>> {
+        self.inner.power_on(device_id, port_number).await
+    }
+
+    async fn power_off(
+        &self,
+        device_id: &str,
+        port_number: usize,
+    ) -> anyhow::Result<UnifiResponse<()>> {
+        self.inner.power_off(device_id, port_number).await
+    }
+
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        self.inner.warm_up().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ENDPOINTS;
+    use crate::{config::ControllerConfig, unifi::failover::controller_client};
+    use serde_json::json;
+    use wiremock::{matchers::any, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn should_only_send_requests_to_allowed_endpoints() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({"meta": {"rc": "ok"}, "data": []})),
+            )
+            .mount(&mock_server)
+            .await;
+        let config = ControllerConfig {
+            least_privilege: true,
+            ..Default::default()
+        };
+        let client = controller_client(&mock_server.uri(), &config).unwrap();
+        client.login("maas", "secret").await.unwrap();
+        client.devices().await.unwrap();
+        client.power_on("device-id", 1).await.unwrap();
+        client.power_off("device-id", 1).await.unwrap();
+        client.warm_up().await.unwrap();
+        assert!(client.clients().await.is_err());
+        assert!(client.events().await.is_err());
+        assert!(client.sites().await.is_err());
+        assert!(client.account().await.is_err());
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 5);
+        for request in requests {
+            let path = request.url.path();
+            assert!(
+                ENDPOINTS.iter().any(|endpoint| path.contains(endpoint)),
+                "{path} is not an allowed endpoint"
+            );
+        }
+    }
+}
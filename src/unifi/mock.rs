@@ -0,0 +1,204 @@
+use super::{
+    client::{ControllerApiError, UnifiClient},
+    models::{Device, DeviceId, PoeMode, Port, Station, UnifiResponse},
+};
+use crate::config::Config;
+use async_trait::async_trait;
+use mac_address::MacAddress;
+use std::sync::{Arc, Mutex};
+
+/// The power a mock machine draws while its port is on, in watts.
+const MOCK_DRAW_WATTS: f64 = 5.0;
+
+/// An in-memory controller with every configured device and mapped port,
+/// for running the service without UniFi hardware. Every port starts off,
+/// and a powered port draws power, has a link and shows the machine's NIC
+/// as a client. Clones share the same devices.
+#[derive(Clone)]
+pub struct MockUnifiClient {
+    devices: Arc<Mutex<Vec<MockDevice>>>,
+}
+
+#[derive(Clone)]
+struct MockDevice {
+    mac: MacAddress,
+    name: String,
+    ports: Vec<MockPort>,
+}
+
+#[derive(Clone)]
+struct MockPort {
+    port_idx: usize,
+    nic: Option<MacAddress>,
+    on: bool,
+}
+
+impl MockDevice {
+    fn device_id(&self) -> DeviceId {
+        DeviceId::new(self.mac.to_string().replace(':', "").to_lowercase())
+    }
+}
+
+impl MockUnifiClient {
+    pub fn new(config: &Config) -> Self {
+        let devices = config
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| MockDevice {
+                mac: device.mac,
+                name: format!("mock-switch-{}", index + 1),
+                ports: device
+                    .machines
+                    .iter()
+                    .map(|machine| MockPort {
+                        port_idx: machine.port_id,
+                        nic: machine.mac,
+                        on: false,
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            devices: Arc::new(Mutex::new(devices)),
+        }
+    }
+
+    fn set_power(&self, device_id: &str, port_number: usize, on: bool) -> anyhow::Result<()> {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let port = devices
+            .iter_mut()
+            .find(|device| device.device_id().to_string() == device_id)
+            .and_then(|device| {
+                device
+                    .ports
+                    .iter_mut()
+                    .find(|port| port.port_idx == port_number)
+            })
+            .ok_or_else(|| ControllerApiError {
+                status: 400,
+                code: "api.err.InvalidTarget".to_owned(),
+                msg: None,
+            })?;
+        port.on = on;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UnifiClient for MockUnifiClient {
+    async fn login(&self, _: &str, _: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<Device>>> {
+        let devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let data = devices
+            .iter()
+            .map(|device| Device {
+                mac: device.mac,
+                device_id: device.device_id(),
+                port_table: device
+                    .ports
+                    .iter()
+                    .map(|port| Port {
+                        port_idx: port.port_idx,
+                        name: Some(format!("Port {}", port.port_idx)),
+                        poe_mode: Some(if port.on { PoeMode::Auto } else { PoeMode::Off }),
+                        poe_power: Some(if port.on { MOCK_DRAW_WATTS } else { 0.0 }),
+                        up: Some(port.on),
+                        ..Default::default()
+                    })
+                    .collect(),
+                name: Some(device.name.clone()),
+                state: Some(1),
+                adopted: Some(true),
+                ..Default::default()
+            })
+            .collect();
+        Ok(UnifiResponse {
+            data,
+            ..Default::default()
+        })
+    }
+
+    async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+        let devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let data = devices
+            .iter()
+            .flat_map(|device| {
+                device
+                    .ports
+                    .iter()
+                    .filter(|port| port.on)
+                    .filter_map(move |port| {
+                        Some(Station {
+                            mac: port.nic?,
+                            sw_mac: Some(device.mac),
+                            sw_port: Some(port.port_idx),
+                            ..Default::default()
+                        })
+                    })
+            })
+            .collect();
+        Ok(UnifiResponse {
+            data,
+            ..Default::default()
+        })
+    }
+
+    async fn power_on(
+        &self,
+        device_id: &str,
+        port_number: usize,
+    ) -> anyhow::Result<UnifiResponse<()>> {
+        self.set_power(device_id, port_number, true)?;
+        Ok(UnifiResponse::default())
+    }
+
+    async fn power_off(
+        &self,
+        device_id: &str,
+        port_number: usize,
+    ) -> anyhow::Result<UnifiResponse<()>> {
+        self.set_power(device_id, port_number, false)?;
+        Ok(UnifiResponse::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MockUnifiClient;
+    use crate::{
+        config::{Config, Device, Machine},
+        unifi::client::UnifiClient,
+    };
+    use mac_address::MacAddress;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn should_power_configured_ports() {
+        let config = Config {
+            devices: vec![Device {
+                mac: MacAddress::from_str("00:00:00:00:00:01").unwrap(),
+                machines: vec![Machine {
+                    maas_id: "abc123".to_owned(),
+                    port_id: 2,
+                    mac: Some(MacAddress::from_str("aa:00:00:00:00:01").unwrap()),
+                    ..Default::default()
+                }],
+            }],
+            ..Default::default()
+        };
+        let client = MockUnifiClient::new(&config);
+        let device_id = client.devices().await.unwrap().data[0]
+            .device_id
+            .to_string();
+        assert!(client.clients().await.unwrap().data.is_empty());
+        client.clone().power_on(&device_id, 2).await.unwrap();
+        let devices = client.devices().await.unwrap().data;
+        assert_eq!(devices[0].power_status(2).unwrap().status, "running");
+        assert_eq!(client.clients().await.unwrap().data[0].sw_port, Some(2));
+        assert!(client.power_on(&device_id, 3).await.is_err());
+    }
+}
@@ -29,6 +29,9 @@ pub struct UnifiResponse<T> {
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Meta {
     pub rc: String,
+    /// The error code when `rc` is `error`, e.g. `api.err.NoPermission`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -36,26 +39,142 @@ pub struct Device {
     pub mac: MacAddress,
     pub device_id: DeviceId,
     pub port_table: Vec<Port>,
+    /// The name given to the device on the controller, if any.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// 1 while the device is connected to the controller.
+    #[serde(default)]
+    pub state: Option<u32>,
+    #[serde(default)]
+    pub adopted: Option<bool>,
+    /// When the controller last heard from the device, in seconds since the
+    /// epoch.
+    #[serde(default)]
+    pub last_seen: Option<u64>,
+    /// Seconds since the device booted.
+    #[serde(default)]
+    pub uptime: Option<u64>,
+    /// The PoE budget of the device in watts.
+    #[serde(default, deserialize_with = "de_optional_f64")]
+    pub total_max_power: Option<f64>,
+    /// In degrees Celsius, only devices with a sensor report it.
+    #[serde(default, deserialize_with = "de_optional_f64")]
+    pub general_temperature: Option<f64>,
+    #[serde(default)]
+    pub overheating: Option<bool>,
+    /// The fan speed, 0 while stopped, only devices with a fan report it.
+    #[serde(default)]
+    pub fan_level: Option<u32>,
 }
 
 impl Device {
-    pub fn power_status(&self, port_id: usize) -> Option<PowerStatus> {
+    pub fn port(&self, port_id: usize) -> Option<&Port> {
+        self.port_table.iter().find(|port| port.port_idx == port_id)
+    }
+
+    /// Whether the controller can reach the device. Controllers which do not
+    /// report a state are taken at their word that the device is there.
+    pub fn is_connected(&self) -> bool {
+        self.state.unwrap_or(1) == 1
+    }
+
+    /// The power drawn through every port in watts.
+    pub fn poe_watts(&self) -> f64 {
         self.port_table
             .iter()
-            .find(|port| port.port_idx == port_id)
-            .and_then(|port| match port.poe_mode {
-                Some(PoeMode::Auto) => Some(PowerStatus {
-                    status: "running".to_owned(),
-                }),
-                Some(PoeMode::Off) => Some(PowerStatus {
-                    status: "stopped".to_owned(),
-                }),
-                _ => None,
-            })
+            .filter_map(|port| port.poe_power)
+            .sum()
+    }
+
+    pub fn power_status(&self, port_id: usize) -> Option<PowerStatus> {
+        self.port(port_id).and_then(|port| match port.poe_mode {
+            Some(PoeMode::Auto) => Some(PowerStatus {
+                status: "running".to_owned(),
+            }),
+            Some(PoeMode::Off) => Some(PowerStatus {
+                status: "stopped".to_owned(),
+            }),
+            _ => None,
+        })
     }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+/// A client the controller sees on the network. Wired clients are reported
+/// with the switch and port they were learned on.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Station {
+    pub mac: MacAddress,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub sw_mac: Option<MacAddress>,
+    #[serde(default)]
+    pub sw_port: Option<usize>,
+}
+
+/// An event the controller logged, e.g. a port losing its link or going over
+/// its PoE budget. Events about a switch port carry the switch and port.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ControllerEvent {
+    /// e.g. `EVT_SW_PoeOverload`.
+    pub key: String,
+    #[serde(default)]
+    pub msg: Option<String>,
+    /// When the event happened, in milliseconds since the epoch.
+    pub time: u64,
+    #[serde(default)]
+    pub sw: Option<MacAddress>,
+    #[serde(default)]
+    pub port: Option<usize>,
+}
+
+/// The logged in account as the controller sees it on the site.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Account {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// e.g. `admin` or `readonly`.
+    #[serde(default)]
+    pub site_role: Option<String>,
+    /// Super admins can manage every site whatever their site role.
+    #[serde(default)]
+    pub is_super: Option<bool>,
+}
+
+impl Account {
+    /// Why the account cannot power ports on the site, if it cannot. Roles
+    /// the controller does not name are assumed to be able to.
+    pub fn permission_problem(&self) -> Option<String> {
+        if self.is_super == Some(true) {
+            return None;
+        }
+        let account = match &self.name {
+            Some(name) => format!("the controller account `{name}`"),
+            None => "the controller account".to_owned(),
+        };
+        let role = self.site_role.as_deref()?;
+        let can = match role {
+            "readonly" => "can list devices but not power ports",
+            "nopermission" => "can neither list devices nor power ports",
+            _ => return None,
+        };
+        Some(format!(
+            "{account} has the `{role}` role on the site, so it {can}; \
+             give it the admin role on the site, or a limited admin role that can manage devices"
+        ))
+    }
+}
+
+/// A site on the controller. URLs name it by `name`, a short id such as
+/// `default` or `k3x9tq1z`, while the controller shows `desc`.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Site {
+    pub name: String,
+    #[serde(default)]
+    pub desc: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
 pub struct DeviceId(String);
 
 impl DeviceId {
@@ -73,12 +192,91 @@ impl Display for DeviceId {
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Port {
     pub port_idx: usize,
+    /// The name given to the port on the controller, e.g. `Port 1`.
+    #[serde(default)]
+    pub name: Option<String>,
     pub poe_mode: Option<PoeMode>,
+    /// Power currently drawn through the port in watts. The controller reports
+    /// this as a string, e.g. `"3.45"`.
+    #[serde(default, deserialize_with = "de_optional_f64")]
+    pub poe_power: Option<f64>,
+    /// Whether the port has a link.
+    #[serde(default)]
+    pub up: Option<bool>,
+    /// Whether the port has PoE hardware at all.
+    #[serde(default)]
+    pub port_poe: Option<bool>,
+    /// Bitmask of the PoE standards the port supports, 0 if none.
+    #[serde(default)]
+    pub poe_caps: Option<u32>,
+}
+
+impl Port {
+    /// Whether the port can supply PoE. Ports the controller reports nothing
+    /// about are assumed to, older controllers omit these fields.
+    pub fn supports_poe(&self) -> bool {
+        self.port_poe != Some(false) && self.poe_caps != Some(0)
+    }
+
+    /// Whether the port is drawing at least `min_watts` of power.
+    pub fn is_drawing_power(&self, min_watts: f64) -> bool {
+        self.poe_power.is_some_and(|watts| watts >= min_watts)
+    }
+}
+
+/// The controller is inconsistent about whether numeric fields are sent as
+/// numbers or strings, so accept both.
+fn de_optional_f64<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(f64),
+        String(String),
+    }
+    match Option::<NumberOrString>::deserialize(deserializer)? {
+        Some(NumberOrString::Number(n)) => Ok(Some(n)),
+        Some(NumberOrString::String(s)) => s.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum PoeMode {
     Auto,
     Off,
 }
+
+#[cfg(test)]
+mod test {
+    use super::Port;
+    use serde_json::json;
+
+    #[test]
+    fn should_parse_poe_power_from_string() {
+        let port: Port =
+            serde_json::from_value(json!({"port_idx": 1, "poe_power": "3.45"})).unwrap();
+        assert_eq!(port.poe_power, Some(3.45));
+    }
+
+    #[test]
+    fn should_parse_poe_power_from_number() {
+        let port: Port = serde_json::from_value(json!({"port_idx": 1, "poe_power": 0.5})).unwrap();
+        assert!(port.is_drawing_power(0.5));
+    }
+
+    #[test]
+    fn should_not_support_poe_without_caps() {
+        let sfp: Port =
+            serde_json::from_value(json!({"port_idx": 25, "port_poe": false, "poe_caps": 0}))
+                .unwrap();
+        assert!(!sfp.supports_poe());
+        let poe: Port =
+            serde_json::from_value(json!({"port_idx": 1, "port_poe": true, "poe_caps": 7}))
+                .unwrap();
+        assert!(poe.supports_poe());
+    }
+}
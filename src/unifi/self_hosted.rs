@@ -1,35 +1,153 @@
 use super::{
-    client::UnifiClient,
-    models::{AuthData, Device, PoeMode, UnifiResponse},
+    client::{ControllerApiError, UnifiClient},
+    models::{
+        Account, AuthData, ControllerEvent, Device, Meta, PoeMode, Site, Station, UnifiResponse,
+    },
 };
+use crate::config::ControllerConfig;
 use async_trait::async_trait;
 use hyper::{header::CONTENT_TYPE, Method};
-use reqwest::{Client, Url};
+use reqwest::{Client, Response, Url};
+use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::json;
+use std::{net::SocketAddr, time::Duration};
+
+/// The HTTP client for the controller. Self-hosted controllers usually have
+/// a self-signed certificate, so it is not verified.
+pub fn http_client(config: &ControllerConfig) -> reqwest::Result<Client> {
+    let builder = Client::builder()
+        .cookie_store(true)
+        .danger_accept_invalid_certs(true)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_secs))
+        .tcp_keepalive(Duration::from_secs(60));
+    config
+        .resolve
+        .iter()
+        .fold(builder, |builder, (host, ip)| {
+            // The port is taken from the URL, not from here.
+            builder.resolve(host, SocketAddr::new(*ip, 0))
+        })
+        .build()
+}
+
+/// The site every controller has, used unless `controller.site` is set.
+pub const DEFAULT_SITE: &str = "default";
+
+/// How far back to ask the controller for events, and how many at most.
+const EVENTS_WITHIN_HOURS: u64 = 24;
+const EVENTS_LIMIT: usize = 200;
 
 #[derive(Clone, Debug)]
 pub struct UnifiSelfHostedClient {
     base_url: Url,
+    urls: Urls,
     client: Client,
 }
 
+/// The controller's endpoints, joined once up front rather than per request.
+#[derive(Clone, Debug)]
+struct Urls {
+    status: Url,
+    login: Url,
+    sites: Url,
+    account: Url,
+    devices: Url,
+    clients: Url,
+    events: Url,
+    device_rest: Url,
+}
+
+impl Urls {
+    fn new(base_url: &Url, site: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            status: base_url.join("/status")?,
+            login: base_url.join("/api/login")?,
+            sites: base_url.join("/api/self/sites")?,
+            account: base_url.join(&format!("/api/s/{site}/self"))?,
+            devices: base_url.join(&format!("/api/s/{site}/stat/device"))?,
+            clients: base_url.join(&format!("/api/s/{site}/stat/sta"))?,
+            events: base_url.join(&format!("/api/s/{site}/stat/event"))?,
+            device_rest: base_url.join(&format!("/api/s/{site}/rest/device/"))?,
+        })
+    }
+}
+
+/// The body of an error from the controller, `meta` on the classic API and a
+/// bare `code` on the v2 API.
+#[derive(Deserialize)]
+struct ErrorBody {
+    meta: Option<Meta>,
+    code: Option<String>,
+    message: Option<String>,
+}
+
+impl ErrorBody {
+    fn into_error(self, status: u16) -> Option<ControllerApiError> {
+        let code = match self.meta {
+            Some(Meta { rc, msg: Some(msg) }) if rc == "error" => msg,
+            _ => self.code?,
+        };
+        Some(ControllerApiError {
+            status,
+            code,
+            msg: self.message,
+        })
+    }
+}
+
+/// Fails a response the controller answered with an error, keeping the
+/// `api.err.*` code it gave.
+async fn checked(response: Response) -> anyhow::Result<Response> {
+    let Err(error) = response.error_for_status_ref() else {
+        return Ok(response);
+    };
+    let status = response.status().as_u16();
+    let body = response.bytes().await.unwrap_or_default();
+    match serde_json::from_slice::<ErrorBody>(&body)
+        .ok()
+        .and_then(|body| body.into_error(status))
+    {
+        Some(api_error) => Err(api_error.into()),
+        None => Err(error.into()),
+    }
+}
+
+/// Reads a successful response, which can still carry an error in `meta`.
+async fn read<T: DeserializeOwned>(response: Response) -> anyhow::Result<UnifiResponse<T>> {
+    let status = response.status().as_u16();
+    let body = checked(response).await?.bytes().await?;
+    if let Some(api_error) = serde_json::from_slice::<ErrorBody>(&body)
+        .ok()
+        .and_then(|body| body.into_error(status))
+    {
+        return Err(api_error.into());
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
 impl UnifiSelfHostedClient {
     pub fn new<S: AsRef<str>>(base_url: S, client: Client) -> anyhow::Result<Self> {
-        let url = Url::parse(base_url.as_ref())?;
+        let base_url = Url::parse(base_url.as_ref())?;
         Ok(Self {
-            base_url: url,
+            urls: Urls::new(&base_url, DEFAULT_SITE)?,
+            base_url,
             client,
         })
     }
 
+    /// Manages `site`, by its short name, rather than the default site.
+    pub fn with_site(mut self, site: &str) -> anyhow::Result<Self> {
+        self.urls = Urls::new(&self.base_url, site)?;
+        Ok(self)
+    }
+
     async fn power(
         &self,
         poe_mode: PoeMode,
         device_id: &str,
         port_number: usize,
     ) -> anyhow::Result<UnifiResponse<()>> {
-        let url = self.base_url.join("/api/s/default/rest/device/")?;
-        let url = url.join(device_id)?;
+        let url = self.urls.device_rest.join(device_id)?;
         let body = serde_json::to_string(
             &json!({"port_overrides":[{"port_idx":port_number,"poe_mode":poe_mode}]}),
         )?;
@@ -41,7 +159,7 @@ impl UnifiSelfHostedClient {
             .body(body)
             .send()
             .await?;
-        response.error_for_status()?;
+        checked(response).await?;
         Ok(UnifiResponse {
             data: (),
             ..Default::default()
@@ -54,27 +172,70 @@ impl UnifiClient for UnifiSelfHostedClient {
     async fn login(&self, username: &str, password: &str) -> anyhow::Result<()> {
         let auth_data = AuthData::new(username.into(), password.into());
         let auth_data_json = serde_json::to_string(&auth_data)?;
-        let url = self.base_url.join("/api/login")?;
         let response = self
             .client
-            .request(Method::POST, url)
+            .request(Method::POST, self.urls.login.clone())
             .header(CONTENT_TYPE, "application/json")
             .body(auth_data_json)
             .send()
             .await?;
-        Ok(response.error_for_status().map(|_| ())?)
+        checked(response).await.map(|_| ())
     }
 
     async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<Device>>> {
-        let url = self.base_url.join("/api/s/default/stat/device")?;
         let response = self
             .client
-            .request(Method::GET, url)
+            .request(Method::GET, self.urls.devices.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await?;
+        read(response).await
+    }
+
+    async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+        let response = self
+            .client
+            .request(Method::GET, self.urls.clients.clone())
             .header(CONTENT_TYPE, "application/json")
             .send()
             .await?;
-        let response = response.error_for_status()?;
-        Ok(response.json::<UnifiResponse<Vec<Device>>>().await?)
+        read(response).await
+    }
+
+    async fn sites(&self) -> anyhow::Result<UnifiResponse<Vec<Site>>> {
+        let response = self
+            .client
+            .request(Method::GET, self.urls.sites.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await?;
+        read(response).await
+    }
+
+    async fn account(&self) -> anyhow::Result<UnifiResponse<Vec<Account>>> {
+        let response = self
+            .client
+            .request(Method::GET, self.urls.account.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await?;
+        read(response).await
+    }
+
+    async fn events(&self) -> anyhow::Result<UnifiResponse<Vec<ControllerEvent>>> {
+        let response = self
+            .client
+            .request(Method::POST, self.urls.events.clone())
+            .json(&json!({"_sort": "-time", "within": EVENTS_WITHIN_HOURS, "_limit": EVENTS_LIMIT}))
+            .send()
+            .await?;
+        read(response).await
+    }
+
+    async fn warm_up(&self) -> anyhow::Result<()> {
+        // Only the connection matters, `/status` answers without a login.
+        self.client.get(self.urls.status.clone()).send().await?;
+        Ok(())
     }
 
     async fn power_on(
@@ -96,9 +257,14 @@ impl UnifiClient for UnifiSelfHostedClient {
 
 #[cfg(test)]
 mod test {
-    use crate::unifi::models::{Meta, PoeMode};
+    use crate::unifi::{
+        client::ControllerApiError,
+        models::{Meta, PoeMode},
+    };
 
-    use super::{Device, UnifiClient, UnifiResponse, UnifiSelfHostedClient};
+    use super::{http_client, Device, UnifiClient, UnifiResponse, UnifiSelfHostedClient};
+    use crate::config::ControllerConfig;
+    use mac_address::MacAddress;
     use serde_json::json;
     use wiremock::{
         matchers::{body_json, method, path},
@@ -129,6 +295,38 @@ mod test {
         assert!(response.is_ok(), "{:?}", response);
     }
 
+    #[tokio::test]
+    async fn should_warm_up_connection_whatever_the_status() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let unifi_client =
+            UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        unifi_client.warm_up().await.unwrap();
+        let unifi_client =
+            UnifiSelfHostedClient::new("http://127.0.0.1:1", reqwest::Client::new()).unwrap();
+        assert!(unifi_client.warm_up().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_resolve_overridden_host() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/login"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let config: ControllerConfig =
+            toml::from_str(r#"resolve = { "unifi.invalid" = "127.0.0.1" }"#).unwrap();
+        let url = format!("http://unifi.invalid:{}", mock_server.address().port());
+        let unifi_client = UnifiSelfHostedClient::new(url, http_client(&config).unwrap()).unwrap();
+        unifi_client.login("", "").await.unwrap();
+    }
+
     #[tokio::test]
     async fn should_list_devices() {
         let mock_server = MockServer::start().await;
@@ -144,6 +342,87 @@ mod test {
         assert!(response.is_ok(), "{:?}", response);
     }
 
+    #[tokio::test]
+    async fn should_keep_the_controller_error_code() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+                "meta": {"rc": "error", "msg": "api.err.NoPermission"},
+                "data": []
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/device"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "meta": {"rc": "error", "msg": "api.err.LoginRequired"},
+                "data": []
+            })))
+            .mount(&mock_server)
+            .await;
+        let unifi_client =
+            UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let error = unifi_client.power_on(UNIFI_DEVICE_ID, 1).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ControllerApiError>(),
+            Some(&ControllerApiError {
+                status: 403,
+                code: "api.err.NoPermission".to_owned(),
+                msg: None,
+            })
+        );
+        let error = unifi_client.devices().await.unwrap_err();
+        let error = error.downcast_ref::<ControllerApiError>().unwrap();
+        assert_eq!(error.code, "api.err.LoginRequired");
+    }
+
+    #[tokio::test]
+    async fn should_list_clients() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/s/default/stat/sta"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "meta": {"rc": "ok"},
+                "data": [
+                    {"mac": "aa:00:00:00:00:01", "sw_mac": "00:00:00:00:00:01", "sw_port": 3},
+                    {"mac": "aa:00:00:00:00:02", "hostname": "laptop"}
+                ]
+            })))
+            .mount(&mock_server)
+            .await;
+        let unifi_client =
+            UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let clients = unifi_client.clients().await.unwrap().data;
+        assert_eq!(
+            clients[0].sw_mac,
+            Some(MacAddress::from([0, 0, 0, 0, 0, 1]))
+        );
+        assert_eq!(clients[0].sw_port, Some(3));
+        assert_eq!(clients[1].sw_port, None);
+    }
+
+    #[tokio::test]
+    async fn should_list_recent_events() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/s/default/stat/event"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "meta": {"rc": "ok"},
+                "data": [
+                    {"key": "EVT_SW_PoeOverload", "time": 1700000000000u64, "sw": "00:00:00:00:00:01", "port": 3},
+                    {"key": "EVT_AD_Login", "time": 1690000000000u64, "msg": "admin logged in"}
+                ]
+            })))
+            .mount(&mock_server)
+            .await;
+        let unifi_client =
+            UnifiSelfHostedClient::new(mock_server.uri(), reqwest::Client::new()).unwrap();
+        let events = unifi_client.events().await.unwrap().data;
+        assert_eq!(events[0].key, "EVT_SW_PoeOverload");
+        assert_eq!(events[0].port, Some(3));
+        assert_eq!(events[1].sw, None);
+    }
+
     #[tokio::test]
     async fn should_power_on_machine() {
         let mock_server = MockServer::start().await;
@@ -151,6 +430,7 @@ mod test {
         let response = UnifiResponse::<Vec<Device>> {
             meta: Meta {
                 rc: "ok".to_owned(),
+                ..Default::default()
             },
             ..Default::default()
         };
@@ -180,6 +460,7 @@ mod test {
         let response = UnifiResponse::<Vec<Device>> {
             meta: Meta {
                 rc: "ok".to_owned(),
+                ..Default::default()
             },
             ..Default::default()
         };
@@ -0,0 +1,268 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{parse_config, Config},
+    unifi::handler::UnifiHandler,
+};
+
+/// The outcome of checking a candidate config without applying it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub issues: Vec<ValidationIssue>,
+    /// The running controller the devices and ports were checked against,
+    /// `None` if the checks stopped before that stage. The candidate's own
+    /// `url` is never contacted.
+    pub controller: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ValidationIssue {
+    pub stage: ValidationStage,
+    pub message: String,
+}
+
+/// Later stages only run once the earlier ones pass.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationStage {
+    Syntax,
+    Config,
+    Controller,
+}
+
+impl ValidationReport {
+    fn new(stage: ValidationStage, messages: Vec<String>) -> Self {
+        Self {
+            valid: messages.is_empty(),
+            issues: messages
+                .into_iter()
+                .map(|message| ValidationIssue { stage, message })
+                .collect(),
+            controller: None,
+        }
+    }
+}
+
+/// Parses and validates `config_toml`, then checks every configured device and
+/// port exists on the running controller, reached at `controller_url`.
+pub async fn validate_config(
+    config_toml: &str,
+    controller: &UnifiHandler,
+    controller_url: &str,
+) -> ValidationReport {
+    let config = match parse_config(config_toml) {
+        Ok(config) => config,
+        Err(e) => return ValidationReport::new(ValidationStage::Syntax, vec![e.to_string()]),
+    };
+    let problems = config.problems();
+    if !problems.is_empty() {
+        return ValidationReport::new(ValidationStage::Config, problems);
+    }
+    ValidationReport {
+        controller: Some(controller_url.to_owned()),
+        ..ValidationReport::new(
+            ValidationStage::Controller,
+            reconcile(&config, controller).await,
+        )
+    }
+}
+
+/// Checks the account can power ports, and that every configured device
+/// exists on the controller and has the ports its machines are mapped to.
+pub async fn reconcile(config: &Config, controller: &UnifiHandler) -> Vec<String> {
+    let mut problems = Vec::new();
+    // Checked again only while it fails, so a fixed role lifts the problem
+    // without a restart.
+    if controller.permission_problem().is_some() {
+        problems.extend(controller.check_permissions().await);
+    }
+    let devices = match controller.devices().await {
+        Ok(devices) => devices,
+        Err(e) => {
+            problems.push(format!("failed to list devices on the controller: {e:?}"));
+            return problems;
+        }
+    };
+    for configured in &config.devices {
+        let Some(device) = devices.iter().find(|device| device.mac == configured.mac) else {
+            problems.push(format!(
+                "device `{}` was not found on the controller",
+                configured.mac
+            ));
+            continue;
+        };
+        for machine in &configured.machines {
+            match device.port(machine.port_id) {
+                None => problems.push(format!(
+                    "machine `{}` is on port {} but device `{}` has no such port",
+                    machine.maas_id, machine.port_id, configured.mac
+                )),
+                Some(port) if !port.supports_poe() => problems.push(format!(
+                    "machine `{}` is on port {} of device `{}` which cannot supply PoE",
+                    machine.maas_id, machine.port_id, configured.mac
+                )),
+                Some(_) => {}
+            }
+        }
+    }
+    problems
+}
+
+/// How long a `reconcile` result answers readiness probes.
+const RECONCILED_TTL: Duration = Duration::from_secs(5);
+
+/// The last `reconcile` result, reused for a few seconds so frequent readiness
+/// probes do not each list the devices. Probes arriving together wait for one
+/// run.
+#[derive(Clone, Default)]
+pub struct Reconciled {
+    last: Arc<Mutex<Option<Reconcile>>>,
+}
+
+struct Reconcile {
+    at: Instant,
+    problems: Vec<String>,
+}
+
+impl Reconciled {
+    pub async fn problems(&self, config: &Config, controller: &UnifiHandler) -> Vec<String> {
+        let mut last = self.last.lock().await;
+        if let Some(last) = last
+            .as_ref()
+            .filter(|last| last.at.elapsed() < RECONCILED_TTL)
+        {
+            return last.problems.clone();
+        }
+        let problems = reconcile(config, controller).await;
+        *last = Some(Reconcile {
+            at: Instant::now(),
+            problems: problems.clone(),
+        });
+        problems
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate_config, Reconciled, ValidationStage};
+    use crate::config::parse_config;
+    use crate::unifi::{
+        client::UnifiClient,
+        handler::UnifiHandler,
+        models::{Device, DeviceId, Port, Station, UnifiResponse},
+    };
+    use async_trait::async_trait;
+    use mac_address::MacAddress;
+
+    #[derive(Clone)]
+    struct FakeUnifiClient {}
+
+    #[async_trait]
+    impl UnifiClient for FakeUnifiClient {
+        async fn login(&self, _: &str, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn devices(&self) -> anyhow::Result<UnifiResponse<Vec<Device>>> {
+            Ok(UnifiResponse {
+                data: vec![Device {
+                    mac: MacAddress::from([0; 6]),
+                    device_id: DeviceId::new("device-id"),
+                    port_table: vec![
+                        Port {
+                            port_idx: 1,
+                            ..Default::default()
+                        },
+                        Port {
+                            port_idx: 25,
+                            port_poe: Some(false),
+                            poe_caps: Some(0),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+        }
+
+        async fn clients(&self) -> anyhow::Result<UnifiResponse<Vec<Station>>> {
+            Ok(UnifiResponse::default())
+        }
+
+        async fn power_on(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            Ok(UnifiResponse::default())
+        }
+
+        async fn power_off(&self, _: &str, _: usize) -> anyhow::Result<UnifiResponse<()>> {
+            Ok(UnifiResponse::default())
+        }
+    }
+
+    const URL: &str = "https://unifi.example:8443";
+
+    fn controller() -> UnifiHandler {
+        UnifiHandler::new(Box::new(FakeUnifiClient {}))
+    }
+
+    fn config(port_id: usize) -> String {
+        format!(
+            r#"
+            url = "https://localhost:8443"
+
+            [[devices]]
+            mac = "00:00:00:00:00:00"
+
+            [[devices.machines]]
+            maas_id = "maas_id"
+            port_id = {port_id}
+            "#
+        )
+    }
+
+    #[tokio::test]
+    async fn should_accept_config_matching_controller() {
+        let report = validate_config(&config(1), &controller(), URL).await;
+        assert!(report.valid, "{:?}", report.issues);
+        assert_eq!(report.controller.as_deref(), Some(URL));
+    }
+
+    #[tokio::test]
+    async fn should_report_syntax_errors() {
+        let report = validate_config("url = ", &controller(), URL).await;
+        assert!(!report.valid);
+        assert!(report.controller.is_none());
+        assert_eq!(report.issues[0].stage, ValidationStage::Syntax);
+    }
+
+    #[tokio::test]
+    async fn should_report_ports_missing_on_controller() {
+        let report = validate_config(&config(5), &controller(), URL).await;
+        assert!(!report.valid);
+        assert_eq!(report.issues[0].stage, ValidationStage::Controller);
+    }
+
+    #[tokio::test]
+    async fn should_report_ports_without_poe() {
+        let report = validate_config(&config(25), &controller(), URL).await;
+        assert!(!report.valid);
+        assert!(report.issues[0].message.contains("cannot supply PoE"));
+    }
+
+    #[tokio::test]
+    async fn should_reuse_a_recent_reconcile() {
+        let reconciled = Reconciled::default();
+        let missing_port = parse_config(&config(5)).unwrap();
+        let problems = reconciled.problems(&missing_port, &controller()).await;
+        assert_eq!(problems.len(), 1);
+        let fixed = parse_config(&config(1)).unwrap();
+        assert_eq!(reconciled.problems(&fixed, &controller()).await, problems);
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+
+use crate::{
+    backend::Target,
+    config::WatchdogConfig,
+    metrics::Metrics,
+    notifications::{EventResult, Notifier, PowerAction, PowerEvent},
+};
+
+/// Polls the machine until it draws at least `min_power_watts` or the timeout
+/// elapses. Returns whether any power draw was seen, backends which cannot
+/// measure power draw always pass.
+pub async fn wait_for_power_draw(target: &Target, config: &WatchdogConfig) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(config.timeout_secs);
+    loop {
+        match target.backend.power_draw(&target.machine).await {
+            Ok(None) => return true,
+            Ok(Some(watts)) if watts >= config.min_power_watts => return true,
+            Ok(Some(_)) => {}
+            Err(e) => tracing::debug!(
+                "watchdog failed to read power draw of {}: {e:?}",
+                target.machine.maas_id
+            ),
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        sleep(Duration::from_secs(config.poll_interval_secs)).await;
+    }
+}
+
+/// Polls the machine until its network port is up or `link_timeout_secs`
+/// elapses. Returns whether the link came up, backends which cannot tell
+/// always pass.
+pub async fn wait_for_link(target: &Target, config: &WatchdogConfig) -> bool {
+    let Some(timeout_secs) = config.link_timeout_secs else {
+        return true;
+    };
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+    loop {
+        match target.backend.link_up(&target.machine).await {
+            Ok(None | Some(true)) => return true,
+            Ok(Some(false)) => {}
+            Err(e) => tracing::debug!(
+                "watchdog failed to read link of {}: {e:?}",
+                target.machine.maas_id
+            ),
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        sleep(Duration::from_secs(config.poll_interval_secs)).await;
+    }
+}
+
+/// Spawns a background check after a power on. A port that never draws power
+/// is almost always a dead PSU or an unplugged cable, which MaaS would otherwise
+/// only report as a commissioning timeout. A port that draws power but never
+/// comes up points at a machine that does not boot or a bad network cable.
+pub fn watch_power_on(
+    target: Target,
+    metrics: Metrics,
+    notifier: Notifier,
+    config: WatchdogConfig,
+) {
+    tokio::spawn(async move {
+        let system_id = target.machine.maas_id.as_str();
+        let power_draw = async {
+            if !wait_for_power_draw(&target, &config).await {
+                tracing::warn!(
+                    system_id,
+                    "machine has drawn no power {}s after power on, check the PSU and cabling",
+                    config.timeout_secs
+                );
+                metrics.increment("power_on_without_draw", &[("system_id", system_id)]);
+                let event = PowerEvent::new(system_id, PowerAction::On, EventResult::NoPowerDraw);
+                notifier.send(&event).await;
+            }
+        };
+        let link = async {
+            if !wait_for_link(&target, &config).await {
+                tracing::warn!(
+                    system_id,
+                    "machine's port has no link {}s after power on, check that it boots",
+                    config.link_timeout_secs.unwrap_or_default()
+                );
+                metrics.increment("power_on_without_link", &[("system_id", system_id)]);
+                let event = PowerEvent::new(system_id, PowerAction::On, EventResult::NoLink);
+                notifier.send(&event).await;
+            }
+        };
+        tokio::join!(power_draw, link);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::{wait_for_link, wait_for_power_draw};
+    use crate::{
+        backend::{BackendError, PowerBackend, Target},
+        config::{Machine, WatchdogConfig},
+        unifi::models::PowerStatus,
+    };
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    const WATCHDOG: WatchdogConfig = WatchdogConfig {
+        timeout_secs: 0,
+        poll_interval_secs: 0,
+        min_power_watts: 0.5,
+        link_timeout_secs: Some(0),
+    };
+
+    struct FakeBackend {
+        power_draw: Option<f64>,
+        link_up: Option<bool>,
+    }
+
+    #[async_trait]
+    impl PowerBackend for FakeBackend {
+        async fn status(&self, _: &Machine) -> Result<PowerStatus, BackendError> {
+            Ok(PowerStatus {
+                status: "running".to_owned(),
+            })
+        }
+
+        async fn power_on(&self, _: &Machine) -> Result<(), BackendError> {
+            Ok(())
+        }
+
+        async fn power_off(&self, _: &Machine) -> Result<(), BackendError> {
+            Ok(())
+        }
+
+        async fn power_draw(&self, _: &Machine) -> Result<Option<f64>, BackendError> {
+            Ok(self.power_draw)
+        }
+
+        async fn link_up(&self, _: &Machine) -> Result<Option<bool>, BackendError> {
+            Ok(self.link_up)
+        }
+    }
+
+    fn target(power_draw: Option<f64>) -> Target {
+        link_target(power_draw, None)
+    }
+
+    fn link_target(power_draw: Option<f64>, link_up: Option<bool>) -> Target {
+        Target {
+            backend: Arc::new(FakeBackend {
+                power_draw,
+                link_up,
+            }),
+            machine: Machine::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_see_power_draw() {
+        assert!(wait_for_power_draw(&target(Some(4.2)), &WATCHDOG).await);
+    }
+
+    #[tokio::test]
+    async fn should_time_out_without_power_draw() {
+        assert!(!wait_for_power_draw(&target(Some(0.0)), &WATCHDOG).await);
+    }
+
+    #[tokio::test]
+    async fn should_pass_if_backend_cannot_measure_power_draw() {
+        assert!(wait_for_power_draw(&target(None), &WATCHDOG).await);
+    }
+
+    #[tokio::test]
+    async fn should_time_out_without_link() {
+        assert!(wait_for_link(&link_target(Some(4.2), Some(true)), &WATCHDOG).await);
+        assert!(!wait_for_link(&link_target(Some(4.2), Some(false)), &WATCHDOG).await);
+        let without_link_check = WatchdogConfig {
+            link_timeout_secs: None,
+            ..WATCHDOG
+        };
+        assert!(wait_for_link(&link_target(Some(4.2), Some(false)), &without_link_check).await);
+    }
+}